@@ -1,13 +1,29 @@
-use anyhow::{bail, Context, Result};
-use log::{debug, error, info};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveTime};
+use log::{debug, error, info, warn};
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsString;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, IsTerminal, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
 
-use crate::config::{Config, Job};
+use crate::bench_log;
+use crate::check;
+use crate::config::{
+    Config, Job, JobCommand, Measure, OnBuildFailure, Settings, SmokeTest, TimeOutput,
+};
 use crate::database::{Database, Run};
-use crate::result::TimeResult;
-use crate::util;
+use crate::lock::DataDirLock;
+use crate::notify::{self, Event, JobMetrics, Notifier};
+use crate::remote::{self, Remote};
+use crate::result::{median_index, JobStats, TimeResult};
+use crate::util::{self, CcacheStats, CommitMetadata, CommitOrder};
 
 pub struct Bencher<'a> {
     config: &'a mut Config,
@@ -15,16 +31,332 @@ pub struct Bencher<'a> {
     src_dir: &'a PathBuf,
     bench_type: BenchType,
     options: BenchOptions<'a>,
+    runs: Vec<(i64, String)>,
+    artifacts: Option<ArtifactOptions>,
+    only_if_changed: bool,
+    /// Clone the repo into `src_dir` if it isn't one, in addition to the `auto_clone` setting
+    clone: bool,
+    /// Pin jobs to the CPUs isolated with `isolcpus`
+    use_isolated_cpus: bool,
+    /// Sample the RSS of every bench job, in addition to those with `profile_memory`
+    profile_memory: bool,
+    /// Record runs with this `was_master` value instead of the default
+    master_override: Option<bool>,
+    /// Decide `was_master` from this many of the most recent commits of origin/master
+    commit_depth: Option<usize>,
+    /// Print how dates and commits resolve instead of benchmarking
+    explain: bool,
+    /// Wall time budget for the whole run
+    max_runtime: Option<Duration>,
+    /// When `max_runtime` runs out, set once the run starts
+    deadline: Option<Instant>,
+    /// Recent history of origin/master, fetched once per sweep when `commit_depth` is set
+    master_history: Option<MasterHistory>,
+    /// CPUs jobs are pinned to, empty to leave them unpinned
+    pinned_cpus: Vec<usize>,
+    /// `--version` of each configured binary, recorded against every run
+    binary_versions: Vec<(String, Option<String>)>,
+    /// Metadata of the commits looked up during a run, keyed by full hash as refs may move
+    commit_cache: RefCell<HashMap<String, CommitMetadata>>,
+    /// The data dir was kept from the previous commit of the sweep, see
+    /// `reuse_datadir_when_compatible`
+    reusing_datadir: bool,
+    /// Told about finished runs and regressions, from `[[notifiers]]`
+    notifiers: Vec<Box<dyn Notifier>>,
+    /// Host jobs run on over SSH, connected once the run starts, from `remote_host`
+    remote: Option<Remote>,
+    /// `bin` directory of the release being benchmarked, put first on the PATH of its jobs
+    release_bin: Option<PathBuf>,
+}
+
+/// What the build jobs of a run did, gathered as they run.
+#[derive(Default)]
+struct BuildProgress {
+    /// A job with `build = true` ran, so the build's toolchain can be recorded
+    ran: bool,
+    /// The source tree was clean ahead of a build job, see `check_clean_build`
+    clean: Option<bool>,
+}
+
+/// Where a job's output, reports and profile are written, shared by its executions.
+struct JobFiles {
+    output: String,
+    error: String,
+    /// Time report, unless it is read from a pipe or the job is measured natively
+    outfile: String,
+    /// Metrics files following the time report in `outfile`
+    metrics: Vec<String>,
+    profile: Option<String>,
+    memory_events: Option<String>,
+}
+
+/// The outcome of executing a job once.
+struct Execution {
+    status: ExitStatus,
+    failed_criterion: Option<String>,
+    /// Results of a bench job, taken once recorded
+    result: Option<TimeResult>,
+    /// What GNU time reported, kept so `verify` can parse it again
+    report: Option<String>,
+    /// RSS samples of the job's processes, with `profile_memory`
+    memory_timeline: Option<Vec<(i64, u64)>>,
+}
+
+impl Execution {
+    fn succeeded(&self) -> bool {
+        self.status.success() && self.failed_criterion.is_none()
+    }
+
+    /// Exit code of the execution, `128 + signal` when it was killed by one.
+    fn exit_code(&self) -> i32 {
+        self.status
+            .code()
+            .or_else(|| self.status.signal().map(|signal| 128 + signal))
+            .unwrap_or_default()
+    }
+}
+
+/// The most recent commits of origin/master, so checking whether a commit is on master during a
+/// sweep is a lookup rather than a `git merge-base --is-ancestor` per commit.
+struct MasterHistory {
+    tip: String,
+    depth: usize,
+    commits: HashSet<String>,
+    /// Commit date of the oldest commit within the depth
+    oldest_date: i64,
+}
+
+impl MasterHistory {
+    fn load(src_dir: &Path, depth: usize) -> Result<Self> {
+        let tip = util::get_commit_hash(src_dir, "origin/master")?;
+        let recent = util::get_recent_commits(src_dir, &tip, depth)?;
+        Ok(MasterHistory {
+            tip,
+            depth,
+            oldest_date: recent
+                .iter()
+                .map(|(_, date)| *date)
+                .min()
+                .unwrap_or(i64::MIN),
+            commits: recent.into_iter().map(|(commit_id, _)| commit_id).collect(),
+        })
+    }
+
+    /// Whether `commit_id` is on master, or `None` when it is older than the depth covers.
+    fn contains(&self, commit_id: &str, commit_date: i64) -> Option<bool> {
+        if self.commits.contains(commit_id) {
+            Some(true)
+        } else if self.commits.len() < self.depth || commit_date >= self.oldest_date {
+            // Either the whole history is covered, or the commit is recent enough that it would be
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// The `was_master` flag recorded for a commit and why.
+struct MasterDecision {
+    was_master: bool,
+    /// Why the flag was set, `None` for the default of recording every commit as master
+    reason: Option<String>,
+    /// Whether the commit was beyond `--commit-depth` and fell back to the default
+    fallback: bool,
+}
+
+/// A `build` job failed under `on_build_failure = "skip"` or `"record"`, after the run was
+/// discarded or marked, so a sweep carries on with the next commit.
+#[derive(Debug)]
+struct BuildFailed {
+    commit_id: String,
+    error: String,
+}
+
+impl std::fmt::Display for BuildFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Commit {} failed to build: {}",
+            self.commit_id, self.error
+        )
+    }
+}
+
+impl std::error::Error for BuildFailed {}
+
+/// `--max-runtime` ran out before a commit's remaining jobs.
+#[derive(Debug)]
+struct OutOfTime {
+    job_name: String,
+}
+
+impl std::fmt::Display for OutOfTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "--max-runtime is used up, not starting job {} or the jobs after it",
+            self.job_name
+        )
+    }
+}
+
+impl std::error::Error for OutOfTime {}
+
+/// Midnight UTC of each day from the start to the end of a daily sweep.
+fn multi_dates(multi: &Multi) -> Result<Vec<i64>> {
+    let format = multi.date_format.map(String::as_str);
+    let start_date = util::parse_date_with_format(multi.start, format)
+        .map_err(|e| anyhow!("Failed to parse start date: {}", e))?;
+    let end_date = util::parse_date_with_format(multi.end, format)
+        .map_err(|e| anyhow!("Failed to parse end date: {}", e))?;
+    utc_midnights(start_date, end_date)
+}
+
+/// Midnight UTC of each day from the day of `start` to the day of `end`. Stepping by calendar day
+/// in UTC rather than by 86400 seconds keeps every date at midnight and includes the end's day,
+/// whatever the time of day of `start` and `end`, and whatever DST changes the local timezone has.
+fn utc_midnights(start: i64, end: i64) -> Result<Vec<i64>> {
+    let day = |timestamp: i64| {
+        DateTime::from_timestamp(timestamp, 0)
+            .map(|datetime| datetime.date_naive())
+            .ok_or_else(|| anyhow!("Date {} is out of range", timestamp))
+    };
+    let end_day = day(end)?;
+    Ok(day(start)?
+        .iter_days()
+        .take_while(|date| *date <= end_day)
+        .map(|date| date.and_time(NaiveTime::MIN).and_utc().timestamp())
+        .collect())
+}
+
+/// Refuse a daily sweep reaching outside `sweep_min_date` to `sweep_max_date`, and one of more
+/// than `sweep_max_runs` runs unless it's confirmed with `--yes` or at the prompt, which is only
+/// shown when stdin is a terminal.
+fn check_sweep(settings: &Settings, multi: &Multi, dates: &[i64]) -> Result<()> {
+    let (Some(&first), Some(&last)) = (dates.first(), dates.last()) else {
+        return Ok(());
+    };
+    if let Some(min_date) = &settings.sweep_min_date {
+        let min = util::parse_date_with_format(min_date, None)
+            .map_err(|e| anyhow!("Failed to parse sweep_min_date: {}", e))?;
+        if first < min {
+            bail!(
+                "Sweep starts on {}, before sweep_min_date {}",
+                util::unix_timestamp_to_hr(first),
+                min_date
+            );
+        }
+    }
+    if let Some(max_date) = &settings.sweep_max_date {
+        let max = util::parse_date_with_format(max_date, None)
+            .map_err(|e| anyhow!("Failed to parse sweep_max_date: {}", e))?;
+        if last > max {
+            bail!(
+                "Sweep ends on {}, after sweep_max_date {}",
+                util::unix_timestamp_to_hr(last),
+                max_date
+            );
+        }
+    }
+
+    let runs = multi
+        .limit_runs
+        .map_or(dates.len(), |limit| limit.min(dates.len()));
+    let Some(max_runs) = settings.sweep_max_runs else {
+        return Ok(());
+    };
+    if runs <= max_runs || multi.confirmed {
+        return Ok(());
+    }
+    let message = format!(
+        "Sweep from {} to {} makes {} runs, more than sweep_max_runs ({})",
+        util::unix_timestamp_to_hr(first),
+        util::unix_timestamp_to_hr(last),
+        runs,
+        max_runs
+    );
+    if !std::io::stdin().is_terminal() {
+        bail!("{}, pass --yes to run it anyway", message);
+    }
+    eprint!("{}. Continue? [y/N] ", message);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => bail!("Sweep cancelled"),
+    }
+}
+
+/// Seconds the system clock may drift from the boot clock before a sweep warns about it.
+const CLOCK_JUMP_TOLERANCE: i64 = 60;
+
+/// Notices the system clock being stepped, e.g. by NTP, during a sweep by comparing how far it
+/// has moved with the time since boot. That counts time suspended, which the monotonic clock
+/// behind `Instant` doesn't, so suspending the machine isn't taken for a jump. Durations and
+/// estimates use the monotonic clock and every run of the sweep shares one run date, so a jump
+/// only affects anything else reading the time.
+struct ClockWatch {
+    boot_started: Duration,
+    wall_started: i64,
+    /// Drift of the system clock already warned about
+    drift: i64,
+}
+
+impl ClockWatch {
+    fn new() -> Result<Self> {
+        Ok(ClockWatch {
+            boot_started: util::boot_time()?,
+            wall_started: chrono::Utc::now().timestamp(),
+            drift: 0,
+        })
+    }
+
+    /// Warn if the system clock has jumped since it was last checked.
+    fn check(&mut self) {
+        let Ok(boot_time) = util::boot_time() else {
+            return;
+        };
+        let wall_elapsed = chrono::Utc::now().timestamp() - self.wall_started;
+        let drift = wall_elapsed - boot_time.saturating_sub(self.boot_started).as_secs() as i64;
+        if (drift - self.drift).abs() > CLOCK_JUMP_TOLERANCE {
+            warn!(
+                "The system clock jumped by {}s during the sweep, runs keep the sweep's start \
+                as their run date",
+                drift - self.drift
+            );
+            self.drift = drift;
+        }
+    }
+}
+
+/// How often `wait_for_idle` checks the load average.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the CPU temperature and throttle counters are read while a run's jobs execute.
+const THERMAL_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where to archive the bitcoin data dir after each run.
+pub struct ArtifactOptions {
+    pub dir: PathBuf,
+    /// Compress archives with zstd
+    pub compress: bool,
 }
 
 pub enum BenchType {
     Single,
     Multi,
+    Commits,
+    Tags,
+    Release,
 }
 
 pub enum BenchOptions<'a> {
     Single(Single),
     Multi(Multi<'a>),
+    Commits(CommitRange),
+    Tags(Tags),
+    Release(Release),
 }
 
 pub struct Single {
@@ -34,6 +366,57 @@ pub struct Single {
 pub struct Multi<'a> {
     pub start: &'a String,
     pub end: &'a String,
+    /// chrono format of `start` and `end`, any common format is accepted without it
+    pub date_format: Option<&'a String>,
+    /// Carry on to the next commit when one fails, rather than stopping the sweep
+    pub collect_all: bool,
+    /// Stop after this many successful runs
+    pub limit_runs: Option<usize>,
+    /// Run even if the sweep has more than `sweep_max_runs` runs, without asking
+    pub confirmed: bool,
+}
+
+pub struct CommitRange {
+    /// A `<good>..<bad>` or `<good>...<bad>` range expression
+    pub range: String,
+    /// Benchmark every commit in the range in this order, otherwise only its first-parent chain
+    pub order: Option<CommitOrder>,
+    /// Benchmark every Nth commit in the range
+    pub every: usize,
+    /// Carry on to the next commit when one fails, rather than stopping the sweep
+    pub collect_all: bool,
+    /// Stop after this many successful runs
+    pub limit_runs: Option<usize>,
+}
+
+/// Every tag matching a pattern, benchmarked in order of creation.
+pub struct Tags {
+    /// Glob the tags must match, e.g. `v*`
+    pub pattern: String,
+    /// Carry on to the next tag when one fails, rather than stopping the sweep
+    pub collect_all: bool,
+    /// Stop after this many successful runs
+    pub limit_runs: Option<usize>,
+}
+
+/// An official release tarball, benchmarked in place of a source build.
+#[derive(Clone)]
+pub struct Release {
+    pub version: String,
+    /// Expected SHA256 of the tarball
+    pub sha256: String,
+    /// Tarball URL with `{version}` for the version, `release_url_template` if not given
+    pub url_template: Option<String>,
+}
+
+/// A commit selected for benchmarking.
+struct CommitToBench {
+    commit_id: String,
+    commit_date: i64,
+    /// Date of a daily sweep which selected the commit
+    sweep_date: Option<i64>,
+    /// Tag which selected the commit
+    tag: Option<String>,
 }
 
 impl<'a> Bencher<'a> {
@@ -55,25 +438,168 @@ impl<'a> Bencher<'a> {
                     bail!("Start and end dates must be provided for Multi bench type");
                 }
             }
+            BenchOptions::Commits(range) => {
+                util::parse_commit_range(&range.range)?;
+                if range.every == 0 {
+                    bail!("Commit sampling interval must be at least 1");
+                }
+            }
+            BenchOptions::Tags(tags) => {
+                if tags.pattern.is_empty() {
+                    bail!("Tag pattern must be provided for Tags bench type");
+                }
+            }
+            BenchOptions::Release(release) => {
+                if release.version.is_empty() {
+                    bail!("Version must be provided for Release bench type");
+                }
+                // The version names the directory the release is extracted into
+                if release.version.contains('/')
+                    || release.version.contains("..")
+                    || release.version.starts_with('.')
+                {
+                    bail!(
+                        "Version '{}' must not contain '/' or '..' or start with '.'",
+                        release.version
+                    );
+                }
+                if release.sha256.len() != 64
+                    || !release.sha256.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    bail!(
+                        "SHA256 must be a 64 character hex digest, got '{}'",
+                        release.sha256
+                    );
+                }
+                if release.url_template.is_none() && config.settings.release_url_template.is_none()
+                {
+                    bail!("Give a --url-template or set release_url_template in the config");
+                }
+                if config.settings.remote_host.is_some() {
+                    bail!("Releases can't be benchmarked on a remote_host");
+                }
+            }
         }
 
+        let notifiers = notify::from_config(&config.notifiers)?;
         Ok(Bencher {
             config,
             db,
             src_dir,
             bench_type,
             options,
+            runs: Vec::new(),
+            artifacts: None,
+            only_if_changed: false,
+            clone: false,
+            use_isolated_cpus: false,
+            profile_memory: false,
+            master_override: None,
+            commit_depth: None,
+            explain: false,
+            max_runtime: None,
+            deadline: None,
+            master_history: None,
+            pinned_cpus: Vec::new(),
+            binary_versions: Vec::new(),
+            commit_cache: RefCell::new(HashMap::new()),
+            reusing_datadir: false,
+            release_bin: None,
+            notifiers,
+            remote: None,
         })
     }
 
+    /// Skip commits whose tree is identical to that of the last recorded run.
+    pub fn with_only_if_changed(mut self, only_if_changed: bool) -> Self {
+        self.only_if_changed = only_if_changed;
+        self
+    }
+
+    /// Clone `repo_url` into `src_dir` before benchmarking if it isn't already a repository.
+    pub fn with_clone(mut self, clone: bool) -> Self {
+        self.clone = clone;
+        self
+    }
+
+    /// Pin jobs to the CPUs isolated from the scheduler with the `isolcpus` kernel parameter.
+    pub fn with_isolated_cpus(mut self, use_isolated_cpus: bool) -> Self {
+        self.use_isolated_cpus = use_isolated_cpus;
+        self
+    }
+
+    /// Write a memory timeline for every bench job, see `profile_memory`.
+    pub fn with_profile_memory(mut self, profile_memory: bool) -> Self {
+        self.profile_memory = profile_memory;
+        self
+    }
+
+    /// Force the `was_master` flag recorded for runs, e.g. to plot a branch as master.
+    pub fn with_master_override(mut self, master_override: Option<bool>) -> Self {
+        self.master_override = master_override;
+        self
+    }
+
+    /// Record whether each commit is on origin/master by looking through its `depth` most recent
+    /// commits. Older commits fall back to the default of being recorded as master.
+    pub fn with_commit_depth(mut self, commit_depth: Option<usize>) -> Self {
+        self.commit_depth = commit_depth;
+        self
+    }
+
+    /// Stop gracefully between commits or jobs once the run has taken `max_runtime`.
+    pub fn with_max_runtime(mut self, max_runtime: Option<Duration>) -> Self {
+        self.max_runtime = max_runtime;
+        self
+    }
+
+    /// Print the date and commit resolution of the run instead of benchmarking it.
+    pub fn with_explain(mut self, explain: bool) -> Self {
+        self.explain = explain;
+        self
+    }
+
+    /// Archive the bitcoin data dir after each run, before any cleanup.
+    pub fn with_artifacts(mut self, artifacts: Option<ArtifactOptions>) -> Self {
+        self.artifacts = artifacts;
+        self
+    }
+
+    /// The `(run_id, commit_id)` of each run recorded so far.
+    pub fn runs(&self) -> &[(i64, String)] {
+        &self.runs
+    }
+
+    /// Metadata of `commit_id`, from git only the first time a commit is looked up. Short hashes
+    /// and refs are resolved to the full hash the cache is keyed by first.
+    fn commit_metadata(&self, commit_id: &str) -> Result<CommitMetadata> {
+        let is_full_hash =
+            commit_id.len() == 40 && commit_id.bytes().all(|b| b.is_ascii_hexdigit());
+        let hash = if is_full_hash {
+            commit_id.to_string()
+        } else {
+            util::get_commit_hash(self.src_dir, commit_id)?
+        };
+        if let Some(metadata) = self.commit_cache.borrow().get(&hash) {
+            return Ok(metadata.clone());
+        }
+        let metadata = util::get_commit_metadata(self.src_dir, &hash)?;
+        self.commit_cache
+            .borrow_mut()
+            .insert(hash, metadata.clone());
+        Ok(metadata)
+    }
+
     pub fn setup(&self, date_to_use: i64) -> Result<(i64, String)> {
         let (commit_id, commit_date) = match &self.options {
             BenchOptions::Single(single) => {
-                let commit_date = util::get_commit_date(self.src_dir, &single.commit)
+                let commit_date = self
+                    .commit_metadata(&single.commit)
                     .unwrap_or_else(|e| {
                         error!("Error fetching commit date: {}", e);
                         std::process::exit(exitcode::USAGE);
-                    });
+                    })
+                    .date;
                 (single.commit.clone(), commit_date)
             }
             BenchOptions::Multi(_) => {
@@ -82,18 +608,34 @@ impl<'a> Bencher<'a> {
                         error!("Error fetching commit ID: {}", e);
                         std::process::exit(exitcode::USAGE);
                     });
-                let commit_date = util::get_commit_date(self.src_dir, &fetched_commit_id)
+                let commit_date = self
+                    .commit_metadata(&fetched_commit_id)
                     .unwrap_or_else(|e| {
                         error!("Error fetching commit date: {}", e);
                         std::process::exit(exitcode::USAGE);
-                    });
+                    })
+                    .date;
                 (fetched_commit_id, commit_date)
             }
+            BenchOptions::Commits(_) | BenchOptions::Tags(_) | BenchOptions::Release(_) => {
+                bail!("Only the Single and Multi bench types select by date")
+            }
         };
 
         Ok((commit_date, commit_id))
     }
 
+    /// This process's PATH with the `bin` directory of the release being benchmarked first, for
+    /// the release's jobs.
+    fn release_path(&self) -> Result<Option<OsString>> {
+        let Some(bin) = &self.release_bin else {
+            return Ok(None);
+        };
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let paths = std::iter::once(bin.clone()).chain(std::env::split_paths(&path));
+        Ok(Some(std::env::join_paths(paths)?))
+    }
+
     fn process_env_vars(&self, env: &Option<Vec<String>>) -> Option<Vec<(OsString, OsString)>> {
         env.as_ref().map(|env_vars| {
             env_vars
@@ -106,155 +648,2086 @@ impl<'a> Bencher<'a> {
         })
     }
 
-    fn process_args(&'a self, args: &'a str) -> Result<Vec<&str>> {
-        let parts: Vec<&str> = args.split_whitespace().collect();
+    /// The environment `job` runs with, as recorded: the job's `env` merged over the harmless
+    /// variables of this process which affect builds and benchmarks, or only the job's `env`
+    /// inside a container or on a remote host. Values of sensitive looking variables and
+    /// credentials in URLs are redacted unless `record_sensitive_env` is set.
+    fn job_environment(&self, job: &Job) -> Vec<(String, String)> {
+        let settings = &self.config.settings;
+        let mut env: BTreeMap<String, String> = match settings.container_image {
+            Some(_) => BTreeMap::new(),
+            None if self.remote.is_some() => BTreeMap::new(),
+            None => std::env::vars_os()
+                .filter(|(key, _)| util::is_recorded_process_env(&key.to_string_lossy()))
+                .map(|(key, value)| {
+                    (
+                        key.to_string_lossy().into_owned(),
+                        value.to_string_lossy().into_owned(),
+                    )
+                })
+                .collect(),
+        };
+        if let Ok(Some(path)) = self.release_path() {
+            env.insert("PATH".to_string(), path.to_string_lossy().into_owned());
+        }
+        for (key, value) in self.process_env_vars(&job.env).unwrap_or_default() {
+            env.insert(
+                key.to_string_lossy().into_owned(),
+                value.to_string_lossy().into_owned(),
+            );
+        }
+        if settings.record_sensitive_env {
+            return env.into_iter().collect();
+        }
+        env.into_iter()
+            .map(|(key, value)| match util::is_sensitive_env_name(&key) {
+                true => (key, "<redacted>".to_string()),
+                false => {
+                    let value = util::redact_url_credentials(&value);
+                    (key, value)
+                }
+            })
+            .collect()
+    }
+
+    fn process_args(&'a self, command: &'a JobCommand) -> Result<Vec<&'a str>> {
+        let parts = command.args();
         if parts.is_empty() {
             bail!("Empty command provided");
         }
         Ok(parts)
     }
 
-    fn run_single_job(&self, job: &Job, run_id: i64) -> Result<()> {
-        let output_filename = format!("/tmp/{}-{}-output.log", run_id, &job.name);
-        let error_filename = format!("/tmp/{}-{}-error.log", run_id, &job.name);
-        let output_file = std::fs::File::create(&output_filename)?;
-        let error_file = std::fs::File::create(&error_filename)?;
-
-        let bench_args = self.process_args(&job.command)?;
-        let is_macos = std::env::consts::OS == "macos";
-        let mut command = if job.bench {
-            let mut cmd = if is_macos {
-                Command::new("/usr/local/bin/gtime")
+    /// Build the command for a job, wrapped in a memory limited scope, `perf`, `time` and a
+    /// container as configured.
+    fn create_command(
+        &self,
+        job: &Job,
+        outfile: &str,
+        profile_path: Option<&str>,
+        memory_events_path: Option<&str>,
+    ) -> Result<Command> {
+        let mut argv: Vec<String> = self
+            .process_args(&job.command)?
+            .into_iter()
+            .map(String::from)
+            .collect();
+        if let Some((limit_mb, path)) = job.memory_high_mb.zip(memory_events_path) {
+            argv.splice(0..0, util::memory_high_args(limit_mb, path));
+        }
+        if let Some(path) = profile_path {
+            argv.splice(0..0, ["perf", "record", "-g", "-o", path].map(String::from));
+        }
+
+        let settings = &self.config.settings;
+        if job.bench && job.measure == Measure::Time {
+            if let Some(wrapper) = &job.time_wrapper {
+                let args = wrapper
+                    .args
+                    .iter()
+                    .map(|arg| arg.replace("{outfile}", outfile));
+                argv.splice(0..0, std::iter::once(wrapper.path.clone()).chain(args));
             } else {
-                Command::new("/usr/bin/time")
-            };
-            cmd.args([
-                "-v",
-                format!("--output={}", job.outfile.as_ref().unwrap()).as_str(),
-            ])
-            .args(&bench_args)
-            .stdout(Stdio::from(output_file))
-            .stderr(Stdio::from(error_file));
+                let is_macos = std::env::consts::OS == "macos";
+                // Containers are always Linux, so use the GNU time inside the image
+                let time = if let Some(remote) = &self.remote {
+                    &remote.time
+                } else if is_macos && settings.container_image.is_none() {
+                    "/usr/local/bin/gtime"
+                } else {
+                    "/usr/bin/time"
+                };
+                argv.splice(
+                    0..0,
+                    [
+                        time.to_string(),
+                        "-v".to_string(),
+                        format!("--output={}", outfile),
+                    ],
+                );
+            }
+        }
+
+        let envs = self.process_env_vars(&job.env).unwrap_or_default();
+        let command = if let Some(remote) = &self.remote {
+            let mut words = vec!["exec".to_string()];
+            if !envs.is_empty() {
+                words.push("env".to_string());
+                words.extend(envs.iter().map(|(key, value)| {
+                    remote::shell_quote(&format!(
+                        "{}={}",
+                        key.to_string_lossy(),
+                        value.to_string_lossy()
+                    ))
+                }));
+            }
+            words.extend(argv.iter().map(|arg| remote::shell_quote(arg)));
+            remote.command(&words.join(" "))
+        } else if let Some(image) = &settings.container_image {
+            // We are running from within src_dir at this point
+            let src_dir = std::env::current_dir()?;
+            let mut volumes = vec![src_dir.clone()];
+            if let Some(data_dir) = &settings.bitcoin_data_dir {
+                volumes.push(data_dir.clone());
+            }
+            if let Some(outfile_dir) = PathBuf::from(outfile).parent() {
+                volumes.push(outfile_dir.to_path_buf());
+            }
 
+            let mut cmd = Command::new(&settings.container_runtime);
+            cmd.args(["run", "--rm", "-w"]).arg(&src_dir);
+            for volume in volumes {
+                let volume = volume.to_string_lossy();
+                cmd.arg("-v").arg(format!("{}:{}", volume, volume));
+            }
+            for (key, value) in &envs {
+                let mut env = key.clone();
+                env.push("=");
+                env.push(value);
+                cmd.arg("-e").arg(env);
+            }
+            if !self.pinned_cpus.is_empty() {
+                cmd.arg(format!(
+                    "--cpuset-cpus={}",
+                    util::format_cpu_list(&self.pinned_cpus)
+                ));
+            }
+            cmd.arg(image).args(&argv);
             cmd
         } else {
-            let parts: Vec<&str> = job.command.split_whitespace().collect();
-            if parts.is_empty() {
-                bail!("Empty command provided for job {}", job.name);
-            }
-            let (cmd, args) = parts.split_at(1);
-            let mut cmd = Command::new(cmd[0]);
-            cmd.args(args)
-                .stdout(Stdio::from(output_file))
-                .stderr(Stdio::from(error_file));
+            let (program, args) = argv.split_at(1);
+            let mut cmd = Command::new(&program[0]);
+            // Also where the program is looked up
+            if let Some(path) = self.release_path()? {
+                cmd.env("PATH", path);
+            }
+            cmd.args(args).envs(envs);
+            if !self.pinned_cpus.is_empty() {
+                util::pin_to_cpus(&mut cmd, &self.pinned_cpus)?;
+            }
             cmd
         };
 
-        if let Some(envs) = self.process_env_vars(&job.env) {
-            command.envs(envs);
+        Ok(command)
+    }
+
+    fn run_single_job(&self, job: &Job, run_id: i64) -> Result<()> {
+        let files = self.job_files(job, run_id)?;
+        let executions = if job.bench { job.runs } else { 1 };
+        let individual = executions > 1 && self.config.settings.record_individual_samples;
+        let sample_memory = self.samples_memory(job);
+
+        let mut samples = Vec::new();
+        for sample in 0..executions {
+            if executions > 1 {
+                info!(
+                    "Running job {} ({} of {})",
+                    job.name,
+                    sample + 1,
+                    executions
+                );
+            }
+            // Each execution starts from the same data dir as the first
+            if sample > 0 && self.config.jobs.cleanup {
+                self.cleanup_data_dir()?;
+            }
+            let mut execution = self.execute_job(job, &files, sample_memory)?;
+            let succeeded = execution.succeeded();
+            if individual {
+                if let Some(result) = execution.result.take() {
+                    let stats = JobStats::from_results(std::slice::from_ref(&result));
+                    let job_id = self.record_execution(
+                        job,
+                        run_id,
+                        &files,
+                        result,
+                        &execution,
+                        stats.as_ref(),
+                    )?;
+                    self.db.set_sample_index(job_id, sample)?;
+                }
+            }
+            samples.push(execution);
+            if !succeeded {
+                break;
+            }
         }
+        let last = samples.len() - 1;
 
-        info!("Running command: {:?}", command);
-        let status = command.spawn()?.wait()?;
+        // Record bench results even for failed jobs, the exit status is stored alongside them
+        if job.bench && !individual {
+            let results: Vec<TimeResult> = samples
+                .iter_mut()
+                .filter_map(|execution| execution.result.take())
+                .collect();
+            let stats = JobStats::from_results(&results);
+            // A failed execution's results are recorded, otherwise the typical one's
+            let recorded = if samples[last].succeeded() {
+                let user_times: Vec<f64> = results.iter().map(|r| r.user_time).collect();
+                median_index(&user_times)
+            } else {
+                results.len() - 1
+            };
+            if let Some(result) = results.into_iter().nth(recorded) {
+                self.record_execution(
+                    job,
+                    run_id,
+                    &files,
+                    result,
+                    &samples[recorded],
+                    stats.as_ref(),
+                )?;
+            }
+        }
+        if let Some(ref path) = files.profile {
+            info!("Wrote perf profile for job {} to '{}'", job.name, path);
+        }
 
+        let Execution {
+            status,
+            failed_criterion,
+            ..
+        } = &samples[last];
         if !status.success() {
+            // The log may not outlive a CI job, so show the likely cause inline
+            let stderr = std::fs::read_to_string(&files.error).unwrap_or_default();
+            let tail = util::tail_lines(&stderr, 20, 200, 4096);
+            bail!(
+                "Job {} failed, see '{}' for details{}",
+                job.name,
+                files.error,
+                if tail.is_empty() {
+                    String::new()
+                } else {
+                    format!(":\n{}", tail)
+                }
+            );
+        } else if let Some(criterion) = failed_criterion {
             bail!(
-                "Job {} failed, see '{}' for details",
+                "Job {} exited 0 but failed its {}, see '{}' for details",
                 job.name,
-                error_filename
+                criterion,
+                files.output
             );
         } else {
             info!(
                 "Job {} completed successfully, see '{}' for details",
-                job.name, output_filename,
+                job.name, files.output,
             );
         }
 
-        if job.bench {
-            if let Some(ref outfile_path) = job.outfile {
-                let results = TimeResult::from_file(outfile_path)?;
-                self.db.record_job(run_id, results)?;
-            }
-        }
-
         Ok(())
     }
 
-    fn run_benchmarks(&mut self, run_date: i64, commit_id: &str, commit_date: i64) -> Result<()> {
-        let run = Run {
-            id: None,
-            run_date,
-            commit_id: commit_id.to_string(),
-            commit_date,
-            was_master: true,
+    /// Where `job` writes its output, time report, metrics, profile and memory events in `run_id`.
+    fn job_files(&self, job: &Job, run_id: i64) -> Result<JobFiles> {
+        // Include the run in the name so concurrent runs don't read each other's results
+        let outfiles: Vec<String> = job
+            .outfile
+            .as_ref()
+            .with_context(|| format!("Job {} has no outfile", job.name))?
+            .paths()
+            .iter()
+            .map(|path| path.replace("{run_id}", &run_id.to_string()))
+            .collect();
+        let (outfile, metrics) = outfiles
+            .split_first()
+            .with_context(|| format!("Job {} has no outfile for the time report", job.name))?;
+        let profile = if job.profile && self.remote.is_some() {
+            warn!(
+                "Running job {} without profiling, perf isn't run on a remote_host",
+                job.name
+            );
+            None
+        } else if job.profile {
+            match util::check_perf_usable() {
+                Ok(()) => Some(format!("{}.perf", outfile)),
+                Err(e) => {
+                    warn!("Running job {} without profiling: {}", job.name, e);
+                    None
+                }
+            }
+        } else {
+            None
         };
+        Ok(JobFiles {
+            output: format!("/tmp/{}-{}-output.log", run_id, &job.name),
+            error: format!("/tmp/{}-{}-error.log", run_id, &job.name),
+            outfile: outfile.clone(),
+            metrics: metrics.to_vec(),
+            profile,
+            memory_events: self
+                .memory_high_supported(job)
+                .then(|| format!("/tmp/{}-{}-memory.events", run_id, job.name)),
+        })
+    }
 
-        let run_id = self.db.record_run(run)?;
-        let jobs = std::mem::take(&mut self.config.jobs);
-
-        std::env::set_current_dir(self.src_dir)
-            .map_err(|e| anyhow::anyhow!("Failed to change directory: {:?}", e))?;
-        info!("Changed working directory to {}", self.src_dir.display());
+    /// Run `job` once, reading back its results if it is a bench job and sampling its RSS if
+    /// `sample_memory` is set.
+    fn execute_job(&self, job: &Job, files: &JobFiles, sample_memory: bool) -> Result<Execution> {
+        let output_file = std::fs::File::create(&files.output)?;
+        let error_file = std::fs::File::create(&files.error)?;
+        // A report left by an earlier execution mustn't pass for this one's
+        for path in std::iter::once(&files.outfile).chain(&files.metrics) {
+            let _ = std::fs::remove_file(path);
+        }
+        let remote = self.remote.as_ref().filter(|_| job.bench);
+        let reports: Vec<&str> = std::iter::once(&files.outfile)
+            .chain(&files.metrics)
+            .map(String::as_str)
+            .collect();
+        if let Some(remote) = remote {
+            remote.prepare_files(&reports)?;
+        }
 
-        util::checkout_commit(self.src_dir, commit_id).unwrap_or_else(|e| {
-            error!("Error checking out commit: {}", e);
-            std::process::exit(exitcode::SOFTWARE);
-        });
+        let native = job.bench && job.measure == Measure::Native;
+        let report_pipe = if job.bench && !native && self.pipe_time_output() {
+            Some(std::io::pipe()?)
+        } else {
+            None
+        };
+        let report_path = if report_pipe.is_some() {
+            "/dev/fd/3"
+        } else {
+            &files.outfile
+        };
+        let mut command = self.create_command(
+            job,
+            report_path,
+            files.profile.as_deref(),
+            files.memory_events.as_deref(),
+        )?;
+        command
+            .stdout(Stdio::from(output_file))
+            .stderr(Stdio::from(error_file));
+        if let Some((_, writer)) = &report_pipe {
+            util::pass_fd_as_3(&mut command, writer.as_raw_fd());
+        }
 
-        debug!(
-            "Using date: {:?}, and commit_id: {}",
-            util::unix_timestamp_to_hr(commit_date),
-            commit_id
-        );
+        info!("Running command: {:?}", command);
+        let started = Instant::now();
+        let mut child = command.spawn()?;
+        let interval = Duration::from_millis(self.config.settings.memory_sample_interval_ms);
+        let rss_sampler = sample_memory.then(|| util::RssSampler::start(child.id(), interval));
+        // Drop our write end. The report is read once time exits, without waiting for EOF, as the
+        // job and anything it leaves running inherit the write end too
+        let report_reader = report_pipe.map(|(reader, _)| reader);
+        let io = if job.bench && self.measure_io() {
+            util::wait_for_exit(child.id())
+                .and_then(|()| util::get_process_io(child.id()))
+                .map_err(|e| warn!("Could not measure I/O for job {}: {}", job.name, e))
+                .ok()
+        } else {
+            None
+        };
+        let (status, usage) = if native {
+            let (status, usage) = util::wait_with_rusage(child.id())?;
+            (status, Some(usage))
+        } else {
+            (child.wait()?, None)
+        };
+        let elapsed = started.elapsed().as_secs_f64();
+        let memory_timeline = rss_sampler.map(util::RssSampler::stop);
+        if let Some(remote) = remote {
+            // The bench log is copied to the same path locally for parsing. Files which weren't
+            // written are reported as missing below.
+            let bench_log = job
+                .bench_log
+                .as_deref()
+                .filter(|_| job.bench_phases.is_some());
+            for path in reports.iter().copied().chain(bench_log) {
+                if let Err(e) = remote.fetch_file(path) {
+                    warn!("{}", e);
+                }
+            }
+        }
+        let failed_criterion = if status.success() {
+            self.check_success_criteria(job, &files.output)?
+        } else {
+            None
+        };
+        let mut execution = Execution {
+            status,
+            failed_criterion,
+            result: None,
+            report: None,
+            memory_timeline,
+        };
+        if !job.bench {
+            return Ok(execution);
+        }
 
-        for job in &jobs.jobs {
-            self.run_single_job(job, run_id)?;
+        let expected = files
+            .metrics
+            .iter()
+            .chain(std::iter::once(&files.outfile).filter(|_| report_reader.is_none() && !native));
+        let missing: Vec<&str> = expected
+            .filter(|path| !Path::new(path).exists())
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() && status.success() {
+            bail!(
+                "Job {} did not write its outfile(s): {}",
+                job.name,
+                missing.join(", ")
+            );
+        }
+        // A job which failed may not have got as far as writing them, its failure is reported by
+        // the caller and whatever it did write is recorded
+        if missing.contains(&files.outfile.as_str()) {
+            return Ok(execution);
         }
-        self.config.jobs = jobs; // What was this doing again?
 
-        Ok(())
+        let report = &mut execution.report;
+        let result = match (usage, report_reader) {
+            (Some(usage), _) => Ok(TimeResult::from_rusage(
+                job.command.args().join(" "),
+                &usage,
+                execution.exit_code(),
+                elapsed,
+            )),
+            (None, Some(reader)) => util::read_written(reader)
+                .and_then(|text| TimeResult::parse(report.insert(text), "time output pipe")),
+            (None, None) => std::fs::read_to_string(&files.outfile)
+                .with_context(|| format!("Failed to open file: {}", files.outfile))
+                .and_then(|text| TimeResult::parse(report.insert(text), &files.outfile)),
+        };
+        let result = result.with_context(|| format!("Failed to read results for job {}", job.name));
+        let mut result = match result {
+            Ok(result) => result,
+            Err(e) if !status.success() => {
+                warn!("{:#}", e);
+                return Ok(execution);
+            }
+            Err(e) => return Err(e),
+        };
+        result.read_bytes = io.map(|io| io.read_bytes);
+        result.write_bytes = io.map(|io| io.write_bytes);
+        execution.result = Some(result);
+        Ok(execution)
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        let src_dir_path = util::check_source_file(self.src_dir).unwrap_or_else(|e| {
-            error!("Error checking for source code: {}", e);
-            std::process::exit(exitcode::NOINPUT);
-        });
-
-        if let Err(e) = util::fetch_repo(src_dir_path) {
-            error!("Error updating repo: {}", e);
-            std::process::exit(exitcode::SOFTWARE);
+    /// Record `result` as a job of `run_id` along with the metrics the job's latest execution left
+    /// in `files`, returning the id of the job.
+    fn record_execution(
+        &self,
+        job: &Job,
+        run_id: i64,
+        files: &JobFiles,
+        mut result: TimeResult,
+        execution: &Execution,
+        stats: Option<&JobStats>,
+    ) -> Result<i64> {
+        // GNU time reports exit status 0 for a job killed by a signal, so mark a failed execution
+        // by its own status to keep it out of timing results
+        if !execution.status.success() && result.exit_status == 0 {
+            result.exit_status = execution.exit_code();
+        }
+        if let Some(path) = &self.config.settings.results_jsonl {
+            self.append_result_line(path, run_id, job, &result)?;
         }
+        let job_id =
+            self.db
+                .record_job(run_id, &job.name, result, stats, files.profile.as_deref())?;
+        if let Some(criterion) = &execution.failed_criterion {
+            self.db.set_failed_criterion(job_id, criterion)?;
+        }
+        if let Some(report) = &execution.report {
+            self.db.set_raw_time_output(job_id, report)?;
+        }
+        if let Some(samples) = &execution.memory_timeline {
+            let path = format!("{}.{}.memory.csv", files.outfile, job_id);
+            util::write_memory_timeline(&path, samples)?;
+            self.db.set_memory_timeline_path(job_id, &path)?;
+            info!("Wrote memory timeline of job {} to '{}'", job.name, path);
+        }
+        self.db.record_job_env(job_id, &self.job_environment(job))?;
 
-        let run_date = chrono::Utc::now().timestamp();
-        match self.bench_type {
-            BenchType::Single => {
-                let (commit_date, commit_id) = self.setup(run_date)?;
-                self.run_benchmarks(run_date, &commit_id, commit_date)?;
-                if self.config.jobs.cleanup {
-                    util::erase_dir_and_contents(&self.config.settings.bitcoin_data_dir)?;
-                }
+        for path in &files.metrics {
+            if !execution.status.success() && !Path::new(path).exists() {
+                continue;
             }
-            BenchType::Multi => {
-                let options = match &self.options {
-                    BenchOptions::Multi(multi) => multi,
-                    _ => bail!("Invalid options for Multi bench type"),
-                };
-                let start_date =
-                    util::parse_date(options.start).context("Failed to parse start date")?;
-                let end_date = util::parse_date(options.end).context("Failed to parse end date")?;
-
-                let mut current_date = start_date;
-                while current_date <= end_date {
-                    let (commit_date, commit_id) = self.setup(current_date)?;
-                    self.run_benchmarks(run_date, &commit_id, commit_date)?;
-                    current_date += 86400; // Increment by one day (86400 seconds)
-                    if self.config.jobs.cleanup {
-                        util::erase_dir_and_contents(&self.config.settings.bitcoin_data_dir)?;
+            let metrics = bench_log::parse_metrics_file(path)?;
+            self.db.record_job_metrics(job_id, &metrics)?;
+        }
+        if let Some(phases) = &job.bench_phases {
+            let bench_log = job.bench_log.as_ref().unwrap_or(&files.output);
+            match bench_log::parse_bench_log(bench_log, phases) {
+                Ok(metrics) => self.db.record_job_metrics(job_id, &metrics)?,
+                Err(e) if !execution.status.success() => warn!("{:#}", e),
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(metrics) = self.datadir_size_metrics() {
+            self.db.record_job_metrics(job_id, &metrics)?;
+        }
+        if let Some((limit_mb, path)) = job.memory_high_mb.zip(files.memory_events.as_ref()) {
+            match std::fs::read_to_string(path)
+                .map_err(|e| anyhow!("Failed to read {}: {}", path, e))
+                .and_then(|events| util::parse_memory_high_events(&events))
+            {
+                Ok(events) => {
+                    if events > 0 {
+                        info!(
+                            "Job {} went over its {} MiB memory_high_mb {} times",
+                            job.name, limit_mb, events
+                        );
                     }
+                    let metrics = [
+                        ("memory_high_mb".to_string(), limit_mb as f64),
+                        ("memory_high_events".to_string(), events as f64),
+                    ];
+                    self.db.record_job_metrics(job_id, &metrics)?;
                 }
+                Err(e) => warn!("Could not read memory events for job {}: {}", job.name, e),
             }
         }
-        Ok(())
+        Ok(job_id)
+    }
+
+    /// The success criterion a job which exited 0 failed, if any.
+    fn check_success_criteria(&self, job: &Job, output_filename: &str) -> Result<Option<String>> {
+        if let Some(pattern) = &job.success_pattern {
+            let regex = Regex::new(pattern)?;
+            let output = std::fs::File::open(output_filename)
+                .with_context(|| format!("Failed to open {}", output_filename))?;
+            let mut matched = false;
+            for line in BufReader::new(output).lines() {
+                if regex.is_match(&line?) {
+                    matched = true;
+                    break;
+                }
+            }
+            if !matched {
+                return Ok(Some(format!(
+                    "success_pattern '{}', which matched no output",
+                    pattern
+                )));
+            }
+        }
+        if let Some(success_command) = &job.success_command {
+            let mut command = match &self.remote {
+                Some(remote) => remote.command(success_command),
+                None => {
+                    let mut command = Command::new("sh");
+                    command
+                        .args(["-c", success_command])
+                        .current_dir(self.src_dir);
+                    command
+                }
+            };
+            let status = command
+                .stdout(Stdio::null())
+                .status()
+                .with_context(|| format!("Failed to execute '{}'", success_command))?;
+            if !status.success() {
+                return Ok(Some(format!(
+                    "success_command '{}', which exited with {}",
+                    success_command, status
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Decide the `was_master` flag recorded for a commit.
+    fn decide_master(&self, metadata: &CommitMetadata) -> MasterDecision {
+        let (was_master, reason, fallback) = match (self.master_override, &self.master_history) {
+            (Some(was_master), _) => (was_master, Some("manually set".to_string()), false),
+            (None, Some(history)) => {
+                let window = format!(
+                    "the last {} commits of origin/master ({})",
+                    history.depth, history.tip
+                );
+                match history.contains(&metadata.hash, metadata.date) {
+                    Some(true) => (true, Some(format!("among {}", window)), false),
+                    Some(false) => (false, Some(format!("not among {}", window)), false),
+                    None => (true, Some(format!("older than {}", window)), true),
+                }
+            }
+            (None, None) => (true, None, false),
+        };
+        MasterDecision {
+            was_master,
+            reason,
+            fallback,
+        }
+    }
+
+    /// Print how the run would resolve its dates or commit range into commits, and whether each
+    /// would be recorded as master, without benchmarking anything.
+    fn explain(&self) -> Result<()> {
+        match &self.options {
+            BenchOptions::Single(single) => {
+                println!("commit {}", single.commit);
+                self.explain_commit(&single.commit)?;
+            }
+            BenchOptions::Multi(multi) => {
+                for date in multi_dates(multi)? {
+                    println!("date {}", util::unix_timestamp_to_iso(date));
+                    println!("  git {}", util::commit_from_date_args(date).join(" "));
+                    let commit_id = util::get_commit_id_from_date(self.src_dir, &date)?;
+                    if commit_id.is_empty() {
+                        println!("  no commit on master before this date");
+                        continue;
+                    }
+                    self.explain_commit(&commit_id)?;
+                }
+            }
+            BenchOptions::Commits(range) => {
+                println!(
+                    "git {}",
+                    util::commits_in_range_args(&range.range, range.order).join(" ")
+                );
+                let commits = util::get_commits_in_range(self.src_dir, &range.range, range.order)?;
+                println!(
+                    "{} commits, benchmarking every {}",
+                    commits.len(),
+                    range.every
+                );
+                for commit_id in commits.iter().step_by(range.every) {
+                    println!("commit {}", commit_id);
+                    self.explain_commit(commit_id)?;
+                }
+            }
+            BenchOptions::Tags(tags) => {
+                println!("git {}", util::tags_args(&tags.pattern).join(" "));
+                let tags = util::get_tags(self.src_dir, &tags.pattern)?;
+                println!("{} tags", tags.len());
+                for (tag, commit_id) in &tags {
+                    println!("tag {}", tag);
+                    self.explain_commit(commit_id)?;
+                }
+            }
+            BenchOptions::Release(release) => {
+                println!("release {}", release.version);
+                println!("  download {}", self.release_url(release));
+                println!("  sha256 {}", release.sha256.to_lowercase());
+                println!(
+                    "  was_master {} ({})",
+                    self.master_override.unwrap_or(false),
+                    match self.master_override {
+                        Some(_) => "manually set",
+                        None => "default for releases",
+                    }
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn explain_commit(&self, commit_id: &str) -> Result<()> {
+        let metadata = self.commit_metadata(commit_id)?;
+        let decision = self.decide_master(&metadata);
+        println!(
+            "  resolved to {} committed {} by {}: {}",
+            metadata.hash,
+            util::unix_timestamp_to_iso(metadata.date),
+            metadata.author,
+            metadata.subject
+        );
+        println!(
+            "  was_master {} ({})",
+            decision.was_master,
+            decision.reason.as_deref().unwrap_or("default")
+        );
+        Ok(())
+    }
+
+    /// Runs all configured jobs against `commit_id`, selected by `tag` if given, returning the
+    /// names of any jobs which failed.
+    fn run_benchmarks(
+        &mut self,
+        run_date: i64,
+        commit_id: &str,
+        commit_date: i64,
+        tag: Option<&str>,
+    ) -> Result<Vec<String>> {
+        let metadata = self.commit_metadata(commit_id)?;
+        let tree_hash = metadata.tree_hash.clone();
+        if self.only_if_changed && self.db.get_last_tree_hash()?.as_ref() == Some(&tree_hash) {
+            info!(
+                "Skipping commit {}, tree {} is unchanged since the last run",
+                commit_id, tree_hash
+            );
+            return Ok(Vec::new());
+        }
+
+        let signature_verified = if self.config.settings.verify_signature {
+            // A tag is verified itself, as the commit it points at needn't be signed
+            util::verify_signature(self.src_dir, tag.unwrap_or(commit_id))?;
+            Some(true)
+        } else {
+            None
+        };
+
+        let decision = self.decide_master(&metadata);
+        match &decision.reason {
+            Some(reason) if decision.fallback => warn!(
+                "Recording commit {} with was_master {}: {}",
+                commit_id, decision.was_master, reason
+            ),
+            Some(reason) => info!(
+                "Recording commit {} with was_master {}: {}",
+                commit_id, decision.was_master, reason
+            ),
+            None => {}
+        }
+        let was_master = decision.was_master;
+
+        let run = Run {
+            commit_index: util::get_commit_height(self.src_dir, commit_id)
+                .map_err(|e| warn!("Could not determine commit index: {}", e))
+                .ok(),
+            tree_hash: Some(tree_hash),
+            signature_verified,
+            commit_order: match &self.options {
+                BenchOptions::Commits(range) => range.order.map(|order| order.as_str().to_string()),
+                _ => None,
+            },
+            commit_range: match &self.options {
+                BenchOptions::Commits(range) => Some(range.range.clone()),
+                _ => None,
+            },
+            tag: tag.map(String::from),
+            ..self.new_run(run_date, commit_id, commit_date, was_master)
+        };
+        let run_id = self.record_new_run(run)?;
+        if self.reusing_datadir {
+            self.db.set_datadir_reused(run_id)?;
+        }
+
+        std::env::set_current_dir(self.src_dir)
+            .map_err(|e| anyhow::anyhow!("Failed to change directory: {:?}", e))?;
+        info!("Changed working directory to {}", self.src_dir.display());
+
+        util::checkout_commit(self.src_dir, commit_id).unwrap_or_else(|e| {
+            error!("Error checking out commit: {}", e);
+            std::process::exit(exitcode::SOFTWARE);
+        });
+        if let Some(remote) = &self.remote {
+            remote.checkout(commit_id)?;
+        }
+
+        debug!(
+            "Using date: {:?}, and commit_id: {}",
+            util::unix_timestamp_to_hr(commit_date),
+            commit_id
+        );
+
+        let ccache_before = self.ccache_stats();
+
+        let mut build = BuildProgress::default();
+        let result = self.run_and_record_jobs(run_id, commit_id, &mut build)?;
+
+        let ccache = ccache_before
+            .zip(self.ccache_stats())
+            .map(|(before, after)| after.since(&before));
+        self.db.set_build_state(run_id, build.clean, ccache)?;
+        // Read after the build jobs, which generate the build system's caches. Remote builds leave
+        // them on the remote host, and without a build job the host's compiler built nothing.
+        if build.ran && self.remote.is_none() {
+            self.db
+                .set_toolchain(run_id, &util::detect_toolchain(self.src_dir))?;
+        }
+        if let Some(failure) = result
+            .as_ref()
+            .err()
+            .and_then(|e| e.downcast_ref::<BuildFailed>())
+        {
+            match self.config.settings.on_build_failure {
+                OnBuildFailure::Skip => {
+                    self.db.delete_run(run_id)?;
+                    self.runs.pop();
+                }
+                _ => self.db.set_build_error(run_id, &failure.error)?,
+            }
+            return result;
+        }
+        self.finish_run(run_id, was_master, commit_id, result)
+    }
+
+    /// A run of `commit_id` with the details shared by every kind of run filled in.
+    fn new_run(&self, run_date: i64, commit_id: &str, commit_date: i64, was_master: bool) -> Run {
+        let settings = &self.config.settings;
+        Run {
+            id: None,
+            run_date,
+            commit_id: commit_id.to_string(),
+            commit_date,
+            commit_index: None,
+            tree_hash: None,
+            container_image: settings.container_image.clone(),
+            container_digest: settings.container_image.as_ref().and_then(|image| {
+                util::get_container_image_id(&settings.container_runtime, image)
+                    .map_err(|e| warn!("Could not determine container image digest: {}", e))
+                    .ok()
+            }),
+            signature_verified: None,
+            commit_order: None,
+            commit_range: None,
+            tag: None,
+            run_duration: None,
+            assumevalid: settings.assumevalid.clone(),
+            par: settings.par.map(i64::from),
+            notes: None,
+            from_release: false,
+            was_master,
+            num_cores: match &self.remote {
+                Some(remote) => Some(remote.num_cores.into()),
+                None => util::get_nproc().ok().and_then(|n| n.parse().ok()),
+            },
+            cpu_model: match &self.remote {
+                Some(remote) => remote.cpu_model.clone(),
+                None => util::get_cpu_model()
+                    .map_err(|e| warn!("Could not determine CPU model: {}", e))
+                    .ok(),
+            },
+            remote_host: self.remote.as_ref().map(|remote| remote.host.clone()),
+            throttled: None,
+            max_temperature_c: None,
+            datadir_reused: false,
+        }
+    }
+
+    /// Record `run` along with the binary versions, config and CPUs it runs with.
+    fn record_new_run(&mut self, run: Run) -> Result<i64> {
+        let commit_id = run.commit_id.clone();
+        let run_id = self.db.record_run(run)?;
+        self.db
+            .record_binary_versions(run_id, &self.binary_versions)?;
+        self.db.set_config_hash(run_id, &self.config.hash)?;
+        if !self.pinned_cpus.is_empty() {
+            self.db
+                .set_pinned_cpus(run_id, &util::format_cpu_list(&self.pinned_cpus))?;
+        }
+        self.runs.push((run_id, commit_id));
+        Ok(run_id)
+    }
+
+    /// Deal with the `result` of running a run's jobs: discard a run stopped by `--max-runtime`,
+    /// or compare it with the previous run and archive its data dir.
+    fn finish_run(
+        &mut self,
+        run_id: i64,
+        was_master: bool,
+        commit_id: &str,
+        result: Result<Vec<String>>,
+    ) -> Result<Vec<String>> {
+        if result.as_ref().is_err_and(|e| e.is::<OutOfTime>()) {
+            // Resuming benchmarks this commit again, so don't keep its partial results
+            if self.db.is_append_only() {
+                warn!(
+                    "Keeping the partial results of run {} as the database is append-only",
+                    run_id
+                );
+            } else {
+                self.db.delete_run(run_id)?;
+                self.runs.pop();
+            }
+            return result;
+        }
+        let settings = &self.config.settings;
+        if was_master && !settings.compare_previous.is_empty() {
+            match check::compare_with_previous(
+                self.db,
+                run_id,
+                &settings.compare_previous,
+                settings.store_deltas,
+            ) {
+                Ok(regressions) if !regressions.is_empty() => notify::notify_all(
+                    &self.notifiers,
+                    &Event::Regression {
+                        run_id,
+                        commit: commit_id.to_string(),
+                        regressions,
+                    },
+                ),
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "Could not compare run {} with the previous run: {}",
+                    run_id, e
+                ),
+            }
+        }
+        if let Some(artifacts) = &self.artifacts {
+            if self.remote.is_some() {
+                warn!("The data dir of a remote_host isn't archived");
+            } else {
+                self.archive_data_dir(artifacts, run_id, commit_id)?;
+            }
+        }
+        self.notify_run_completed(run_id, commit_id, &result);
+        result
+    }
+
+    /// Tell the notifiers `run_id` finished with `result`, along with the metrics of its jobs.
+    fn notify_run_completed(&self, run_id: i64, commit_id: &str, result: &Result<Vec<String>>) {
+        if self.notifiers.is_empty() {
+            return;
+        }
+        let jobs = match self.db.get_jobs_for_run(run_id) {
+            Ok(jobs) => jobs.iter().map(|(job, _)| JobMetrics::from(job)).collect(),
+            Err(e) => {
+                warn!("Could not read the jobs of run {} to notify: {}", run_id, e);
+                Vec::new()
+            }
+        };
+        let event = Event::RunCompleted {
+            run_id,
+            commit: commit_id.to_string(),
+            failed_jobs: result.as_ref().map_or(Vec::new(), Vec::clone),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            jobs,
+        };
+        notify::notify_all(&self.notifiers, &event);
+    }
+
+    /// The URL of `release`'s tarball.
+    fn release_url(&self, release: &Release) -> String {
+        let template = release
+            .url_template
+            .as_ref()
+            .or(self.config.settings.release_url_template.as_ref())
+            .expect("checked when the bencher was created");
+        template.replace("{version}", &release.version)
+    }
+
+    /// Download `release`'s tarball into the release dir, unless a copy with the expected SHA256
+    /// is already there, and extract it, returning the directory of the extracted release and the
+    /// release's date.
+    fn fetch_release(&self, release: &Release) -> Result<(PathBuf, i64)> {
+        let url = self.release_url(release);
+        let expected = release.sha256.to_lowercase();
+        let file_name = url
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .unwrap_or("release.tar.gz");
+        std::fs::create_dir_all(self.src_dir)
+            .with_context(|| format!("Failed to create {}", self.src_dir.display()))?;
+        let tarball = self.src_dir.join(file_name);
+
+        if tarball.exists() && util::file_sha256(&tarball)? == expected {
+            info!("Using the previously downloaded {}", tarball.display());
+        } else {
+            util::download_file(&url, &tarball)?;
+            let sha256 = util::file_sha256(&tarball)?;
+            if sha256 != expected {
+                std::fs::remove_file(&tarball)?;
+                bail!("SHA256 of {} is {}, expected {}", url, sha256, expected);
+            }
+        }
+        let root = util::extract_tarball(&tarball, &self.src_dir.join(&release.version))?;
+        let release_date = util::newest_file_date(&root)?;
+        Ok((root, release_date))
+    }
+
+    /// Runs all configured jobs except the build jobs against an official release, returning the
+    /// names of any jobs which failed.
+    fn run_release(&mut self, run_date: i64, release: &Release) -> Result<Vec<String>> {
+        let (root, release_date) = self.fetch_release(release)?;
+
+        let was_master = self.master_override.unwrap_or(false);
+        let run = Run {
+            from_release: true,
+            ..self.new_run(run_date, &release.version, release_date, was_master)
+        };
+        let run_id = self.record_new_run(run)?;
+
+        std::env::set_current_dir(&root)
+            .map_err(|e| anyhow!("Failed to change directory: {:?}", e))?;
+        self.release_bin = Some(root.join("bin"));
+        info!(
+            "Running jobs in {} with its bin directory first on PATH",
+            root.display()
+        );
+
+        let result =
+            self.run_and_record_jobs(run_id, &release.version, &mut BuildProgress::default())?;
+        self.finish_run(run_id, was_master, &release.version, result)
+    }
+
+    /// Run the jobs of `run_id` against `commit_id`, recording how long they took and whether the
+    /// CPU was throttled meanwhile. The jobs' result is returned once those are recorded.
+    fn run_and_record_jobs(
+        &self,
+        run_id: i64,
+        commit_id: &str,
+        build: &mut BuildProgress,
+    ) -> Result<Result<Vec<String>>> {
+        let run_started_at = Instant::now();
+        let thermal = self.start_thermal_monitor();
+        let result = self.run_jobs(run_id, commit_id, build);
+        self.record_thermal(run_id, thermal)?;
+        self.db
+            .set_run_duration(run_id, run_started_at.elapsed().as_secs() as i64)?;
+        Ok(result)
+    }
+
+    /// Append a job's results and the commit it ran against to `path` as a single JSON line.
+    fn append_result_line(
+        &self,
+        path: &Path,
+        run_id: i64,
+        job: &Job,
+        result: &TimeResult,
+    ) -> Result<()> {
+        let run = self.db.get_run(run_id)?;
+        let line = serde_json::json!({
+            "run_id": run_id,
+            "run_date": run.run_date,
+            "commit_id": run.commit_id,
+            "commit_date": run.commit_date,
+            "job_name": job.name,
+            "result": result,
+        });
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        // Write the line in one call so a reader tailing the file never sees half of it
+        file.write_all(format!("{}\n", line).as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Whether time's report should be read from a pipe rather than the job's outfile.
+    fn pipe_time_output(&self) -> bool {
+        let settings = &self.config.settings;
+        if settings.time_output != TimeOutput::Pipe {
+            return false;
+        }
+        if settings.container_image.is_some() {
+            warn!("time_output = \"pipe\" isn't passed into containers, using the outfile");
+            return false;
+        }
+        if self.remote.is_some() {
+            warn!("time_output = \"pipe\" isn't passed over SSH, using the outfile");
+            return false;
+        }
+        true
+    }
+
+    /// Whether `/proc/<pid>/io` of the spawned process reflects the job's I/O.
+    fn measure_io(&self) -> bool {
+        let settings = &self.config.settings;
+        if !settings.measure_io {
+            return false;
+        }
+        if std::env::consts::OS != "linux" {
+            warn!("measure_io is only supported on Linux, skipping");
+            return false;
+        }
+        if settings.container_image.is_some() {
+            warn!("measure_io would only see the container client's I/O, skipping");
+            return false;
+        }
+        if self.remote.is_some() {
+            warn!("measure_io would only see the ssh client's I/O, skipping");
+            return false;
+        }
+        true
+    }
+
+    /// Whether to sample `job`'s RSS, warning why not if it was asked for but can't be.
+    fn samples_memory(&self, job: &Job) -> bool {
+        if !job.bench || !(job.profile_memory || self.profile_memory) {
+            return false;
+        }
+        if std::env::consts::OS != "linux" {
+            warn!("profile_memory is only supported on Linux, skipping");
+            return false;
+        }
+        if self.config.settings.container_image.is_some() {
+            warn!("profile_memory would only see the container client's memory, skipping");
+            return false;
+        }
+        if self.remote.is_some() {
+            warn!("profile_memory would only see the ssh client's memory, skipping");
+            return false;
+        }
+        true
+    }
+
+    /// Whether `job`'s `memory_high_mb` limit can be applied, warning why not if it can't.
+    fn memory_high_supported(&self, job: &Job) -> bool {
+        if job.memory_high_mb.is_none() {
+            return false;
+        }
+        if std::env::consts::OS != "linux" {
+            warn!(
+                "memory_high_mb is only supported on Linux, running {} without it",
+                job.name
+            );
+            return false;
+        }
+        if self.config.settings.container_image.is_some() {
+            warn!(
+                "memory_high_mb isn't applied inside containers, running {} without it",
+                job.name
+            );
+            return false;
+        }
+        if self.remote.is_some() {
+            warn!(
+                "memory_high_mb isn't applied on a remote_host, running {} without it",
+                job.name
+            );
+            return false;
+        }
+        if !util::cgroup_v2_available() {
+            warn!(
+                "memory_high_mb needs cgroup v2, running {} without it",
+                job.name
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Sizes of the data dir the job left behind, if `measure_datadir_size` is on.
+    fn datadir_size_metrics(&self) -> Option<Vec<(String, f64)>> {
+        let settings = &self.config.settings;
+        if !settings.measure_datadir_size {
+            return None;
+        }
+        if self.remote.is_some() {
+            warn!("measure_datadir_size can't measure the data dir of a remote_host, skipping");
+            return None;
+        }
+        let data_dir = settings.bitcoin_data_dir.as_ref()?;
+        util::datadir_size_metrics(data_dir)
+            .map_err(|e| {
+                warn!(
+                    "Could not measure the size of {}: {}",
+                    data_dir.display(),
+                    e
+                )
+            })
+            .ok()
+    }
+
+    fn ccache_stats(&self) -> Option<CcacheStats> {
+        if !self.config.settings.track_ccache {
+            return None;
+        }
+        if self.remote.is_some() {
+            warn!(
+                "track_ccache would read the local ccache rather than the remote_host's, skipping"
+            );
+            return None;
+        }
+        util::get_ccache_stats()
+            .map_err(|e| warn!("Could not read ccache stats: {}", e))
+            .ok()
+    }
+
+    fn run_jobs(
+        &self,
+        run_id: i64,
+        commit_id: &str,
+        build: &mut BuildProgress,
+    ) -> Result<Vec<String>> {
+        let jobs = &self.config.jobs;
+        let mut failed_jobs = Vec::new();
+        for job in jobs.in_run_order() {
+            if self.out_of_time() {
+                return Err(OutOfTime {
+                    job_name: job.name.clone(),
+                }
+                .into());
+            }
+            if job.build && matches!(self.options, BenchOptions::Release(_)) {
+                info!("Skipping build job {} for a release", job.name);
+                continue;
+            }
+            if let Some(condition) = &job.run_if {
+                let condition = match &self.remote {
+                    Some(remote) => remote.shell_line(condition),
+                    None => condition.clone(),
+                };
+                if !util::check_precondition(&condition)? {
+                    info!("Skipping job {} (precondition not met)", job.name);
+                    continue;
+                }
+            }
+            if job.build {
+                if build.clean != Some(true) {
+                    self.check_clean_build(&mut build.clean);
+                }
+                build.ran = true;
+            }
+            if job.bench && self.config.settings.wait_for_idle {
+                self.wait_for_idle(job);
+            }
+            if let Err(e) = self.run_single_job(job, run_id) {
+                if job.build && self.config.settings.on_build_failure != OnBuildFailure::Abort {
+                    return Err(BuildFailed {
+                        commit_id: commit_id.to_string(),
+                        error: e.to_string(),
+                    }
+                    .into());
+                }
+                if !jobs.continue_on_error {
+                    return Err(e);
+                }
+                error!("{}, continuing with remaining jobs", e);
+                failed_jobs.push(format!("{} ({})", job.name, commit_id));
+            }
+            if let Some(smoke_test) = &self.config.settings.smoke_test {
+                if smoke_test.after == job.name {
+                    self.run_smoke_test(smoke_test, run_id)?;
+                }
+            }
+        }
+
+        Ok(failed_jobs)
+    }
+
+    /// Set `clean_build` to whether the source tree is clean ahead of a build job, so the build
+    /// counts as clean if the tree was clean before any of its build jobs ran, whether it started
+    /// out clean or an earlier build job such as `git clean -dfx` cleaned it. Not known for remote
+    /// builds, whose tree is on the remote host.
+    fn check_clean_build(&self, clean_build: &mut Option<bool>) {
+        if self.remote.is_some() {
+            return;
+        }
+        match util::is_worktree_clean(self.src_dir) {
+            Ok(clean) => *clean_build = Some(clean),
+            Err(e) => warn!("Could not determine whether the build is clean: {}", e),
+        }
+    }
+
+    /// Watch for thermal throttling while the jobs run, on Linux machines which expose their
+    /// temperatures or throttle counters in sysfs.
+    fn start_thermal_monitor(&self) -> Option<util::ThermalMonitor> {
+        // The jobs of a remote host heat its CPU rather than this one's
+        if std::env::consts::OS != "linux" || self.remote.is_some() {
+            return None;
+        }
+        let monitor = util::ThermalMonitor::start(Path::new("/sys"), THERMAL_SAMPLE_INTERVAL);
+        if monitor.is_none() {
+            debug!("No thermal zones or throttle counters found, not watching for throttling");
+        }
+        monitor
+    }
+
+    fn record_thermal(&self, run_id: i64, monitor: Option<util::ThermalMonitor>) -> Result<()> {
+        let Some(report) = monitor.map(util::ThermalMonitor::stop) else {
+            return Ok(());
+        };
+        if report.throttled {
+            warn!(
+                "The CPU was thermally throttled during run {}{}, its times may be inflated",
+                run_id,
+                report
+                    .max_temperature_c
+                    .map_or(String::new(), |max| format!(" (up to {:.1}°C)", max))
+            );
+        }
+        self.db
+            .set_thermal(run_id, report.throttled, report.max_temperature_c)
+    }
+
+    /// Wait until the 1 minute load average per core is at most `max_load`, running `job` anyway
+    /// once `idle_timeout` has passed or if the load average can't be read.
+    fn wait_for_idle(&self, job: &Job) {
+        let settings = &self.config.settings;
+        if self.remote.is_some() {
+            warn!(
+                "wait_for_idle only sees the local load average, not waiting before job {}",
+                job.name
+            );
+            return;
+        }
+        let cores = util::get_nproc()
+            .ok()
+            .and_then(|n| n.parse::<f64>().ok())
+            .unwrap_or(1.0);
+        let threshold = settings.max_load * cores;
+        let timeout = Duration::from_secs(settings.idle_timeout);
+        let started = Instant::now();
+        let mut waiting = false;
+        loop {
+            let load = match util::get_load_average() {
+                Ok(load) => load,
+                Err(e) => {
+                    warn!("Not waiting for the system to be idle, {}", e);
+                    return;
+                }
+            };
+            let waited = started.elapsed();
+            if load <= threshold {
+                if waiting {
+                    info!(
+                        "Load average fell to {:.2} after waiting {}s, running job {}",
+                        load,
+                        waited.as_secs(),
+                        job.name
+                    );
+                }
+                return;
+            }
+            if waited >= timeout {
+                warn!(
+                    "Load average is still {:.2}, above {:.2}, after {}s, running job {} anyway",
+                    load,
+                    threshold,
+                    waited.as_secs(),
+                    job.name
+                );
+                return;
+            }
+            if !waiting {
+                waiting = true;
+                info!(
+                    "Load average {:.2} is above {:.2} ({} per core), waiting up to {}s before job {}",
+                    load, threshold, settings.max_load, settings.idle_timeout, job.name
+                );
+            }
+            std::thread::sleep(IDLE_POLL_INTERVAL.min(timeout - waited));
+        }
+    }
+
+    /// Whether the `--max-runtime` budget is used up.
+    fn out_of_time(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// How to continue a sweep which stopped before `commit`.
+    fn resume_hint(&self, commit: &CommitToBench) -> String {
+        match (&self.options, commit.sweep_date) {
+            (BenchOptions::Multi(multi), Some(date)) => format!(
+                "resume with a start date of {} and end date of {}",
+                util::unix_timestamp_to_hr(date)
+                    .split(' ')
+                    .next()
+                    .unwrap_or_default(),
+                multi.end
+            ),
+            (BenchOptions::Commits(range), _) => {
+                let end = util::parse_commit_range(&range.range)
+                    .map(|(_, end)| end)
+                    .unwrap_or("<end>");
+                format!("resume with --commit-range {}^..{}", commit.commit_id, end)
+            }
+            _ => format!("resume from commit {}", commit.commit_id),
+        }
+    }
+
+    /// Run the smoke test, failing the run so its remaining jobs are skipped if it doesn't pass.
+    fn run_smoke_test(&self, smoke_test: &SmokeTest, run_id: i64) -> Result<()> {
+        info!("Running smoke test: {}", smoke_test.command);
+        let log_path = format!("/tmp/{}-smoke-test.log", run_id);
+        let command = match &self.remote {
+            Some(remote) => remote.shell_line(&smoke_test.command),
+            None => smoke_test.command.clone(),
+        };
+        let result =
+            util::run_with_timeout(&command, Duration::from_secs(smoke_test.timeout), &log_path);
+        self.db.set_smoke_test_passed(run_id, result.is_ok())?;
+        result.map_err(|e| {
+            anyhow::anyhow!(
+                "Smoke test failed after job {}, skipping the remaining jobs: {}",
+                smoke_test.after,
+                e
+            )
+        })
+    }
+
+    /// Whether the data dir left by `from` can be carried over to `to`, logging why not.
+    fn can_reuse_datadir(&self, from: &CommitToBench, to: &CommitToBench) -> bool {
+        match util::datadir_incompatibility(self.src_dir, &from.commit_id, &to.commit_id) {
+            Ok(None) => {
+                info!("Keeping the data dir for commit {}", to.commit_id);
+                true
+            }
+            Ok(Some(reason)) => {
+                info!(
+                    "Wiping the data dir before commit {}: {}",
+                    to.commit_id, reason
+                );
+                false
+            }
+            Err(e) => {
+                warn!(
+                    "Wiping the data dir, could not tell whether commit {} can reuse it: {}",
+                    to.commit_id, e
+                );
+                false
+            }
+        }
+    }
+
+    /// Erase the bitcoin data dir once bitcoind has released it.
+    fn cleanup_data_dir(&self) -> Result<()> {
+        let data_dir = &self.config.settings.bitcoin_data_dir;
+        let grace_period = Duration::from_secs(self.config.jobs.cleanup_grace_period);
+        if let Some((remote, dir)) = self.remote.as_ref().zip(data_dir.as_ref()) {
+            // A lost ssh connection leaves the remote bitcoind running
+            util::wait_for_datadir_release(dir, grace_period, |dir| {
+                remote.datadir_lock_holder(dir)
+            })?;
+            return remote.erase_dir_contents(dir);
+        }
+        if let Some(dir) = data_dir {
+            util::wait_for_datadir_release(dir, grace_period, util::get_datadir_lock_holder)?;
+        }
+        util::erase_dir_and_contents(data_dir)
+    }
+
+    /// Archive the bitcoin data dir produced by a run, before it is cleaned up.
+    fn archive_data_dir(
+        &self,
+        artifacts: &ArtifactOptions,
+        run_id: i64,
+        commit_id: &str,
+    ) -> Result<()> {
+        let data_dir = self
+            .config
+            .settings
+            .bitcoin_data_dir
+            .as_ref()
+            .context("bitcoin_data_dir is not set")?;
+        let extension = if artifacts.compress { "tar.zst" } else { "tar" };
+        let archive_path = artifacts
+            .dir
+            .join(format!("{}-{}.{}", run_id, commit_id, extension));
+
+        util::archive_dir(data_dir, &archive_path, artifacts.compress)?;
+        self.db
+            .set_artifact_path(run_id, &archive_path.to_string_lossy())?;
+        info!(
+            "Archived data dir for run {} to {}",
+            run_id,
+            archive_path.display()
+        );
+        Ok(())
+    }
+
+    /// Log how long benchmarking `commits` commits should take from the mean elapsed time of each
+    /// job's history, counting every execution of jobs with `runs`.
+    fn log_sweep_estimate(&self, commits: usize) -> Result<()> {
+        let mut per_commit = 0.0;
+        let mut unknown = Vec::new();
+        for job in &self.config.jobs.jobs {
+            match self.db.get_mean_elapsed(&job.name)? {
+                Some(elapsed) => {
+                    let executions = if job.bench { job.runs } else { 1 };
+                    per_commit += elapsed * f64::from(executions);
+                }
+                None => unknown.push(job.name.as_str()),
+            }
+        }
+        if unknown.len() == self.config.jobs.jobs.len() {
+            info!("No job history to estimate the duration of the sweep from");
+            return Ok(());
+        }
+        info!(
+            "Estimated duration of {} commits: {}",
+            commits,
+            util::format_duration(per_commit * commits as f64)
+        );
+        if !unknown.is_empty() {
+            info!(
+                "Estimate excludes jobs with no recorded history: {}",
+                unknown.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Benchmark each commit in turn, returning the names of failed jobs (and commits, when
+    /// `collect_all` is set).
+    fn run_sweep(
+        &mut self,
+        run_date: i64,
+        commits: Vec<CommitToBench>,
+        collect_all: bool,
+        limit_runs: Option<usize>,
+    ) -> Result<Vec<String>> {
+        let mut failed_jobs = Vec::new();
+        let mut summary = Vec::new();
+        let mut successful_runs = 0;
+        let total = limit_runs.map_or(commits.len(), |limit| limit.min(commits.len()));
+        self.log_sweep_estimate(total)?;
+        let jobs = &self.config.jobs;
+        let reuse_datadir = jobs.cleanup && jobs.reuse_datadir_when_compatible;
+        if reuse_datadir {
+            warn!(
+                "reuse_datadir_when_compatible is set, commits which can reuse the data dir of \
+                the one before measure incremental work rather than a sync from scratch"
+            );
+        }
+        let sweep_start = Instant::now();
+        let mut clock = ClockWatch::new()
+            .map_err(|e| warn!("Not watching for clock jumps during the sweep: {}", e))
+            .ok();
+        let mut commits = commits.into_iter().enumerate().peekable();
+        while let Some((done, commit)) = commits.next() {
+            if limit_runs.is_some_and(|limit| successful_runs >= limit) {
+                info!("Reached the limit of {} successful runs", successful_runs);
+                break;
+            }
+            if self.out_of_time() {
+                warn!(
+                    "Stopping after {} of {} commits as --max-runtime is used up, {}",
+                    done,
+                    total,
+                    self.resume_hint(&commit)
+                );
+                break;
+            }
+
+            let runs_before = self.runs.len();
+            let result = self.run_benchmarks(
+                run_date,
+                &commit.commit_id,
+                commit.commit_date,
+                commit.tag.as_deref(),
+            );
+            // A failed run may have left the data dir in any state
+            let next = commits
+                .peek()
+                .filter(|_| reuse_datadir && result.as_ref().is_ok_and(Vec::is_empty));
+            self.reusing_datadir =
+                next.is_some_and(|(_, next)| self.can_reuse_datadir(&commit, next));
+            if self.config.jobs.cleanup && !self.reusing_datadir {
+                self.cleanup_data_dir()?;
+            }
+
+            // Commits skipped as unchanged don't record a run
+            let recorded = self.runs.len() > runs_before;
+            let status = match result {
+                Ok(_) if !recorded => "skipped".to_string(),
+                Ok(failed) if failed.is_empty() => {
+                    successful_runs += 1;
+                    "ok".to_string()
+                }
+                Ok(failed) => {
+                    let status = format!("{} job(s) failed", failed.len());
+                    failed_jobs.extend(failed);
+                    status
+                }
+                Err(e) if e.is::<OutOfTime>() => {
+                    warn!(
+                        "Stopping after {} of {} commits: {}, {}",
+                        done,
+                        total,
+                        e,
+                        self.resume_hint(&commit)
+                    );
+                    summary.push((commit.commit_id, "stopped, out of time".to_string()));
+                    break;
+                }
+                Err(e) if e.is::<BuildFailed>() => {
+                    warn!("{}", e);
+                    match self.config.settings.on_build_failure {
+                        OnBuildFailure::Skip => "skipped, build failed".to_string(),
+                        _ => "build failed".to_string(),
+                    }
+                }
+                Err(e) if collect_all => {
+                    error!("Benchmarks for commit {} failed: {}", commit.commit_id, e);
+                    failed_jobs.push(commit.commit_id.clone());
+                    format!("failed: {}", e)
+                }
+                Err(e) => return Err(e),
+            };
+            summary.push((commit.commit_id, status));
+
+            if let Some(clock) = &mut clock {
+                clock.check();
+            }
+
+            // Project the rest of the sweep from how long the commits so far took
+            let done = done + 1;
+            if done < total {
+                let per_commit = sweep_start.elapsed().as_secs_f64() / done as f64;
+                info!(
+                    "Benchmarked {} of {} commits, about {} remaining",
+                    done,
+                    total,
+                    util::format_duration(per_commit * (total - done) as f64)
+                );
+            }
+        }
+        // The sweep stopped before the commit the data dir was kept for
+        if std::mem::take(&mut self.reusing_datadir) {
+            self.cleanup_data_dir()?;
+        }
+
+        if collect_all {
+            println!("{:<42} status", "commit");
+            for (commit_id, status) in &summary {
+                println!("{:<42} {}", commit_id, status);
+            }
+        }
+        Ok(failed_jobs)
+    }
+
+    /// Clone or fetch the source repository if `update` is set, and load what the run needs from
+    /// its history.
+    fn prepare_source(&mut self, update: bool) -> Result<()> {
+        let settings = &self.config.settings;
+        if update && (self.clone || settings.auto_clone) && !util::is_git_repo(self.src_dir) {
+            let Some(repo_url) = &settings.repo_url else {
+                bail!(
+                    "{} is not a git repository and no repo_url is configured to clone",
+                    self.src_dir.display()
+                );
+            };
+            util::clone_repo(repo_url, self.src_dir)?;
+        }
+
+        let src_dir_path = util::check_source_file(self.src_dir).unwrap_or_else(|e| {
+            error!("Error checking for source code: {}", e);
+            std::process::exit(exitcode::NOINPUT);
+        });
+
+        if !update {
+            info!(
+                "Not updating {}, using refs as they are",
+                src_dir_path.display()
+            );
+        } else if let Err(e) = util::fetch_repo(src_dir_path, self.config.settings.git_retries) {
+            error!("Error updating repo: {}", e);
+            std::process::exit(exitcode::SOFTWARE);
+        }
+
+        if let Some(depth) = self.commit_depth.filter(|_| self.master_override.is_none()) {
+            let history = MasterHistory::load(src_dir_path, depth).map_err(|e| {
+                anyhow!(
+                    "Failed to read origin/master history for --commit-depth: {}",
+                    e
+                )
+            })?;
+            info!(
+                "Checking commits against the last {} commits of origin/master ({})",
+                depth, history.tip
+            );
+            self.master_history = Some(history);
+        }
+
+        // Fetching can move refs, only commits looked up by hash during this run stay cached
+        self.commit_cache.get_mut().clear();
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        // Explaining is a debugging aid, so it neither waits for a running benchmark's lock nor
+        // clones or fetches
+        if self.explain {
+            if !matches!(self.options, BenchOptions::Release(_)) {
+                self.prepare_source(false)?;
+            }
+            return self.explain();
+        }
+        // A budget too long to be represented can never run out
+        self.deadline = self
+            .max_runtime
+            .and_then(|budget| Instant::now().checked_add(budget));
+        if self.db.is_append_only() {
+            if self.config.jobs.cleanup {
+                bail!("database is in append-only mode, which doesn't allow cleanup = true");
+            }
+            if self.config.settings.on_build_failure != OnBuildFailure::Abort {
+                bail!(
+                    "database is in append-only mode, which doesn't allow on_build_failure to \
+                    delete results"
+                );
+            }
+        }
+        let data_dir = self
+            .config
+            .settings
+            .bitcoin_data_dir
+            .clone()
+            .context("bitcoin_data_dir is not set")?;
+        // The data dir of remote jobs is on the remote host, out of reach of the lock
+        let _lock = match self.config.settings.remote_host {
+            Some(_) => None,
+            None => Some(DataDirLock::acquire(&data_dir)?),
+        };
+
+        // Releases are benchmarked from their tarball, without a source checkout
+        if !matches!(self.options, BenchOptions::Release(_)) {
+            self.prepare_source(true)?;
+        }
+        let settings = &self.config.settings;
+        if let Some((host, src_dir)) = settings
+            .remote_host
+            .as_ref()
+            .zip(settings.remote_src_dir.as_ref())
+        {
+            let remote = Remote::connect(host, src_dir)?;
+            self.config.substitute_cores(remote.num_cores);
+            self.remote = Some(remote);
+        }
+        self.binary_versions = match &self.remote {
+            Some(remote) => remote.binary_versions(&self.config.settings.binaries),
+            None => util::get_binary_versions(self.config),
+        };
+        if self.use_isolated_cpus && self.remote.is_some() {
+            warn!("Isolated CPUs aren't used on a remote_host, running jobs unpinned");
+        } else if self.use_isolated_cpus {
+            match util::get_isolated_cpus() {
+                Ok(cpus) => {
+                    let (cpus, unpinnable): (Vec<_>, Vec<_>) =
+                        cpus.into_iter().partition(|cpu| util::fits_cpu_set(*cpu));
+                    if !unpinnable.is_empty() {
+                        warn!(
+                            "Skipping isolated CPUs {}, only CPUs below {} can be pinned to",
+                            util::format_cpu_list(&unpinnable),
+                            libc::CPU_SETSIZE
+                        );
+                    }
+                    if cpus.is_empty() {
+                        warn!("No CPUs are isolated with isolcpus, running jobs unpinned");
+                    } else {
+                        info!(
+                            "Pinning jobs to isolated CPUs {}",
+                            util::format_cpu_list(&cpus)
+                        );
+                        self.pinned_cpus = cpus;
+                    }
+                }
+                Err(e) => warn!(
+                    "Could not detect isolated CPUs, running jobs unpinned: {}",
+                    e
+                ),
+            }
+        }
+
+        // Every run of this invocation is recorded with the same run date
+        let run_date = chrono::Utc::now().timestamp();
+        let mut failed_jobs = Vec::new();
+        match self.bench_type {
+            BenchType::Single => {
+                let (commit_date, commit_id) = self.setup(run_date)?;
+                failed_jobs.extend(self.run_benchmarks(run_date, &commit_id, commit_date, None)?);
+                if self.config.jobs.cleanup {
+                    self.cleanup_data_dir()?;
+                }
+            }
+            BenchType::Multi => {
+                let options = match &self.options {
+                    BenchOptions::Multi(multi) => multi,
+                    _ => bail!("Invalid options for Multi bench type"),
+                };
+                let (collect_all, limit_runs) = (options.collect_all, options.limit_runs);
+
+                let dates = multi_dates(options)?;
+                check_sweep(&self.config.settings, options, &dates)?;
+
+                let mut commits = Vec::new();
+                for date in dates {
+                    let (commit_date, commit_id) = self.setup(date)?;
+                    commits.push(CommitToBench {
+                        commit_id,
+                        commit_date,
+                        sweep_date: Some(date),
+                        tag: None,
+                    });
+                }
+                failed_jobs.extend(self.run_sweep(run_date, commits, collect_all, limit_runs)?);
+            }
+            BenchType::Commits => {
+                let options = match &self.options {
+                    BenchOptions::Commits(range) => range,
+                    _ => bail!("Invalid options for Commits bench type"),
+                };
+                let (collect_all, limit_runs) = (options.collect_all, options.limit_runs);
+
+                let range =
+                    util::get_commits_in_range(self.src_dir, &options.range, options.order)?;
+                info!(
+                    "Found {} commits in {}, benchmarking every {} in {} order",
+                    range.len(),
+                    options.range,
+                    options.every,
+                    options.order.map_or("first-parent", |order| order.as_str())
+                );
+                let mut commits = Vec::new();
+                for commit_id in range.into_iter().step_by(options.every) {
+                    let commit_date = self.commit_metadata(&commit_id)?.date;
+                    commits.push(CommitToBench {
+                        commit_id,
+                        commit_date,
+                        sweep_date: None,
+                        tag: None,
+                    });
+                }
+                failed_jobs.extend(self.run_sweep(run_date, commits, collect_all, limit_runs)?);
+            }
+            BenchType::Tags => {
+                let options = match &self.options {
+                    BenchOptions::Tags(tags) => tags,
+                    _ => bail!("Invalid options for Tags bench type"),
+                };
+                let (collect_all, limit_runs) = (options.collect_all, options.limit_runs);
+
+                let tags = util::get_tags(self.src_dir, &options.pattern)?;
+                if tags.is_empty() {
+                    bail!("No tags match '{}'", options.pattern);
+                }
+                info!("Found {} tags matching {}", tags.len(), options.pattern);
+                let mut commits = Vec::new();
+                for (tag, commit_id) in tags {
+                    let commit_date = self.commit_metadata(&commit_id)?.date;
+                    commits.push(CommitToBench {
+                        commit_id,
+                        commit_date,
+                        sweep_date: None,
+                        tag: Some(tag),
+                    });
+                }
+                failed_jobs.extend(self.run_sweep(run_date, commits, collect_all, limit_runs)?);
+            }
+            BenchType::Release => {
+                let release = match &self.options {
+                    BenchOptions::Release(release) => release.clone(),
+                    _ => bail!("Invalid options for Release bench type"),
+                };
+                failed_jobs.extend(self.run_release(run_date, &release)?);
+                if self.config.jobs.cleanup {
+                    self.cleanup_data_dir()?;
+                }
+            }
+        }
+
+        if !failed_jobs.is_empty() {
+            bail!(
+                "{} job(s) or commit(s) failed: {}",
+                failed_jobs.len(),
+                failed_jobs.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DbDurability;
+    use crate::test_util::{commit_at, fixture_repo, git, DAY, FIRST_COMMIT_DATE};
+    use tempdir::TempDir;
+
+    #[test]
+    fn failed_executions_report_their_exit_code() {
+        let execution = |status| Execution {
+            status: ExitStatus::from_raw(status),
+            failed_criterion: None,
+            result: None,
+            report: None,
+            memory_timeline: None,
+        };
+        // Raw wait statuses: exited with 3, and killed by SIGKILL
+        assert_eq!(execution(3 << 8).exit_code(), 3);
+        assert_eq!(execution(9).exit_code(), 137);
+        assert!(!execution(9).succeeded());
+        assert_eq!(execution(0).exit_code(), 0);
+    }
+
+    #[test]
+    fn caches_commits_looked_up_by_short_hash_or_ref() {
+        let (repo, commits) = fixture_repo();
+        let bench_dir = TempDir::new("bench-data").unwrap();
+        let mut config: Config = toml::from_str(
+            r#"
+            [settings]
+            binaries = []
+
+            [jobs]
+            jobs = []
+            cleanup = false
+            "#,
+        )
+        .unwrap();
+        let db = Database::create_or_load(
+            &bench_dir.path().to_string_lossy(),
+            "db.sqlite",
+            DbDurability::Normal,
+        )
+        .unwrap();
+        let src_dir = repo.path().to_path_buf();
+        let options = BenchOptions::Single(Single {
+            commit: commits[2].clone(),
+        });
+        let bencher = Bencher::new(&mut config, &db, &src_dir, BenchType::Single, options).unwrap();
+
+        assert_eq!(
+            bencher.commit_metadata(&commits[2]).unwrap().hash,
+            commits[2]
+        );
+        // Mark the cached entry, which git would never return
+        bencher
+            .commit_cache
+            .borrow_mut()
+            .get_mut(&commits[2])
+            .unwrap()
+            .author = "cached".to_string();
+        for name in [&commits[2][..7], "HEAD"] {
+            assert_eq!(bencher.commit_metadata(name).unwrap().author, "cached");
+        }
+        assert_eq!(bencher.commit_cache.borrow().len(), 1);
+    }
+
+    #[test]
+    fn src_dir_survives_multi_iterations() {
+        let (repo, commits) = fixture_repo();
+        let bench_dir = TempDir::new("bench-data").unwrap();
+        let mut config: Config = toml::from_str(&format!(
+            r#"
+            [settings]
+            binaries = []
+
+            [jobs]
+            jobs = [{{ name = "noop", command = "true", bench = false, outfile = "{}/out" }}]
+            cleanup = false
+            "#,
+            bench_dir.path().display()
+        ))
+        .unwrap();
+        config.settings.bitcoin_data_dir = Some(bench_dir.path().join("bitcoin"));
+        let db = Database::create_or_load(
+            &bench_dir.path().to_string_lossy(),
+            "db.sqlite",
+            DbDurability::Normal,
+        )
+        .unwrap();
+
+        // Benchmarks change into src_dir, which Cli::init makes absolute so the second iteration
+        // doesn't resolve it again from inside itself
+        let src_dir = repo.path().to_path_buf();
+
+        let (start, end) = ("2024-01-05".to_string(), "2024-01-06".to_string());
+        let options = BenchOptions::Multi(Multi {
+            start: &start,
+            end: &end,
+            date_format: None,
+            collect_all: false,
+            limit_runs: None,
+            confirmed: false,
+        });
+        let original_dir = std::env::current_dir().unwrap();
+        let result = Bencher::new(&mut config, &db, &src_dir, BenchType::Multi, options)
+            .and_then(|mut bencher| bencher.run());
+        // The run leaves the process in src_dir, which is deleted along with `repo`
+        std::env::set_current_dir(original_dir).unwrap();
+
+        result.unwrap();
+        let runs = db.get_runs().unwrap();
+        assert_eq!(runs.len(), 2);
+        assert!(runs.iter().all(|(run, _)| run.commit_id == commits[1]));
+    }
+
+    #[test]
+    fn daily_sweep_steps_through_utc_midnights() {
+        let (start, end) = ("2024-03-30".to_string(), "2024-04-01".to_string());
+        let multi = Multi {
+            start: &start,
+            end: &end,
+            date_format: None,
+            collect_all: false,
+            limit_runs: None,
+            confirmed: false,
+        };
+        let dates: Vec<String> = multi_dates(&multi)
+            .unwrap()
+            .into_iter()
+            .map(util::unix_timestamp_to_iso)
+            .collect();
+        assert_eq!(
+            dates,
+            [
+                "2024-03-30T00:00:00Z",
+                "2024-03-31T00:00:00Z",
+                "2024-04-01T00:00:00Z"
+            ]
+        );
+    }
+
+    #[test]
+    fn steps_through_whole_utc_days() {
+        // Noon on 2024-03-30 to 06:00 on 2024-04-01, either side of the EU DST change
+        let dates: Vec<String> = utc_midnights(1711800000, 1711951200)
+            .unwrap()
+            .into_iter()
+            .map(util::unix_timestamp_to_iso)
+            .collect();
+        assert_eq!(
+            dates,
+            [
+                "2024-03-30T00:00:00Z",
+                "2024-03-31T00:00:00Z",
+                "2024-04-01T00:00:00Z"
+            ]
+        );
+    }
+
+    #[test]
+    fn guards_daily_sweep_range() {
+        let settings: Settings = toml::from_str(
+            r#"
+            binaries = []
+            sweep_min_date = "2024-01-01"
+            sweep_max_date = "2024-12-31"
+            sweep_max_runs = 2
+            "#,
+        )
+        .unwrap();
+        let check = |start: &str, end: &str, limit_runs, confirmed| {
+            let (start, end) = (start.to_string(), end.to_string());
+            let multi = Multi {
+                start: &start,
+                end: &end,
+                date_format: None,
+                collect_all: false,
+                limit_runs,
+                confirmed,
+            };
+            check_sweep(&settings, &multi, &multi_dates(&multi).unwrap())
+        };
+
+        assert!(check("2023-12-31", "2024-01-01", None, true).is_err());
+        assert!(check("2024-12-31", "2025-01-01", None, true).is_err());
+        assert!(check("2024-01-01", "2024-01-02", None, false).is_ok());
+        // Limited to two runs, the long range doesn't need confirming
+        assert!(check("2024-01-01", "2024-12-31", Some(2), false).is_ok());
+        assert!(check("2024-01-01", "2024-12-31", None, true).is_ok());
+    }
+
+    #[test]
+    fn master_history_covers_only_its_depth() {
+        let (origin, commits) = fixture_repo();
+        let clone = TempDir::new("fixture-clone").unwrap();
+        git(
+            clone.path(),
+            &["clone", "-q", origin.path().to_str().unwrap(), "."],
+        );
+        git(clone.path(), &["checkout", "-q", "-b", "branch"]);
+        let branch_date = FIRST_COMMIT_DATE + 9 * DAY;
+        let branch_commit = commit_at(clone.path(), branch_date);
+
+        let history = MasterHistory::load(clone.path(), 2).unwrap();
+        assert_eq!(history.tip, commits[2]);
+        assert_eq!(
+            history.contains(&commits[2], FIRST_COMMIT_DATE + 6 * DAY),
+            Some(true)
+        );
+        assert_eq!(
+            history.contains(&commits[1], FIRST_COMMIT_DATE + 3 * DAY),
+            Some(true)
+        );
+        assert_eq!(history.contains(&commits[0], FIRST_COMMIT_DATE), None);
+        assert_eq!(history.contains(&branch_commit, branch_date), Some(false));
+
+        let full = MasterHistory::load(clone.path(), 10).unwrap();
+        assert_eq!(full.contains(&commits[0], FIRST_COMMIT_DATE), Some(true));
     }
 }
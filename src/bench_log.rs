@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use log::debug;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// Matches bitcoind `-debug=bench` timing lines such as
+/// `    - Connect total: 1.23ms [4.56s (7.89ms/blk)]`, capturing the phase name and duration.
+const BENCH_LINE: &str = r"-\s+(?P<phase>[A-Za-z][^:\[\]]*?):\s+(?P<ms>[0-9]+(?:\.[0-9]+)?)ms";
+
+/// Sum the time spent in each bench phase matching one of `phase_patterns`, in milliseconds.
+/// Metrics are named `bench:<phase>`.
+pub fn parse_bench_log(path: &str, phase_patterns: &[String]) -> Result<Vec<(String, f64)>> {
+    let line_re = Regex::new(BENCH_LINE)?;
+    let phase_res = phase_patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).with_context(|| format!("Invalid bench phase pattern {}", pattern))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let file = File::open(path).with_context(|| format!("Failed to open bench log {}", path))?;
+    let mut totals: BTreeMap<String, f64> = BTreeMap::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.with_context(|| "Failed to read line from bench log")?;
+        let Some(captures) = line_re.captures(&line) else {
+            continue;
+        };
+        let phase = captures["phase"].trim();
+        if !phase_res.iter().any(|re| re.is_match(phase)) {
+            continue;
+        }
+        let ms: f64 = captures["ms"].parse()?;
+        *totals.entry(format!("bench:{}", phase)).or_default() += ms;
+    }
+    debug!("Parsed {} bench phases from {}", totals.len(), path);
+
+    Ok(totals.into_iter().collect())
+}
+
+/// Read the metrics a job wrote to `path`, either a flat JSON object of numbers for `.json` files
+/// or `name=value` lines otherwise. Blank lines and lines starting with `#` are skipped.
+pub fn parse_metrics_file(path: &str) -> Result<Vec<(String, f64)>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read metrics file {}", path))?;
+    if path.ends_with(".json") {
+        let metrics: BTreeMap<String, f64> = serde_json::from_str(&text)
+            .with_context(|| format!("Metrics file {} must be a JSON object of numbers", path))?;
+        return Ok(metrics.into_iter().collect());
+    }
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| {
+            let (name, value) = line
+                .split_once('=')
+                .with_context(|| format!("Expected name=value on line {} of {}", i + 1, path))?;
+            let value = value.trim().parse().with_context(|| {
+                format!("Invalid value '{}' on line {} of {}", value, i + 1, path)
+            })?;
+            Ok((name.trim().to_string(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn parses_metrics_files() {
+        let dir = TempDir::new("metrics").unwrap();
+        let json = dir.path().join("metrics.json");
+        std::fs::write(&json, r#"{"utxos": 1200, "cache_hit_rate": 0.5}"#).unwrap();
+        assert_eq!(
+            parse_metrics_file(json.to_str().unwrap()).unwrap(),
+            vec![
+                ("cache_hit_rate".to_string(), 0.5),
+                ("utxos".to_string(), 1200.0)
+            ]
+        );
+
+        let text = dir.path().join("metrics.txt");
+        std::fs::write(&text, "# totals\nutxos = 1200\n\nbad line\n").unwrap();
+        let err = parse_metrics_file(text.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("line 4"));
+    }
+
+    #[test]
+    fn sums_matching_phases() {
+        let path = format!(
+            "{}/tests/fixtures/debug-bench.log",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let metrics = parse_bench_log(
+            &path,
+            &["^Connect total$".to_string(), "^Flush".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            metrics,
+            vec![
+                ("bench:Connect total".to_string(), 3.5),
+                ("bench:Flush".to_string(), 0.25),
+            ]
+        );
+    }
+}
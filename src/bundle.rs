@@ -0,0 +1,139 @@
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::config::Config;
+use crate::database::Database;
+use crate::manifest::MachineInfo;
+use crate::util;
+
+/// Logs larger than this are cut down to their last bytes, which hold the errors of a failed job.
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+
+/// Everything recorded about a single run, for sharing an anomalous run without the database.
+#[derive(Serialize, Debug)]
+pub struct Bundle {
+    pub tool_version: &'static str,
+    pub created: String,
+    /// The machine which created the bundle, the run row records the machine which ran it
+    pub machine: MachineInfo,
+    /// SHA256 of the config file the run used, `None` for runs recorded before it was stored
+    pub config_sha256: Option<String>,
+    pub run: Map<String, Value>,
+    pub binaries: Vec<Map<String, Value>>,
+    pub jobs: Vec<BundleJob>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BundleJob {
+    #[serde(flatten)]
+    pub row: Map<String, Value>,
+    pub metrics: Vec<Map<String, Value>>,
+    pub deltas: Vec<Map<String, Value>>,
+    /// Environment the job ran with, sensitive values redacted unless `record_sensitive_env`
+    pub env: Vec<Map<String, Value>>,
+    /// Output, error and time report files of the job which still exist
+    pub files: Vec<BundleFile>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BundleFile {
+    pub path: String,
+    pub contents: String,
+    /// Whether only the end of the file is included
+    pub truncated: bool,
+}
+
+fn read_file(path: &str) -> Result<Option<BundleFile>> {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return Ok(None);
+    };
+    let len = file.metadata()?.len();
+    let truncated = len > MAX_LOG_BYTES;
+    if truncated {
+        file.seek(SeekFrom::Start(len - MAX_LOG_BYTES))?;
+    }
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)
+        .with_context(|| format!("Failed to read {}", path))?;
+    Ok(Some(BundleFile {
+        path: path.to_string(),
+        contents: String::from_utf8_lossy(&contents).into_owned(),
+        truncated,
+    }))
+}
+
+/// Paths of the files a job writes during a run, as in `Bencher::run_single_job`.
+fn job_files(config: &Config, run_id: i64, job_name: &str) -> Vec<String> {
+    let mut paths = vec![
+        format!("/tmp/{}-{}-output.log", run_id, job_name),
+        format!("/tmp/{}-{}-error.log", run_id, job_name),
+    ];
+    if let Some(outfile) = config
+        .jobs
+        .jobs
+        .iter()
+        .find(|job| job.name == job_name)
+        .and_then(|job| job.outfile.as_ref())
+    {
+        paths.extend(
+            outfile
+                .paths()
+                .iter()
+                .map(|path| path.replace("{run_id}", &run_id.to_string())),
+        );
+    }
+    paths
+}
+
+pub fn create_bundle(db: &Database, config: &Config, run_id: i64) -> Result<Bundle> {
+    let Some(run) = db.get_rows_json("runs", "run_id", run_id)?.pop() else {
+        bail!("No run found with id {}", run_id);
+    };
+    let mut jobs = Vec::new();
+    for row in db.get_rows_json("jobs", "run_id", run_id)? {
+        let job_id = row["job_id"].as_i64().context("Job row has no id")?;
+        let job_name = row["job_name"].as_str().unwrap_or_default().to_string();
+        let files = job_files(config, run_id, &job_name)
+            .iter()
+            .map(|path| read_file(path))
+            .filter_map(Result::transpose)
+            .collect::<Result<_>>()?;
+        jobs.push(BundleJob {
+            metrics: db.get_rows_json("job_metrics", "job_id", job_id)?,
+            deltas: db.get_rows_json("job_deltas", "job_id", job_id)?,
+            env: db.get_rows_json("job_env", "job_id", job_id)?,
+            files,
+            row,
+        });
+    }
+
+    Ok(Bundle {
+        tool_version: env!("CARGO_PKG_VERSION"),
+        created: chrono::Utc::now().to_rfc3339(),
+        machine: MachineInfo::current(),
+        config_sha256: run["config_hash"].as_str().map(String::from),
+        binaries: db.get_rows_json("run_binaries", "run_id", run_id)?,
+        run,
+        jobs,
+    })
+}
+
+pub fn write_bundle(db: &Database, config: &Config, run_id: i64, path: &Path) -> Result<()> {
+    let bundle = create_bundle(db, config, run_id)?;
+    let contents = serde_json::to_string_pretty(&bundle)?;
+    let mut out = util::open_output(Some(path))?;
+    writeln!(out, "{}", contents)
+        .and_then(|_| out.flush())
+        .with_context(|| format!("Failed to write bundle to {}", path.display()))?;
+    info!(
+        "Wrote run {} with {} jobs to {}",
+        run_id,
+        bundle.jobs.len(),
+        path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,308 @@
+use anyhow::{anyhow, bail, Result};
+use clap::ValueEnum;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::database::{Database, Job, Run};
+use crate::result::{JobStats, Metric, TimeResult};
+
+/// Maximum allowed increase, in percent, of a metric relative to the baseline.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Threshold {
+    pub metric: Metric,
+    pub percent: f64,
+}
+
+impl TryFrom<String> for Threshold {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        parse_threshold(&s)
+    }
+}
+
+/// Parse a `metric=percent` threshold, accepting either `max_rss` or `max-rss` style names.
+pub fn parse_threshold(s: &str) -> Result<Threshold> {
+    let (metric, percent) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Threshold must be in the form metric=percent, got '{}'", s))?;
+    let metric = Metric::from_str(&metric.trim().replace('_', "-"), true)
+        .map_err(|e| anyhow!("Invalid metric '{}': {}", metric, e))?;
+    let percent = percent
+        .trim()
+        .parse()
+        .map_err(|e| anyhow!("Invalid threshold percentage '{}': {}", percent, e))?;
+    Ok(Threshold { metric, percent })
+}
+
+/// A metric of a job which grew by more than its threshold against the baseline run.
+#[derive(Debug, Clone, Serialize)]
+pub struct Regression {
+    pub job: String,
+    pub metric: Metric,
+    pub baseline_commit: String,
+    pub baseline: f64,
+    pub value: f64,
+    pub percent: f64,
+    pub threshold: f64,
+}
+
+/// `jobs_with_runs` with the samples of each job recorded by `record_individual_samples` merged
+/// into one job holding their mean, as graphs plot them, so each run of a job is compared once.
+/// The merged job keeps the id of the first sample. Samples of a job must be consecutive.
+pub fn mean_per_run(jobs_with_runs: Vec<(Job, Run)>) -> Vec<(Job, Run)> {
+    let mut runs: Vec<(Job, Run, Vec<TimeResult>)> = Vec::new();
+    for (job, run) in jobs_with_runs {
+        match runs.last_mut() {
+            Some((last, _, samples))
+                if last.run_id == job.run_id && last.result.command == job.result.command =>
+            {
+                samples.push(job.result)
+            }
+            _ => {
+                let samples = vec![job.result.clone()];
+                runs.push((job, run, samples));
+            }
+        }
+    }
+    runs.into_iter()
+        .map(|(mut job, run, samples)| {
+            if samples.len() > 1 {
+                job.result = TimeResult::mean(&samples).unwrap_or(job.result);
+                job.stats = JobStats::from_results(&samples);
+                job.sample_index = None;
+            }
+            (job, run)
+        })
+        .collect()
+}
+
+/// The most recent master run recorded before `candidate`, if any.
+pub fn fetch_baseline<'a>(
+    jobs_with_runs: &'a [(Job, Run)],
+    candidate: &(Job, Run),
+) -> Option<&'a (Job, Run)> {
+    jobs_with_runs
+        .iter()
+        .filter(|(job, run)| run.was_master && job.run_id < candidate.0.run_id)
+        .max_by_key(|(job, _)| job.run_id)
+}
+
+/// The most recent master run recorded before `candidate` on the same machine, the baseline
+/// `compare_with_previous` compares against.
+pub fn fetch_same_machine_baseline<'a>(
+    jobs_with_runs: &'a [(Job, Run)],
+    candidate: &(Job, Run),
+) -> Option<&'a (Job, Run)> {
+    jobs_with_runs
+        .iter()
+        .filter(|(job, run)| {
+            run.was_master
+                && job.run_id < candidate.0.run_id
+                && run.cpu_model == candidate.1.cpu_model
+                && run.num_cores == candidate.1.num_cores
+                && run.remote_host == candidate.1.remote_host
+        })
+        .max_by_key(|(job, _)| job.run_id)
+}
+
+/// Percentage change from `base` to `value`, 0 when `base` isn't positive.
+pub fn percent_change(base: f64, value: f64) -> f64 {
+    if base > 0.0 {
+        (value - base) / base * 100.0
+    } else {
+        0.0
+    }
+}
+
+/// Compare each job of a newly recorded master run against the previous master run of the same
+/// job on the same machine, warning about and returning metrics which grew by more than their
+/// threshold and optionally storing the changes.
+pub fn compare_with_previous(
+    db: &Database,
+    run_id: i64,
+    thresholds: &[Threshold],
+    store: bool,
+) -> Result<Vec<Regression>> {
+    let mut regressions = Vec::new();
+    let candidates = mean_per_run(db.get_jobs_for_run(run_id)?);
+    if candidates
+        .first()
+        .is_some_and(|(_, run)| run.datadir_reused)
+    {
+        info!(
+            "Run {} reused the data dir of the run before, not comparing its incremental results",
+            run_id
+        );
+        return Ok(regressions);
+    }
+    for candidate in candidates {
+        let job_name = &candidate.0.result.command;
+        let jobs_with_runs = mean_per_run(db.get_jobs_by_name(job_name)?);
+        let Some(baseline) = fetch_same_machine_baseline(&jobs_with_runs, &candidate) else {
+            info!("No previous master run of {} to compare against", job_name);
+            continue;
+        };
+
+        for threshold in thresholds {
+            let (Some(base), Some(value)) = (
+                threshold.metric.value(&baseline.0.result),
+                threshold.metric.value(&candidate.0.result),
+            ) else {
+                info!(
+                    "{} {} wasn't measured in both runs, not comparing it",
+                    job_name,
+                    threshold.metric.label()
+                );
+                continue;
+            };
+            let change = percent_change(base, value);
+            if change > threshold.percent {
+                warn!(
+                    "{} {} regressed by {:.2}% against {} (threshold {:.2}%)",
+                    job_name,
+                    threshold.metric.label(),
+                    change,
+                    baseline.1.commit_id,
+                    threshold.percent
+                );
+                regressions.push(Regression {
+                    job: job_name.clone(),
+                    metric: threshold.metric,
+                    baseline_commit: baseline.1.commit_id.clone(),
+                    baseline: base,
+                    value,
+                    percent: change,
+                    threshold: threshold.percent,
+                });
+            } else {
+                info!(
+                    "{} {} changed by {:+.2}% against {}",
+                    job_name,
+                    threshold.metric.label(),
+                    change,
+                    baseline.1.commit_id
+                );
+            }
+            if store {
+                db.record_job_delta(
+                    candidate.0.job_id,
+                    baseline.0.job_id,
+                    &threshold.metric.name(),
+                    change,
+                )?;
+            }
+        }
+    }
+    Ok(regressions)
+}
+
+/// Compare the latest result for `job_name` against its baseline, failing if any metric
+/// increased by more than its threshold.
+pub fn check_regressions(db: &Database, job_name: &str, thresholds: &[Threshold]) -> Result<()> {
+    let jobs_with_runs = mean_per_run(db.get_jobs_by_name(&job_name.to_string())?);
+    let candidate = jobs_with_runs
+        .iter()
+        .max_by_key(|(job, _)| job.run_id)
+        .ok_or_else(|| anyhow!("No successful {} jobs recorded", job_name))?;
+    let baseline = fetch_baseline(&jobs_with_runs, candidate).ok_or_else(|| {
+        anyhow!(
+            "No earlier master run of {} to compare commit {} against",
+            job_name,
+            candidate.1.commit_id
+        )
+    })?;
+    info!(
+        "Comparing {} at {} against baseline {}",
+        job_name, candidate.1.commit_id, baseline.1.commit_id
+    );
+
+    let mut regressions = Vec::new();
+    for threshold in thresholds {
+        let (Some(base), Some(value)) = (
+            threshold.metric.value(&baseline.0.result),
+            threshold.metric.value(&candidate.0.result),
+        ) else {
+            println!("{:<32} not measured in both runs", threshold.metric.label());
+            continue;
+        };
+        let change = percent_change(base, value);
+        let regressed = change > threshold.percent;
+        println!(
+            "{:<32} {:>14.2} -> {:>14.2} {:>+8.2}% (threshold {:.2}%) {}",
+            threshold.metric.label(),
+            base,
+            value,
+            change,
+            threshold.percent,
+            if regressed { "REGRESSED" } else { "ok" }
+        );
+        if regressed {
+            regressions.push(format!("{} by {:.2}%", threshold.metric.label(), change));
+        }
+    }
+
+    if !regressions.is_empty() {
+        bail!(
+            "{} regressed at {}: {}",
+            job_name,
+            candidate.1.commit_id,
+            regressions.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{job_with_run, DAY, FIRST_COMMIT_DATE};
+
+    #[test]
+    fn compares_the_mean_of_each_runs_samples() {
+        let sample = |run_id, job_id, sample_index, user_time| {
+            let (mut job, run) = job_with_run(run_id, FIRST_COMMIT_DATE + i64::from(run_id) * DAY);
+            job.job_id = job_id;
+            job.sample_index = Some(sample_index);
+            job.result.command = "IBD".to_string();
+            job.result.user_time = user_time;
+            (job, run)
+        };
+        let runs = mean_per_run(vec![
+            sample(1, 1, 0, 10.0),
+            sample(1, 2, 1, 12.0),
+            sample(2, 3, 0, 20.0),
+            sample(2, 4, 1, 24.0),
+            sample(2, 5, 2, 28.0),
+        ]);
+        let merged: Vec<(i64, f64, Option<u32>)> = runs
+            .iter()
+            .map(|(job, _)| (job.job_id, job.result.user_time, job.sample_index))
+            .collect();
+        assert_eq!(merged, [(1, 11.0, None), (3, 24.0, None)]);
+
+        let baseline = fetch_baseline(&runs, &runs[1]).unwrap();
+        assert_eq!(baseline.0.job_id, 1);
+    }
+
+    #[test]
+    fn compares_against_the_same_machine() {
+        let on = |run_id, cpu_model: &str| {
+            let (job, mut run) = job_with_run(run_id, FIRST_COMMIT_DATE + i64::from(run_id) * DAY);
+            run.cpu_model = Some(cpu_model.to_string());
+            run.num_cores = Some(8);
+            (job, run)
+        };
+        let runs = [on(1, "Ryzen 9"), on(2, "Xeon"), on(3, "Ryzen 9")];
+        assert_eq!(fetch_baseline(&runs, &runs[2]).unwrap().0.run_id, 2);
+        assert_eq!(
+            fetch_same_machine_baseline(&runs, &runs[2])
+                .unwrap()
+                .0
+                .run_id,
+            1
+        );
+        assert!(fetch_same_machine_baseline(&runs, &runs[1]).is_none());
+    }
+}
@@ -1,14 +1,67 @@
 use anyhow::{anyhow, Result};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use log::info;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tempdir::TempDir;
 
-fn get_default_data_dir() -> PathBuf {
-    let mut path = dirs::config_dir().expect("Could not get config dir");
-    path.pop();
-    path.push(".config/bench_bitcoin");
-    path
+use crate::check::{parse_threshold, Threshold};
+use crate::graph::{Aggregate, Deviation, GraphOptions, SecondaryMetric, Theme, XAxis};
+use crate::records::RecordFormat;
+use crate::report::{ExportFormat, OutputFormat};
+use crate::result::Metric;
+use crate::util::{parse_date, parse_duration, CommitOrder};
+
+const APP_DIR: &str = "bench_bitcoin";
+
+/// Where the data dir used to live, kept so existing databases continue to be found.
+fn legacy_data_dir(config_dir: &Path) -> Option<PathBuf> {
+    config_dir
+        .parent()
+        .map(|parent| parent.join(".config").join(APP_DIR))
+}
+
+/// The legacy data dir if it holds a `db_name` database, otherwise `<data_dir>/bench_bitcoin`.
+/// On Linux the legacy dir is also where `config.toml` lives, so only the database marks it as
+/// a data dir.
+fn resolve_data_dir(
+    data_dir: Option<PathBuf>,
+    config_dir: Option<PathBuf>,
+    db_name: &str,
+) -> Result<PathBuf> {
+    if let Some(legacy) = config_dir.as_deref().and_then(legacy_data_dir) {
+        if legacy.join(db_name).exists() {
+            return Ok(legacy);
+        }
+    }
+    data_dir
+        .map(|data_dir| data_dir.join(APP_DIR))
+        .ok_or_else(|| anyhow!("Could not find the user data directory, set --bench-data-dir"))
+}
+
+/// `config.toml` in the working directory if present, otherwise
+/// `<config_dir>/bench_bitcoin/config.toml`.
+fn resolve_config_file(current_dir: &Path, config_dir: Option<PathBuf>) -> PathBuf {
+    let local = current_dir.join("config.toml");
+    match config_dir {
+        Some(config_dir) if !local.exists() => config_dir.join(APP_DIR).join("config.toml"),
+        _ => local,
+    }
+}
+
+/// Resolve `path` against the current directory, so it stays valid after benchmarks change into
+/// the source directory.
+pub fn make_absolute(path: &mut PathBuf) -> Result<()> {
+    let current_dir = std::env::current_dir()
+        .map_err(|e| anyhow!("Failed to get current working directory: {}", e))?;
+    resolve_against(&current_dir, path);
+    Ok(())
+}
+
+fn resolve_against(current_dir: &Path, path: &mut PathBuf) {
+    if path.is_relative() {
+        *path = current_dir.join(&*path);
+    }
 }
 
 fn get_random_bitcoin_dir() -> PathBuf {
@@ -26,14 +79,35 @@ pub struct Cli {
     #[arg(long)]
     pub config_file: Option<PathBuf>,
 
-    /// Path to the bitcoin-bench database directory.
-    #[arg(long, env = "BENCH_BITCOIN_DIR", default_value=get_default_data_dir().into_os_string())]
+    /// Path to the bitcoin-bench database directory, by default `bench_bitcoin` in the user data
+    /// directory.
+    #[arg(
+        long = "bench-data-dir",
+        env = "BENCH_BITCOIN_DIR",
+        value_name = "BENCH_DATA_DIR"
+    )]
+    bench_data_dir_arg: Option<PathBuf>,
+
+    /// `--bench-data-dir`, or the default resolved by `init`
+    #[arg(skip)]
     pub bench_data_dir: PathBuf,
 
     /// The bitcoin-bench database name.
     #[arg(long, default_value = "db.sqlite")]
     pub bench_db_name: String,
 
+    /// Overlay the settings from `[profiles.<name>]` in the config file onto `[settings]`.
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Only fail on missing binaries which a job uses, warning about the others
+    #[arg(long)]
+    pub allow_missing_binaries: bool,
+
+    /// Refuse to delete recorded results or clean up the data dir, as the `append_only` setting
+    #[arg(long)]
+    pub append_only: bool,
+
     /// Data dir to use for bitcoin core during tests.
     #[arg(long, default_value=get_random_bitcoin_dir().into_os_string())]
     pub bitcoin_data_dir: Option<PathBuf>,
@@ -52,14 +126,252 @@ pub enum Commands {
     /// Graph-related commands
     #[command(subcommand)]
     Graph(GraphCommands),
+
+    /// List recorded runs
+    List {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+
+        /// Show plain numbers instead of human readable units
+        #[arg(long)]
+        raw: bool,
+
+        /// Only include runs from this long ago onwards, e.g. `7d` or `24h`
+        #[arg(long, value_parser = parse_duration)]
+        since: Option<Duration>,
+    },
+
+    /// Summarise recorded results per job
+    Summary {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+
+        /// Show plain numbers instead of human readable units
+        #[arg(long)]
+        raw: bool,
+
+        /// Only include runs from this long ago onwards, e.g. `7d` or `24h`
+        #[arg(long, value_parser = parse_duration)]
+        since: Option<Duration>,
+    },
+
+    /// Check the latest result for a job against the previous master run for regressions
+    Check {
+        /// Name of the job to check
+        job_name: String,
+
+        /// Maximum allowed increase for a metric in percent, e.g. `user_time=3`. May be repeated.
+        #[arg(long = "threshold", value_parser = parse_threshold, default_value = "user_time=5")]
+        thresholds: Vec<Threshold>,
+    },
+
+    /// Report how noisy each job's results are across recent master runs
+    Stability {
+        /// Name of the job, all jobs if omitted
+        job_name: Option<String>,
+
+        /// Metric to measure
+        #[arg(long, value_enum, default_value_t = Metric::UserTime)]
+        metric: Metric,
+
+        /// Number of most recent master runs to consider
+        #[arg(long, default_value_t = 20)]
+        recent: usize,
+
+        /// Coefficient of variation, in percent, above which a job is flagged as unreliable
+        #[arg(long, default_value_t = 5.0)]
+        threshold: f64,
+    },
+
+    /// Report the Pearson correlation between two metrics across a job's runs, e.g. whether a
+    /// user time regression moved together with max RSS or page faults
+    Correlate {
+        /// Name of the job
+        job_name: String,
+
+        /// First metric, on the x-axis of the plot
+        #[arg(value_enum)]
+        metric_a: Metric,
+
+        /// Second metric, on the y-axis of the plot
+        #[arg(value_enum)]
+        metric_b: Metric,
+
+        /// Also scatter-plot the two metrics into the bench data dir
+        #[arg(long)]
+        plot: bool,
+    },
+
+    /// List the commits between two runs as suspects for a change in a job's results
+    Suspects {
+        /// Path to bitcoin source code directory
+        src_dir: PathBuf,
+
+        /// Name of the job
+        job_name: String,
+
+        /// Earlier run id
+        from_run: i64,
+
+        /// Later run id
+        to_run: i64,
+    },
+
+    /// Compare a job's results for a single commit across the machines which ran it
+    CompareMachines {
+        /// git commit hash (or unique prefix)
+        commit: String,
+
+        /// Name of the job to compare
+        job_name: String,
+
+        /// Metric to compare
+        #[arg(long, value_enum, default_value_t = Metric::UserTime)]
+        metric: Metric,
+    },
+
+    /// Export the latest result of each job compared against a baseline, e.g. for a PR comment
+    Export {
+        /// Output format, defaults to md, or json with `--raw`
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+
+        /// Commit (or unique prefix) to compare against, defaults to the previous master run
+        #[arg(long)]
+        base: Option<String>,
+
+        /// Commit (or unique prefix) to report, defaults to the latest run
+        #[arg(long)]
+        head: Option<String>,
+
+        /// Metrics to include. May be repeated.
+        #[arg(long = "metric", value_enum, default_values_t = [Metric::UserTime, Metric::MaxRss])]
+        metrics: Vec<Metric>,
+
+        /// Export every recorded job as JSON or CSV records which `import` can read
+        #[arg(long, conflicts_with_all = ["base", "head", "metrics"])]
+        raw: bool,
+
+        /// File to write the export to, stdout if omitted or `-`
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+
+        /// Only include runs from this long ago onwards, e.g. `7d` or `24h`
+        #[arg(long, value_parser = parse_duration)]
+        since: Option<Duration>,
+    },
+
+    /// Import job records written by `export --raw` into this database
+    Import {
+        /// Path of the exported records
+        path: PathBuf,
+
+        /// Format of the exported records
+        #[arg(long, value_enum, default_value_t = RecordFormat::Json)]
+        format: RecordFormat,
+    },
+
+    /// Write everything recorded about a run, including its jobs' logs, to a single JSON file
+    Bundle {
+        /// Id of the run
+        run_id: i64,
+
+        /// Path of the bundle to write, `-` for stdout
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+
+    /// Collapse the jobs of old runs into daily means, minimums and maximums to shrink the
+    /// database. Graphs still plot the archived days
+    Archive {
+        /// Archive runs before this date (YYYY-MM-DD), defaults to `archive_after_days` ago
+        #[arg(long, value_parser = parse_date)]
+        before: Option<i64>,
+    },
+
+    /// Parse the stored time output of every job again and report any field which differs from
+    /// the job's stored results, exiting non-zero if one does
+    Verify,
+
+    /// Add a note to a recorded run, e.g. "machine was thermal throttling"
+    Annotate {
+        /// Id of the run
+        run_id: i64,
+
+        /// Text of the note, kept alongside any earlier notes with when it was added
+        note: String,
+    },
+
+    /// Check GNU time, result parsing and the database work by benchmarking `sleep 1`
+    SelfTest {
+        /// Also graph the result, as `__selftest__.png` in the bench data dir
+        #[arg(long)]
+        graph: bool,
+    },
 }
 
 #[derive(Debug, Subcommand)]
 pub enum BenchCommands {
-    /// Run benchmarks
+    /// Run benchmarks. Every run recorded by one invocation shares its start time as the run date
     Run {
         #[command(subcommand)]
         run_command: RunCommands,
+
+        /// Write a JSON reproducibility manifest for the run to this path
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+
+        /// Archive the bitcoin data dir into this directory after each run, before cleanup
+        #[arg(long)]
+        keep_artifacts: Option<PathBuf>,
+
+        /// Compress archived data dirs with zstd
+        #[arg(long, requires = "keep_artifacts")]
+        compress_artifacts: bool,
+
+        /// Skip commits whose tree is identical to the last recorded run's
+        #[arg(long)]
+        only_if_changed: bool,
+
+        /// Benchmark even if the tree is unchanged, overriding --only-if-changed
+        #[arg(long)]
+        force: bool,
+
+        /// Clone `repo_url` into the source directory if it isn't a git repository yet
+        #[arg(long)]
+        clone: bool,
+
+        /// Pin jobs to the CPUs isolated with the `isolcpus` kernel parameter
+        #[arg(long)]
+        use_isolated_cpus: bool,
+
+        /// Sample the RSS of every bench job as `profile_memory` does, not only jobs which set it
+        #[arg(long)]
+        profile_memory: bool,
+
+        /// Record the runs as master runs, overriding the default
+        #[arg(long, conflicts_with = "mark_non_master")]
+        mark_master: bool,
+
+        /// Record the runs as non-master runs, e.g. for a branch being compared against master
+        #[arg(long)]
+        mark_non_master: bool,
+
+        /// Record runs as master only if their commit is among this many of the most recent commits
+        /// of origin/master. Older commits are recorded as master with a warning
+        #[arg(long, value_name = "N", conflicts_with_all = ["mark_master", "mark_non_master"])]
+        commit_depth: Option<usize>,
+
+        /// Stop gracefully between commits or jobs once the run has taken this long, e.g. `5h30m`
+        #[arg(long, value_parser = parse_duration)]
+        max_runtime: Option<Duration>,
+
+        /// Print how each date or commit resolves to a commit and whether it would be recorded as
+        /// master, without benchmarking, locking the data dir or fetching
+        #[arg(long)]
+        explain: bool,
     },
 }
 
@@ -79,28 +391,225 @@ pub enum RunCommands {
         /// Path to bitcoin source code directory
         src_dir: PathBuf,
 
-        /// Start date for daily benchmarks in YYYY-MM-DD format
+        /// Start date for daily benchmarks in YYYY-MM-DD format, other common formats such as
+        /// YYYY/MM/DD or '1 Feb 2024' are also accepted
         start: String,
 
-        /// End date for daily benchmarks in YYYY-MM-DD format
+        /// End date for daily benchmarks, in the same format as the start date
         end: String,
+
+        /// chrono format of the start and end dates, e.g. `%d.%m.%Y`, needed for dates such as
+        /// 01-02-2024 which could be day or month first
+        #[arg(long)]
+        date_format: Option<String>,
+
+        /// Stop the sweep at the first commit which fails (default)
+        #[arg(long, conflicts_with = "collect_all")]
+        fail_fast: bool,
+
+        /// Benchmark every commit even if some fail, then print a summary
+        #[arg(long)]
+        collect_all: bool,
+
+        /// Stop after this many successful runs, even if the end of the range isn't reached
+        #[arg(long)]
+        limit_runs: Option<usize>,
+
+        /// Run the sweep even if it has more runs than `sweep_max_runs`, without asking
+        #[arg(long, short)]
+        yes: bool,
+    },
+
+    /// Run benchmarks on every Nth commit after start, up to and including end, following only first
+    /// parents unless --order is given
+    Commits {
+        /// Path to bitcoin source code directory
+        src_dir: PathBuf,
+
+        /// Commit to start after (exclusive)
+        #[arg(required_unless_present_any = ["commit_range", "commits_from_tags"])]
+        start: Option<String>,
+
+        /// Last commit to benchmark (inclusive)
+        #[arg(required_unless_present_any = ["commit_range", "commits_from_tags"])]
+        end: Option<String>,
+
+        /// Git range expression to benchmark instead of start and end, e.g. `v26.0..master` or
+        /// `good...bad`
+        #[arg(long, conflicts_with_all = ["start", "end"])]
+        commit_range: Option<String>,
+
+        /// Benchmark the commit of every tag matching this glob, e.g. `v*`, in order of creation
+        /// instead of a range, recording the tag with each run
+        #[arg(long, conflicts_with_all = ["start", "end", "commit_range", "every", "order"])]
+        commits_from_tags: Option<String>,
+
+        /// Benchmark every Nth commit in the range
+        #[arg(long, default_value_t = 1)]
+        every: usize,
+
+        /// Benchmark every commit in the range, including those of merged branches, in this order
+        /// rather than only the first-parent chain
+        #[arg(long, value_enum)]
+        order: Option<CommitOrder>,
+
+        /// Stop the sweep at the first commit which fails (default)
+        #[arg(long, conflicts_with = "collect_all")]
+        fail_fast: bool,
+
+        /// Benchmark every commit even if some fail, then print a summary
+        #[arg(long)]
+        collect_all: bool,
+
+        /// Stop after this many successful runs, even if the end of the range isn't reached
+        #[arg(long)]
+        limit_runs: Option<usize>,
+    },
+
+    /// Benchmark an official release tarball rather than a source build, recording the version as
+    /// the commit
+    Release {
+        /// Directory to download and extract the release into
+        release_dir: PathBuf,
+
+        /// Release version, substituted for `{version}` in the URL template, e.g. `27.0`
+        version: String,
+
+        /// Expected SHA256 of the tarball, as listed in the release's SHA256SUMS
+        #[arg(long)]
+        sha256: String,
+
+        /// URL of the tarball with `{version}` for the version, overriding `release_url_template`
+        #[arg(long)]
+        url_template: Option<String>,
     },
 }
 
 #[derive(Debug, Subcommand)]
 pub enum GraphCommands {
     /// Generate graphs
-    Generate {},
+    Generate {
+        /// Name of the job to graph
+        #[arg(long, default_value = "IBD")]
+        job: String,
+
+        /// Only plot runs from this date onwards (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        from: Option<i64>,
+
+        /// Only plot runs up to and including this date (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        to: Option<i64>,
+
+        /// Mark events such as releases with dashed lines, read from a CSV file of `date,label`
+        /// lines or a TOML file of `"date" = "label"` pairs
+        #[arg(long)]
+        annotations: Option<PathBuf>,
+
+        #[command(flatten)]
+        plot: PlotArgs,
+    },
+
+    /// Generate a graph for every recorded job, reading the database once
+    All {
+        /// Number of graphs to draw at once, defaults to the number of CPUs
+        #[arg(long, short)]
+        jobs: Option<usize>,
+
+        /// Only plot runs from this date onwards (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        from: Option<i64>,
+
+        /// Only plot runs up to and including this date (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        to: Option<i64>,
+
+        #[command(flatten)]
+        plot: PlotArgs,
+    },
+}
+
+/// How to draw a graph, shared by the graph commands.
+#[derive(Debug, Args)]
+pub struct PlotArgs {
+    /// Metric to plot on the primary y-axis
+    #[arg(long, alias = "metric", value_enum, default_value_t = Metric::UserTime)]
+    primary: Metric,
+
+    /// Metric to plot on the secondary y-axis, or `none` to plot only the primary metric
+    #[arg(long, default_value = "max-rss")]
+    secondary: SecondaryMetric,
+
+    /// Use a logarithmic primary y-axis, non-positive values are omitted
+    #[arg(long)]
+    log_scale: bool,
+
+    /// What to plot on the x-axis
+    #[arg(long, value_enum, default_value_t = XAxis::Date)]
+    x_axis: XAxis,
+
+    /// Plot the mean of each calendar period instead of every run (date x-axis only)
+    #[arg(long, value_enum, default_value_t = Aggregate::Daily)]
+    aggregate: Aggregate,
+
+    /// Draw min/max whiskers for each aggregated period
+    #[arg(long)]
+    whiskers: bool,
+
+    /// Draw ±1 standard deviation error bars on points from repeated runs
+    #[arg(long)]
+    error_bars: bool,
+
+    /// Colour scheme of the graph
+    #[arg(long, value_enum, default_value_t = Theme::Light)]
+    theme: Theme,
+
+    /// Label axes with plain numbers instead of human readable units
+    #[arg(long)]
+    raw: bool,
+
+    /// Plot how far each run's primary metric is from the mean of all plotted runs, highlighting
+    /// runs beyond 2 standard deviations
+    #[arg(long, alias = "compare-to-mean", value_enum)]
+    deviation: Option<Deviation>,
+
+    /// Only plot runs from this long ago onwards, e.g. `7d` or `24h`
+    #[arg(long, value_parser = parse_duration)]
+    pub since: Option<Duration>,
+}
+
+impl PlotArgs {
+    pub fn options<'a>(&self, job_name: &'a str) -> GraphOptions<'a> {
+        GraphOptions {
+            job_name,
+            metric: self.primary,
+            secondary: self.secondary.0,
+            log_scale: self.log_scale,
+            x_axis: self.x_axis,
+            aggregate: self.aggregate,
+            whiskers: self.whiskers,
+            error_bars: self.error_bars,
+            theme: self.theme,
+            raw: self.raw,
+            events: &[],
+            deviation: self.deviation,
+        }
+    }
 }
 
 impl Cli {
     pub fn init() -> Result<Self> {
         let mut cli = Cli::parse();
+        let current_dir = std::env::current_dir()
+            .map_err(|e| anyhow!("Failed to get current working directory: {}", e))?;
         if cli.config_file.is_none() {
-            let current_dir = std::env::current_dir()
-                .map_err(|e| anyhow!("Failed to get current working directory: {}", e))?;
-            cli.config_file = Some(current_dir.join("config.toml"));
+            cli.config_file = Some(resolve_config_file(&current_dir, dirs::config_dir()));
         }
+        cli.bench_data_dir = match cli.bench_data_dir_arg.take() {
+            Some(bench_data_dir) => bench_data_dir,
+            None => resolve_data_dir(dirs::data_dir(), dirs::config_dir(), &cli.bench_db_name)?,
+        };
+        cli.make_paths_absolute(&current_dir);
         info!(
             "Bitcoin datadir set to: {}",
             cli.bitcoin_data_dir.as_ref().unwrap().to_string_lossy()
@@ -111,4 +620,135 @@ impl Cli {
         );
         Ok(cli)
     }
+
+    fn make_paths_absolute(&mut self, current_dir: &Path) {
+        let paths = [
+            self.config_file.as_mut(),
+            Some(&mut self.bench_data_dir),
+            self.bitcoin_data_dir.as_mut(),
+        ];
+        for path in paths.into_iter().flatten() {
+            resolve_against(current_dir, path);
+        }
+
+        match &mut self.command {
+            Some(Commands::Bench(BenchCommands::Run {
+                run_command,
+                manifest,
+                keep_artifacts,
+                ..
+            })) => {
+                let src_dir = match run_command {
+                    RunCommands::Once { src_dir, .. }
+                    | RunCommands::Daily { src_dir, .. }
+                    | RunCommands::Commits { src_dir, .. }
+                    | RunCommands::Release {
+                        release_dir: src_dir,
+                        ..
+                    } => src_dir,
+                };
+                resolve_against(current_dir, src_dir);
+                for path in [manifest.as_mut(), keep_artifacts.as_mut()]
+                    .into_iter()
+                    .flatten()
+                {
+                    resolve_against(current_dir, path);
+                }
+            }
+            Some(Commands::Suspects { src_dir, .. }) => resolve_against(current_dir, src_dir),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_relative_src_dir_against_launch_dir() {
+        let mut cli = Cli::try_parse_from([
+            "bitcoin-bencher",
+            "bench",
+            "run",
+            "daily",
+            "bitcoin",
+            "2024-01-05",
+            "2024-01-06",
+        ])
+        .unwrap();
+        let launch_dir = Path::new("/home/bench");
+        cli.make_paths_absolute(launch_dir);
+        let Some(Commands::Bench(BenchCommands::Run {
+            run_command: RunCommands::Daily { src_dir, .. },
+            ..
+        })) = &cli.command
+        else {
+            panic!("Expected a daily run, got {:?}", cli.command);
+        };
+        assert_eq!(src_dir, &launch_dir.join("bitcoin"));
+    }
+
+    #[test]
+    fn data_dir_uses_xdg_data_dir() {
+        let home = TempDir::new("home").unwrap();
+        let data_dir = home.path().join(".local/share");
+        let config_dir = home.path().join(".config");
+        assert_eq!(
+            resolve_data_dir(
+                Some(data_dir.clone()),
+                Some(config_dir.clone()),
+                "db.sqlite"
+            )
+            .unwrap(),
+            data_dir.join("bench_bitcoin")
+        );
+
+        // A config file in the legacy dir doesn't make it a data dir
+        std::fs::create_dir_all(config_dir.join("bench_bitcoin")).unwrap();
+        std::fs::write(config_dir.join("bench_bitcoin/config.toml"), "").unwrap();
+        assert_eq!(
+            resolve_data_dir(
+                Some(data_dir.clone()),
+                Some(config_dir.clone()),
+                "db.sqlite"
+            )
+            .unwrap(),
+            data_dir.join("bench_bitcoin")
+        );
+        assert!(resolve_data_dir(None, Some(config_dir), "db.sqlite").is_err());
+    }
+
+    #[test]
+    fn data_dir_falls_back_to_existing_legacy_database() {
+        let home = TempDir::new("home").unwrap();
+        let legacy = home.path().join(".config/bench_bitcoin");
+        std::fs::create_dir_all(&legacy).unwrap();
+        std::fs::write(legacy.join("db.sqlite"), "").unwrap();
+        assert_eq!(
+            resolve_data_dir(
+                Some(home.path().join(".local/share")),
+                Some(home.path().join(".config")),
+                "db.sqlite"
+            )
+            .unwrap(),
+            legacy
+        );
+    }
+
+    #[test]
+    fn config_file_prefers_working_directory() {
+        let cwd = TempDir::new("cwd").unwrap();
+        let config_dir = PathBuf::from("/home/user/.config");
+        assert_eq!(
+            resolve_config_file(cwd.path(), Some(config_dir.clone())),
+            config_dir.join("bench_bitcoin/config.toml")
+        );
+
+        std::fs::write(cwd.path().join("config.toml"), "").unwrap();
+        assert_eq!(
+            resolve_config_file(cwd.path(), Some(config_dir)),
+            cwd.path().join("config.toml")
+        );
+    }
 }
@@ -1,83 +1,1021 @@
-use anyhow::{bail, Context, Result};
-use log::debug;
-use serde::Deserialize;
-use std::{fs, path::PathBuf};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
-use crate::{cli::Cli, util};
+use crate::{
+    check::Threshold,
+    cli::{self, Cli},
+    result::Metric,
+    util,
+};
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub settings: Settings,
     pub jobs: Jobs,
+    /// Values substituted for `{<key>}` in every job's command and environment
+    #[serde(default)]
+    pub vars: BTreeMap<String, toml::Value>,
+    /// Where to send notifications of finished runs and regressions
+    #[serde(default)]
+    pub notifiers: Vec<NotifierConfig>,
+    /// SHA256 of the effective config, after any `--profile` is applied
+    #[serde(skip)]
+    pub hash: String,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Settings {
     pub binaries: Vec<String>,
     pub bitcoin_data_dir: Option<PathBuf>,
+    /// Number of times to retry a git fetch which failed due to a network error
+    #[serde(default = "default_git_retries")]
+    pub git_retries: u32,
+    /// Run jobs inside this container image
+    pub container_image: Option<String>,
+    /// Container runtime used with `container_image`, e.g. `docker` or `podman`
+    #[serde(default = "default_container_runtime")]
+    pub container_runtime: String,
+    /// Record ccache hits and misses for each run
+    #[serde(default)]
+    pub track_ccache: bool,
+    /// Refuse to benchmark a commit or tag without a valid GPG signature
+    #[serde(default)]
+    pub verify_signature: bool,
+    /// Record bytes read from and written to storage by each job from `/proc/<pid>/io` (Linux only)
+    #[serde(default)]
+    pub measure_io: bool,
+    /// How hard sqlite works to make each write durable
+    #[serde(default)]
+    pub db_durability: DbDurability,
+    /// Check the build works before spending time on the remaining jobs
+    pub smoke_test: Option<SmokeTest>,
+    /// Where GNU time writes its report for the job to be read back
+    #[serde(default)]
+    pub time_output: TimeOutput,
+    /// Repository cloned into the source directory when it doesn't contain one yet
+    pub repo_url: Option<String>,
+    /// Clone `repo_url` into the source directory if it isn't a git repository, as `--clone` does
+    #[serde(default)]
+    pub auto_clone: bool,
+    /// Compare each master run with the previous master run on the same machine, warning when a
+    /// metric grows by more than its `metric=percent` threshold
+    #[serde(default)]
+    pub compare_previous: Vec<Threshold>,
+    /// Store the percent changes found by `compare_previous` in the database
+    #[serde(default)]
+    pub store_deltas: bool,
+    /// Append each job's results to this file as a JSON line as soon as the job finishes
+    pub results_jsonl: Option<PathBuf>,
+    /// What to do with a commit when one of its `build` jobs fails
+    #[serde(default)]
+    pub on_build_failure: OnBuildFailure,
+    /// Age in days after which `archive` collapses jobs into daily aggregates, unless it is given
+    /// `--before`
+    pub archive_after_days: Option<u32>,
+    /// Block hash substituted for `{assumevalid}` in job commands, or `0` to verify every script
+    pub assumevalid: Option<String>,
+    /// Number of script verification threads substituted for `{par}` in job commands
+    pub par: Option<u32>,
+    /// Record the size of `bitcoin_data_dir` and its main subdirectories after each job
+    #[serde(default)]
+    pub measure_datadir_size: bool,
+    /// Refuse `archive`, data dir cleanup and anything else which deletes results
+    #[serde(default)]
+    pub append_only: bool,
+    /// URL of release tarballs for `bench run release`, with `{version}` for the version
+    pub release_url_template: Option<String>,
+    /// Record the values of environment variables named like secrets, e.g. `*_TOKEN`, and
+    /// credentials in URLs, rather than redacting them
+    #[serde(default)]
+    pub record_sensitive_env: bool,
+    /// Earliest date a daily sweep may start on
+    pub sweep_min_date: Option<String>,
+    /// Latest date a daily sweep may end on
+    pub sweep_max_date: Option<String>,
+    /// Daily sweeps of more runs than this need `--yes`, or confirming at a prompt
+    pub sweep_max_runs: Option<usize>,
+    /// Wait before each bench job until the 1 minute load average per core is at most `max_load`
+    #[serde(default)]
+    pub wait_for_idle: bool,
+    /// 1 minute load average per core above which `wait_for_idle` waits
+    #[serde(default = "default_max_load")]
+    pub max_load: f64,
+    /// Seconds `wait_for_idle` waits before running the job anyway
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+    /// Record every execution of a job with `runs` above 1 as its own job, numbered by
+    /// `sample_index`, rather than only the median execution
+    #[serde(default)]
+    pub record_individual_samples: bool,
+    /// Milliseconds between RSS samples of jobs with `profile_memory`
+    #[serde(default = "default_memory_sample_interval_ms")]
+    pub memory_sample_interval_ms: u64,
+    /// Run jobs on this host over SSH, e.g. `bench@box`, rather than locally
+    pub remote_host: Option<String>,
+    /// Source checkout on `remote_host` which jobs run in
+    pub remote_src_dir: Option<PathBuf>,
+}
+
+fn default_memory_sample_interval_ms() -> u64 {
+    1000
+}
+
+/// `abort` stops the run as any failed job without `continue_on_error` does, `skip` discards the
+/// commit's run and moves on, and `record` keeps the run, marked as build-failed with the error
+/// and without job results, so graphs show a gap.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnBuildFailure {
+    #[default]
+    Abort,
+    Skip,
+    Record,
+}
+
+/// Maps to sqlite's `PRAGMA synchronous`. The database uses WAL mode, in which `normal` can lose
+/// the most recent writes on power loss but never corrupts the database, `full` also survives
+/// power loss, and `off` is fastest but may corrupt the database if the OS crashes.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DbDurability {
+    Off,
+    #[default]
+    Normal,
+    Full,
+}
+
+impl DbDurability {
+    pub fn pragma_value(&self) -> &'static str {
+        match self {
+            DbDurability::Off => "OFF",
+            DbDurability::Normal => "NORMAL",
+            DbDurability::Full => "FULL",
+        }
+    }
+}
+
+/// A destination for notifications, selected by its `type`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifierConfig {
+    /// POST each event as JSON to `url`
+    Webhook {
+        url: String,
+        /// Seconds to wait for the endpoint to respond
+        #[serde(default = "default_notify_timeout")]
+        timeout: u64,
+    },
+    /// Email each event through an SMTP server
+    Email {
+        smtp_server: String,
+        /// Defaults to 587 with `starttls`, 465 for implicit TLS without it, or 25 with `insecure`
+        port: Option<u16>,
+        /// Upgrade the connection with STARTTLS, otherwise connect with implicit TLS
+        #[serde(default = "default_starttls")]
+        starttls: bool,
+        /// Connect in plaintext when `starttls` is off, sending any credentials in the clear. Only
+        /// for a relay on the local machine.
+        #[serde(default)]
+        insecure: bool,
+        username: Option<String>,
+        /// Environment variable holding the SMTP password, to keep it out of the config
+        password_env: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+fn default_notify_timeout() -> u64 {
+    10
+}
+
+fn default_starttls() -> bool {
+    true
+}
+
+/// A quick shell command run on the host once a build job has finished. If it fails or times out
+/// the commit's remaining jobs are skipped.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SmokeTest {
+    pub command: String,
+    /// Name of the job after which to run the smoke test, usually the build
+    pub after: String,
+    /// Seconds to wait for the command before treating it as failed
+    #[serde(default = "default_smoke_test_timeout")]
+    pub timeout: u64,
+}
+
+fn default_smoke_test_timeout() -> u64 {
+    60
+}
+
+/// `file` has time write each job's report to its outfile, `pipe` passes time a pipe on fd 3 so no
+/// file is written. Containers don't see the pipe, so they always use `file`.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeOutput {
+    #[default]
+    File,
+    Pipe,
+}
+
+fn default_max_load() -> f64 {
+    0.25
+}
+
+fn default_idle_timeout() -> u64 {
+    600
+}
+
+fn default_container_runtime() -> String {
+    "docker".to_string()
+}
+
+fn default_git_retries() -> u32 {
+    3
 }
 
 #[derive(Deserialize, Debug, Default)]
 pub struct Jobs {
     pub jobs: Vec<Job>,
     pub cleanup: bool,
+    /// During a sweep, keep the data dir for the next commit when it descends from the last one
+    /// without changing the on-disk formats, rather than cleaning up
+    #[serde(default)]
+    pub reuse_datadir_when_compatible: bool,
+    /// Log and record failed jobs, then carry on with the rest of the run
+    #[serde(default)]
+    pub continue_on_error: bool,
+    /// Seconds to wait for bitcoind to release the data dir lock before cleanup
+    #[serde(default = "default_cleanup_grace_period")]
+    pub cleanup_grace_period: u64,
+}
+
+fn default_cleanup_grace_period() -> u64 {
+    30
+}
+
+impl Jobs {
+    /// The jobs in the order a run executes them: `build` jobs in config order before the jobs
+    /// which use the build, then highest `priority` first, with jobs of equal priority, including
+    /// those without one (priority 0), in config order. Build jobs can't set a priority.
+    pub fn in_run_order(&self) -> Vec<&Job> {
+        let mut jobs: Vec<&Job> = self.jobs.iter().collect();
+        jobs.sort_by_key(|job| (!job.build, std::cmp::Reverse(job.priority.unwrap_or(0))));
+        jobs
+    }
 }
 
 fn default_bench() -> bool {
     true
 }
 
+/// A `time`-compatible program used to measure a job in place of GNU time.
+#[derive(Deserialize, Debug, Clone)]
+pub struct TimeWrapper {
+    pub path: String,
+    /// Arguments placed before the job command, `{outfile}` is replaced with the job's outfile
+    #[serde(default = "default_time_wrapper_args")]
+    pub args: Vec<String>,
+}
+
+fn default_time_wrapper_args() -> Vec<String> {
+    vec!["-v".to_string(), "--output={outfile}".to_string()]
+}
+
+/// A job's command, either a string split on whitespace or an argv array used as given.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum JobCommand {
+    Line(String),
+    Argv(Vec<String>),
+}
+
+impl JobCommand {
+    pub fn args(&self) -> Vec<&str> {
+        match self {
+            JobCommand::Line(line) => line.split_whitespace().collect(),
+            JobCommand::Argv(argv) => argv.iter().map(String::as_str).collect(),
+        }
+    }
+
+    pub fn contains(&self, pattern: &str) -> bool {
+        match self {
+            JobCommand::Line(line) => line.contains(pattern),
+            JobCommand::Argv(argv) => argv.iter().any(|arg| arg.contains(pattern)),
+        }
+    }
+
+    fn replace(&mut self, from: &str, to: &str) {
+        match self {
+            JobCommand::Line(line) => *line = line.replace(from, to),
+            JobCommand::Argv(argv) => {
+                for arg in argv {
+                    *arg = arg.replace(from, to);
+                }
+            }
+        }
+    }
+}
+
+/// A job's output files, the first of which is always GNU time's report.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Outfile {
+    Path(String),
+    Paths(Vec<String>),
+}
+
+impl Outfile {
+    pub fn paths(&self) -> &[String] {
+        match self {
+            Outfile::Path(path) => std::slice::from_ref(path),
+            Outfile::Paths(paths) => paths,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Job {
     pub name: String,
     pub env: Option<Vec<String>>,
-    pub command: String,
+    pub command: JobCommand,
     #[serde(default = "default_bench")]
     pub bench: bool,
-    pub outfile: Option<String>,
+    /// GNU time output file, or a list of it followed by metrics files the job writes. `{run_id}` is
+    /// replaced with the id of the run
+    pub outfile: Option<Outfile>,
+    /// Shell command run before the job, the job is skipped unless it exits 0
+    pub run_if: Option<String>,
+    /// Wrap the command in `perf record` (Linux only)
+    #[serde(default)]
+    pub profile: bool,
+    /// Patterns for `-debug=bench` phases to record as metrics
+    pub bench_phases: Option<Vec<String>>,
+    /// Log file to scan for bench phases, defaults to the job's output
+    pub bench_log: Option<String>,
+    /// Measure this job with a different wrapper than the default GNU time
+    pub time_wrapper: Option<TimeWrapper>,
+    /// How the job is measured
+    #[serde(default)]
+    pub measure: Measure,
+    /// Show this job's `headline_metric` as the headline figure of each run
+    #[serde(default)]
+    pub primary: bool,
+    /// Metric of the primary job used as the headline, defaults to user time
+    pub headline_metric: Option<Metric>,
+    /// Part of building the commit, a failure is handled by `on_build_failure`
+    #[serde(default)]
+    pub build: bool,
+    /// Regex which a line of the job's output must match for it to count as successful
+    pub success_pattern: Option<String>,
+    /// Shell command which must exit 0 after the job for it to count as successful
+    pub success_command: Option<String>,
+    /// Soft memory limit in MiB, which throttles and reclaims from the job rather than killing it
+    /// (Linux with cgroup v2 only)
+    pub memory_high_mb: Option<u64>,
+    /// Times to execute a bench job. The execution with the median user time is recorded, along
+    /// with the spread over all of them, unless `record_individual_samples` is set
+    #[serde(default = "default_runs")]
+    pub runs: u32,
+    /// Sample the RSS of a bench job's processes every `memory_sample_interval_ms`, writing the
+    /// timeline to a CSV file referenced from the job (Linux only)
+    #[serde(default)]
+    pub profile_memory: bool,
+    /// Jobs with a higher priority run earlier in each run, equal priorities keep config order.
+    /// Jobs without one have priority 0. Not allowed on build jobs, which run first in config order
+    pub priority: Option<i32>,
 }
 
+fn default_runs() -> u32 {
+    1
+}
+
+/// `time` wraps the job in GNU time (or `time_wrapper`), `native` runs the job directly and reads
+/// its resource usage from `wait4`, recording only the core metrics.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Measure {
+    #[default]
+    Time,
+    Native,
+}
+
+impl Job {
+    /// Whether running this job needs `binary`, matching by file name so `/usr/bin/time` and
+    /// `time` are the same binary.
+    pub fn uses_binary(&self, binary: &str) -> bool {
+        let file_name = |path: &str| {
+            Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        };
+        let binary = file_name(binary);
+        let mut programs = self.command.args();
+        match &self.time_wrapper {
+            _ if !self.bench || self.measure == Measure::Native => {}
+            Some(wrapper) => programs.push(&wrapper.path),
+            None => programs.extend(["time", "gtime"]),
+        }
+        programs
+            .into_iter()
+            .any(|program| file_name(program) == binary)
+    }
+}
+
+/// Overlay the settings in `[profiles.<name>]` onto `[settings]`. Flags passed on the command line
+/// are applied after this, so they still take precedence over the profile.
+fn apply_profile(table: &mut toml::Table, name: &str) -> Result<()> {
+    let profiles = table.get("profiles").and_then(|p| p.as_table());
+    let Some(profile) = profiles.and_then(|p| p.get(name)) else {
+        let available: Vec<&str> = profiles
+            .map(|p| p.keys().map(String::as_str).collect())
+            .unwrap_or_default();
+        bail!(
+            "Unknown profile '{}', available profiles: [{}]",
+            name,
+            available.join(", ")
+        );
+    };
+    let Some(profile) = profile.as_table().cloned() else {
+        bail!("Profile '{}' must be a table of settings", name);
+    };
+    let settings = table
+        .entry("settings")
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    let Some(settings) = settings.as_table_mut() else {
+        bail!("[settings] must be a table");
+    };
+    settings.extend(profile);
+    Ok(())
+}
+
+/// SHA256 of the effective config `table`, so runs with different profiles hash differently.
+fn config_hash(table: &toml::Table) -> Result<String> {
+    Ok(format!("{:x}", Sha256::digest(toml::to_string(table)?)))
+}
+
+/// Placeholders filled in from the settings, which `[vars]` can't redefine.
+const BUILTIN_PLACEHOLDERS: [&str; 4] = ["cores", "datadir", "assumevalid", "par"];
+
 impl Config {
     pub fn load_from_file(cli: &Cli, bitcoin_data_dir: &Option<PathBuf>) -> Result<Self> {
         let config_contents = fs::read_to_string(cli.config_file.as_ref().unwrap())?;
-        let mut config: Config = toml::from_str(&config_contents)?;
+        let mut table: toml::Table = toml::from_str(&config_contents)?;
+        if let Some(profile) = &cli.profile {
+            apply_profile(&mut table, profile)?;
+        }
+        table.remove("profiles");
+        let hash = config_hash(&table)?;
+        let mut config: Config = table.try_into()?;
         config.settings.bitcoin_data_dir = bitcoin_data_dir.clone();
+        config.hash = hash;
         debug!("Using configuration: {:?}", config);
 
+        if let Some(path) = config.settings.results_jsonl.as_mut() {
+            cli::make_absolute(path)?;
+        }
         config.substitute_defaults(cli);
+        config.substitute_validation()?;
         config.substitute_vars()?;
+        config.check_remote()?;
+        config.check_time_wrappers()?;
+        config.check_smoke_test()?;
+        config.check_primary_job()?;
+        config.check_success_patterns()?;
+        config.check_runs()?;
+        config.check_priorities()?;
+        config.warn_untracked_ccache();
 
         Ok(config)
     }
 
+    /// The primary job and the metric it headlines runs with, if one is configured.
+    pub fn headline(&self) -> Option<(&str, Metric)> {
+        self.jobs.jobs.iter().find(|job| job.primary).map(|job| {
+            (
+                job.name.as_str(),
+                job.headline_metric.unwrap_or(Metric::UserTime),
+            )
+        })
+    }
+
+    fn check_primary_job(&self) -> Result<()> {
+        let primary: Vec<&str> = self
+            .jobs
+            .jobs
+            .iter()
+            .filter(|job| job.primary)
+            .map(|job| job.name.as_str())
+            .collect();
+        if primary.len() > 1 {
+            bail!(
+                "At most one job may be primary, found: {}",
+                primary.join(", ")
+            );
+        }
+        for job in self.jobs.jobs.iter().filter(|job| !job.primary) {
+            if job.headline_metric.is_some() {
+                warn!(
+                    "Job {} sets headline_metric but isn't primary, it is ignored",
+                    job.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn check_runs(&self) -> Result<()> {
+        for job in &self.jobs.jobs {
+            if job.runs == 0 {
+                bail!("Job {} must have runs of at least 1", job.name);
+            }
+            if job.runs > 1 && !job.bench {
+                warn!(
+                    "Job {} sets runs but isn't a bench job, it runs once",
+                    job.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Build jobs depend on each other in config order, so reordering them can't be asked for.
+    fn check_priorities(&self) -> Result<()> {
+        for job in &self.jobs.jobs {
+            if job.build && job.priority.is_some() {
+                bail!(
+                    "Job {} is a build job, which can't set a priority as build jobs run first \
+                    in config order",
+                    job.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn check_success_patterns(&self) -> Result<()> {
+        for job in &self.jobs.jobs {
+            if let Some(pattern) = &job.success_pattern {
+                Regex::new(pattern)
+                    .map_err(|e| anyhow!("Invalid success_pattern for job {}: {}", job.name, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_smoke_test(&self) -> Result<()> {
+        if let Some(smoke_test) = &self.settings.smoke_test {
+            if !self
+                .jobs
+                .jobs
+                .iter()
+                .any(|job| job.name == smoke_test.after)
+            {
+                bail!(
+                    "Smoke test runs after job '{}', which isn't configured",
+                    smoke_test.after
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn warn_untracked_ccache(&self) {
+        if self.settings.track_ccache {
+            return;
+        }
+        let uses_ccache = self.jobs.jobs.iter().any(|job| {
+            job.command.contains("ccache")
+                || job.env.iter().flatten().any(|var| var.contains("ccache"))
+        });
+        if uses_ccache {
+            warn!("Jobs use ccache but track_ccache is off, build times may not be comparable");
+        }
+    }
+
+    fn check_remote(&self) -> Result<()> {
+        let settings = &self.settings;
+        if settings.remote_host.is_some() != settings.remote_src_dir.is_some() {
+            bail!("remote_host and remote_src_dir must be set together");
+        }
+        if settings.remote_host.is_none() {
+            return Ok(());
+        }
+        if settings.container_image.is_some() {
+            bail!("container_image can't be used with remote_host");
+        }
+        if let Some(job) = self
+            .jobs
+            .jobs
+            .iter()
+            .find(|job| job.bench && job.measure == Measure::Native)
+        {
+            bail!(
+                "Job {} uses native measurement, which would measure the ssh client",
+                job.name
+            );
+        }
+        Ok(())
+    }
+
+    fn check_time_wrappers(&self) -> Result<()> {
+        for job in self
+            .jobs
+            .jobs
+            .iter()
+            .filter(|job| job.measure == Measure::Native)
+        {
+            if self.settings.container_image.is_some() {
+                bail!(
+                    "Job {} uses native measurement, which would measure the container client",
+                    job.name
+                );
+            }
+            if job.time_wrapper.is_some() {
+                warn!(
+                    "Job {} uses native measurement, its time_wrapper is ignored",
+                    job.name
+                );
+            }
+        }
+        // Wrappers for containerised and remote jobs live inside the image or on the host
+        if self.settings.container_image.is_some() || self.settings.remote_host.is_some() {
+            return Ok(());
+        }
+        for job in &self.jobs.jobs {
+            if let Some(wrapper) = &job.time_wrapper {
+                which::which(&wrapper.path).with_context(|| {
+                    format!(
+                        "Time wrapper {} for job {} not found",
+                        wrapper.path, job.name
+                    )
+                })?;
+            }
+        }
+        Ok(())
+    }
+
     fn substitute_defaults(&mut self, cli: &Cli) {
         for job in &mut self.jobs.jobs {
-            job.outfile.get_or_insert_with(|| {
-                format!(
-                    "{}/{}-results.txt",
-                    cli.bench_data_dir.to_str().unwrap(),
-                    job.name
-                )
-            });
+            let default = format!(
+                "{}/{{run_id}}-{}-results.txt",
+                cli.bench_data_dir.display(),
+                job.name
+            );
+            match &mut job.outfile {
+                None => job.outfile = Some(Outfile::Path(default)),
+                Some(Outfile::Paths(paths)) if paths.is_empty() => paths.push(default),
+                Some(_) => {}
+            }
         }
     }
 
+    /// Substitute `{assumevalid}` and `{par}`, which must be set if a job uses them and used by a
+    /// job if set, so the values recorded for each run are the ones its jobs ran with.
+    fn substitute_validation(&mut self) -> Result<()> {
+        if let Some(assumevalid) = &self.settings.assumevalid {
+            let is_hash =
+                assumevalid.len() == 64 && assumevalid.chars().all(|c| c.is_ascii_hexdigit());
+            if !is_hash && assumevalid != "0" {
+                bail!(
+                    "assumevalid must be a 64 character hex block hash or 0, got '{}'",
+                    assumevalid
+                );
+            }
+        }
+        let values = [
+            ("assumevalid", self.settings.assumevalid.clone()),
+            ("par", self.settings.par.map(|par| par.to_string())),
+        ];
+        for (name, value) in values {
+            let placeholder = format!("{{{}}}", name);
+            let users: Vec<&mut Job> = self
+                .jobs
+                .jobs
+                .iter_mut()
+                .filter(|job| job.command.contains(&placeholder))
+                .collect();
+            match value {
+                Some(_) if users.is_empty() => {
+                    bail!("{} is set but no job command uses {}", name, placeholder)
+                }
+                Some(value) => {
+                    for job in users {
+                        job.command.replace(&placeholder, &value);
+                    }
+                }
+                None => {
+                    if let Some(job) = users.first() {
+                        bail!(
+                            "Job {} uses {} but {} isn't set",
+                            job.name,
+                            placeholder,
+                            name
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Substitute the `[vars]` and then `{cores}` and `{datadir}`, so vars may use those. Any
+    /// other `{placeholder}` left in a job command is an error rather than passed to the job.
+    /// `{cores}` is left for [`Config::substitute_cores`] when jobs run on `remote_host`.
     fn substitute_vars(&mut self) -> Result<()> {
         let nproc = util::get_nproc().context("Failed to get number of processors")?;
+        // `${VAR}` is left for the shell
+        let placeholder = Regex::new(r"(?:^|[^$])\{([A-Za-z_][A-Za-z0-9_]*)\}")?;
+
+        let mut vars = Vec::new();
+        for (key, value) in &self.vars {
+            if BUILTIN_PLACEHOLDERS.contains(&key.as_str()) {
+                bail!("[vars] can't redefine the built-in placeholder {{{}}}", key);
+            }
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+                    value.to_string()
+                }
+                _ => bail!("[vars] {} must be a string, number or boolean", key),
+            };
+            vars.push((format!("{{{}}}", key), value));
+        }
 
         for job in &mut self.jobs.jobs {
+            for (placeholder, value) in &vars {
+                job.command.replace(placeholder, value);
+                for env_var in job.env.iter_mut().flatten() {
+                    *env_var = env_var.replace(placeholder, value);
+                }
+            }
             if let Some(bitcoin_data_dir) = &self.settings.bitcoin_data_dir {
                 if let Some(bitcoin_data_dir_str) = bitcoin_data_dir.to_str() {
-                    job.command = job.command.replace("{cores}", &nproc.to_string());
-                    job.command = job.command.replace("{datadir}", bitcoin_data_dir_str);
+                    if self.settings.remote_host.is_none() {
+                        job.command.replace("{cores}", &nproc.to_string());
+                    }
+                    job.command.replace("{datadir}", bitcoin_data_dir_str);
+                    for env_var in job.env.iter_mut().flatten() {
+                        if self.settings.remote_host.is_none() {
+                            *env_var = env_var.replace("{cores}", &nproc.to_string());
+                        }
+                        *env_var = env_var.replace("{datadir}", bitcoin_data_dir_str);
+                    }
+                    if let Some(bench_log) = &mut job.bench_log {
+                        *bench_log = bench_log.replace("{datadir}", bitcoin_data_dir_str);
+                    }
                 } else {
                     bail!("Failed to convert bitcoin_data_dir to string");
                 }
             } else {
                 bail!("bitcoin_data_dir is not set");
             }
+            // `{cores}` of a remote host is substituted once it is known
+            let unknown = |text: &str| {
+                placeholder
+                    .captures(text)
+                    .map(|c| c[1].to_string())
+                    .filter(|name| name != "cores" || self.settings.remote_host.is_none())
+            };
+            if let Some(name) = job.command.args().iter().find_map(|arg| unknown(arg)) {
+                bail!(
+                    "Job {} uses {{{}}}, which isn't a built-in placeholder or defined in [vars]",
+                    job.name,
+                    name
+                );
+            }
+            if let Some(name) = job
+                .env
+                .iter()
+                .flatten()
+                .find_map(|env_var| unknown(env_var))
+            {
+                bail!(
+                    "Job {} env uses {{{}}}, which isn't {{cores}}, {{datadir}} or defined in [vars]",
+                    job.name,
+                    name
+                );
+            }
         }
 
         Ok(())
     }
+
+    /// Substitute `{cores}` with the number of CPUs of `remote_host`, once it is known.
+    pub fn substitute_cores(&mut self, cores: u32) {
+        for job in &mut self.jobs.jobs {
+            job.command.replace("{cores}", &cores.to_string());
+            for env_var in job.env.iter_mut().flatten() {
+                *env_var = env_var.replace("{cores}", &cores.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [settings]
+        binaries = ["git"]
+        git_retries = 3
+        measure_io = false
+
+        [profiles.precise]
+        measure_io = true
+        db_durability = "full"
+    "#;
+
+    #[test]
+    fn matches_binaries_used_by_job() {
+        let job: Job = toml::from_str(
+            r#"
+            name = "IBD"
+            command = "taskset -c 1 ./src/bitcoind -datadir={DATA_DIR}"
+            "#,
+        )
+        .unwrap();
+        assert!(job.uses_binary("taskset"));
+        assert!(job.uses_binary("bitcoind"));
+        assert!(job.uses_binary("/usr/bin/time"));
+        assert!(!job.uses_binary("hyperfine"));
+    }
+
+    #[test]
+    fn profile_overrides_base_settings() {
+        let mut table: toml::Table = toml::from_str(CONFIG).unwrap();
+        apply_profile(&mut table, "precise").unwrap();
+        let settings: Settings = table["settings"].clone().try_into().unwrap();
+        assert!(settings.measure_io);
+        assert_eq!(settings.db_durability, DbDurability::Full);
+        assert_eq!(settings.git_retries, 3);
+
+        let err = apply_profile(&mut table, "quick").unwrap_err();
+        assert!(err.to_string().contains("available profiles: [precise]"));
+    }
+
+    #[test]
+    fn profile_changes_config_hash() {
+        let base: toml::Table = toml::from_str(CONFIG).unwrap();
+        let mut precise = base.clone();
+        apply_profile(&mut precise, "precise").unwrap();
+        assert_eq!(config_hash(&base).unwrap(), config_hash(&base).unwrap());
+        assert_ne!(config_hash(&base).unwrap(), config_hash(&precise).unwrap());
+    }
+
+    #[test]
+    fn orders_jobs_by_priority() {
+        let config: Config = toml::from_str(
+            r#"
+            [settings]
+            binaries = []
+            [jobs]
+            cleanup = false
+            jobs = [
+                { name = "configure", command = "./configure", build = true },
+                { name = "IBD", command = "bitcoind", priority = -1 },
+                { name = "make", command = "make", build = true },
+                { name = "unit-tests", command = "make check", priority = 10 },
+                { name = "lint", command = "lint.sh" },
+                { name = "sanity", command = "sanity.sh", priority = 10 },
+            ]
+            "#,
+        )
+        .unwrap();
+        let order: Vec<&str> = config
+            .jobs
+            .in_run_order()
+            .iter()
+            .map(|job| job.name.as_str())
+            .collect();
+        assert_eq!(
+            order,
+            ["configure", "make", "unit-tests", "sanity", "lint", "IBD"]
+        );
+        config.check_priorities().unwrap();
+
+        let prioritised_build: Config = toml::from_str(
+            r#"
+            [settings]
+            binaries = []
+            [jobs]
+            cleanup = false
+            jobs = [{ name = "make", command = "make", build = true, priority = 1 }]
+            "#,
+        )
+        .unwrap();
+        let err = prioritised_build.check_priorities().unwrap_err();
+        assert!(err.to_string().contains("can't set a priority"));
+    }
+
+    #[test]
+    fn allows_at_most_one_primary_job() {
+        let config = |jobs: &str| -> Config {
+            toml::from_str(&format!(
+                "[settings]\nbinaries = []\n[jobs]\ncleanup = false\njobs = [{}]",
+                jobs
+            ))
+            .unwrap()
+        };
+        let single = config(
+            r#"{ name = "make", command = "make" },
+               { name = "IBD", command = "bitcoind", primary = true, headline_metric = "max_rss" }"#,
+        );
+        single.check_primary_job().unwrap();
+        assert_eq!(single.headline(), Some(("IBD", Metric::MaxRss)));
+
+        let double = config(
+            r#"{ name = "make", command = "make", primary = true },
+               { name = "IBD", command = "bitcoind", primary = true }"#,
+        );
+        let err = double.check_primary_job().unwrap_err();
+        assert!(err.to_string().contains("found: make, IBD"));
+    }
+
+    #[test]
+    fn substitutes_validation_settings() {
+        let config = |settings: &str| -> Config {
+            toml::from_str(&format!(
+                "[settings]\nbinaries = []\n{}\n[jobs]\ncleanup = false\njobs = [{}]",
+                settings,
+                r#"{ name = "IBD", command = "bitcoind -assumevalid={assumevalid} -par={par}" }"#
+            ))
+            .unwrap()
+        };
+        let hash = "00000000000000000001a0a448d6cf2546b06801389cc030b2b18c6491266815";
+        let mut valid = config(&format!("assumevalid = \"{}\"\npar = 4", hash));
+        valid.substitute_validation().unwrap();
+        assert!(valid.jobs.jobs[0]
+            .command
+            .contains(&format!("-assumevalid={} -par=4", hash)));
+
+        let err = config("assumevalid = \"0xabc\"\npar = 4")
+            .substitute_validation()
+            .unwrap_err();
+        assert!(err.to_string().contains("64 character hex block hash or 0"));
+        let err = config("assumevalid = \"0\"")
+            .substitute_validation()
+            .unwrap_err();
+        assert!(err.to_string().contains("uses {par} but par isn't set"));
+    }
+
+    #[test]
+    fn substitutes_user_defined_vars() {
+        let config = |jobs: &str| -> Config {
+            let mut config: Config = toml::from_str(&format!(
+                "[settings]\nbinaries = []\n[vars]\nstop = 840000\nblocks = \"{{datadir}}/blocks\"\n\
+                 [jobs]\ncleanup = false\njobs = [{}]",
+                jobs
+            ))
+            .unwrap();
+            config.settings.bitcoin_data_dir = Some(PathBuf::from("/tmp/bench"));
+            config
+        };
+        let mut valid = config(
+            r#"{ name = "IBD", command = "bitcoind -stopatheight={stop} -blocksdir={blocks}" },
+               { name = "reindex", command = ["bitcoind", "-reindex", "-stopatheight={stop}"] }"#,
+        );
+        valid.substitute_vars().unwrap();
+        assert_eq!(
+            valid.jobs.jobs[0].command.args(),
+            [
+                "bitcoind",
+                "-stopatheight=840000",
+                "-blocksdir=/tmp/bench/blocks"
+            ]
+        );
+        assert_eq!(
+            valid.jobs.jobs[1].command.args(),
+            ["bitcoind", "-reindex", "-stopatheight=840000"]
+        );
+
+        let err = config(r#"{ name = "IBD", command = "bitcoind -port={port}" }"#)
+            .substitute_vars()
+            .unwrap_err();
+        assert!(err.to_string().contains("Job IBD uses {port}"));
+        let mut env = config(
+            r#"{ name = "IBD", command = "bitcoind", env = ["BLOCKS={blocks}", "DATADIR={datadir}"] }"#,
+        );
+        env.substitute_vars().unwrap();
+        assert_eq!(
+            env.jobs.jobs[0].env.as_deref().unwrap(),
+            ["BLOCKS=/tmp/bench/blocks", "DATADIR=/tmp/bench"]
+        );
+        let err = config(r#"{ name = "IBD", command = "bitcoind", env = ["PORT={port}"] }"#)
+            .substitute_vars()
+            .unwrap_err();
+        assert!(err.to_string().contains("Job IBD env uses {port}"));
+        let mut shell = config(r#"{ name = "sh", command = ["sh", "-c", "echo ${HOME}"] }"#);
+        shell.substitute_vars().unwrap();
+    }
 }
@@ -1,32 +1,79 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use log::{debug, info};
-use rusqlite::{params, Connection};
+use rusqlite::{params, types::ValueRef, Connection, OptionalExtension, Row};
+use std::collections::BTreeMap;
 use std::path::Path;
 
-use crate::result::TimeResult;
+use crate::config::DbDurability;
+use crate::result::{JobStats, Spread, TimeResult};
+use crate::util::{self, CcacheStats, Toolchain};
 
-#[derive(Debug)]
+#[allow(dead_code)]
+#[derive(Debug, Clone, Default)]
 pub struct Run {
     pub id: Option<i32>,
+    /// When the invocation which recorded the run started, shared by every run of a sweep
     pub run_date: i64,
     pub commit_id: String,
     pub commit_date: i64,
+    /// Number of first-parent commits from the root to this commit
+    pub commit_index: Option<i64>,
+    pub tree_hash: Option<String>,
+    pub container_image: Option<String>,
+    pub container_digest: Option<String>,
+    /// Whether the GPG signature was checked and valid, `None` if it wasn't checked
+    pub signature_verified: Option<bool>,
+    /// `topo` or `date` for runs from a commit range sweep
+    pub commit_order: Option<String>,
+    /// The range expression given for runs from a commit range sweep
+    pub commit_range: Option<String>,
+    /// Tag which selected the commit, for runs from a sweep of tags
+    pub tag: Option<String>,
+    /// Wall time in seconds of all the run's jobs, `None` until they have finished
+    pub run_duration: Option<i64>,
+    /// `-assumevalid` hash substituted into the run's jobs, `0` for full verification
+    pub assumevalid: Option<String>,
+    /// `-par` script verification threads substituted into the run's jobs
+    pub par: Option<i64>,
+    /// Notes added with `annotate` after the run, one per line prefixed with when it was added
+    pub notes: Option<String>,
+    /// Benchmarked from an official release tarball rather than built from the source checkout
+    pub from_release: bool,
     pub was_master: bool,
+    pub num_cores: Option<i64>,
+    pub cpu_model: Option<String>,
+    /// Host the run's jobs ran on over SSH, `None` when they ran locally
+    pub remote_host: Option<String>,
+    /// Whether the CPU was thermally throttled while the jobs ran, `None` if it wasn't watched
+    pub throttled: Option<bool>,
+    /// Hottest thermal zone temperature in °C while the jobs ran
+    pub max_temperature_c: Option<f64>,
+    /// Started from the data dir of the run before in its sweep, so it measures incremental work
+    pub datadir_reused: bool,
 }
 
-#[derive(Debug)]
+#[allow(dead_code)]
+#[derive(Debug, Clone, Default)]
 pub struct Job {
     pub job_id: i64,
     pub run_id: i64,
     pub result: TimeResult,
+    /// Spread over repeated executions, `None` for jobs recorded before it was stored
+    pub stats: Option<JobStats>,
+    /// Which execution this is when `record_individual_samples` records each one
+    pub sample_index: Option<u32>,
 }
 
 pub struct Database {
     conn: Connection,
+    /// Refuse operations which delete recorded results
+    append_only: bool,
+    /// Only read runs made at or after this unix timestamp
+    since: Option<i64>,
 }
 
 impl Database {
-    pub fn create_or_load(data_dir: &str, db_name: &str) -> Result<Self> {
+    pub fn create_or_load(data_dir: &str, db_name: &str, durability: DbDurability) -> Result<Self> {
         let data_dir_path = Path::new(data_dir);
         info!(
             "Using data directory: {:?} with db name: {:?}",
@@ -48,12 +95,48 @@ impl Database {
 
         let conn = Connection::open(db_path_str)
             .map_err(|e| anyhow!("Failed to open database at '{}': {}", db_path_str, e))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", durability.pragma_value())?;
+        debug!("Using sqlite synchronous={}", durability.pragma_value());
 
-        let db = Database { conn };
+        let db = Database {
+            conn,
+            append_only: false,
+            since: None,
+        };
         db.create_tables()?;
         Ok(db)
     }
 
+    /// Refuse to delete recorded results, protecting a shared results database from mistakes.
+    pub fn with_append_only(mut self, append_only: bool) -> Self {
+        self.append_only = append_only;
+        self
+    }
+
+    /// Only read the runs made at or after `since`, leaving out older runs and archived days when
+    /// listing, summarising, exporting or graphing.
+    pub fn with_since(mut self, since: Option<i64>) -> Self {
+        self.since = since;
+        self
+    }
+
+    /// The earliest run date read, all of them without `since`.
+    fn cutoff(&self) -> i64 {
+        self.since.unwrap_or(i64::MIN)
+    }
+
+    pub fn is_append_only(&self) -> bool {
+        self.append_only
+    }
+
+    fn check_not_append_only(&self) -> Result<()> {
+        if self.append_only {
+            bail!("database is in append-only mode");
+        }
+        Ok(())
+    }
+
     fn create_tables(&self) -> Result<()> {
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS runs (
@@ -88,14 +171,188 @@ impl Database {
         )?;
         debug!("jobs table exists");
 
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS job_metrics (
+                metric_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                job_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                value REAL NOT NULL,
+                FOREIGN KEY (job_id) REFERENCES jobs(job_id)
+            );",
+            params![],
+        )?;
+        debug!("job_metrics table exists");
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS job_env (
+                job_env_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                job_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                value TEXT NOT NULL,
+                FOREIGN KEY (job_id) REFERENCES jobs(job_id)
+            );",
+            params![],
+        )?;
+        debug!("job_env table exists");
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS run_binaries (
+                run_binary_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                run_id INTEGER NOT NULL,
+                binary TEXT NOT NULL,
+                version TEXT,
+                FOREIGN KEY (run_id) REFERENCES runs(run_id)
+            );",
+            params![],
+        )?;
+        debug!("run_binaries table exists");
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS job_deltas (
+                job_delta_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                job_id INTEGER NOT NULL,
+                baseline_job_id INTEGER NOT NULL,
+                metric TEXT NOT NULL,
+                percent_change REAL NOT NULL,
+                FOREIGN KEY (job_id) REFERENCES jobs(job_id),
+                FOREIGN KEY (baseline_job_id) REFERENCES jobs(job_id)
+            );",
+            params![],
+        )?;
+        debug!("job_deltas table exists");
+
+        let aggregates: Vec<String> = ARCHIVED_COLUMNS
+            .iter()
+            .map(|column| format!("{0}_mean REAL, {0}_min REAL, {0}_max REAL", column))
+            .collect();
+        self.conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS jobs_archive (
+                    archive_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    job_name TEXT NOT NULL,
+                    day INTEGER NOT NULL,
+                    was_master INTEGER NOT NULL,
+                    job_count INTEGER NOT NULL,
+                    {},
+                    UNIQUE (job_name, day, was_master)
+                );",
+                aggregates.join(",\n")
+            ),
+            params![],
+        )?;
+        debug!("jobs_archive table exists");
+
+        self.migrate_tables()?;
+
         info!("All required tables exist in db");
         Ok(())
     }
 
+    /// Columns added after the initial schema, applied to existing databases on load.
+    fn migrate_tables(&self) -> Result<()> {
+        let migrations = [
+            ("jobs", "page_size_bytes", "INTEGER"),
+            ("jobs", "signals_delivered", "INTEGER"),
+            ("runs", "num_cores", "INTEGER"),
+            ("runs", "cpu_model", "TEXT"),
+            ("jobs", "profile_path", "TEXT"),
+            ("runs", "commit_index", "INTEGER"),
+            ("runs", "artifact_path", "TEXT"),
+            ("runs", "tree_hash", "TEXT"),
+            ("runs", "container_image", "TEXT"),
+            ("runs", "container_digest", "TEXT"),
+            ("runs", "clean_build", "INTEGER"),
+            ("runs", "ccache_hits", "INTEGER"),
+            ("runs", "ccache_misses", "INTEGER"),
+            ("runs", "signature_verified", "INTEGER"),
+            ("jobs", "elapsed", "REAL"),
+            ("jobs", "user_time_min", "REAL"),
+            ("jobs", "user_time_max", "REAL"),
+            ("jobs", "user_time_stddev", "REAL"),
+            ("jobs", "max_rss_min", "REAL"),
+            ("jobs", "max_rss_max", "REAL"),
+            ("jobs", "max_rss_stddev", "REAL"),
+            ("jobs", "elapsed_min", "REAL"),
+            ("jobs", "elapsed_max", "REAL"),
+            ("jobs", "elapsed_stddev", "REAL"),
+            ("jobs", "read_bytes", "INTEGER"),
+            ("jobs", "write_bytes", "INTEGER"),
+            ("runs", "commit_order", "TEXT"),
+            ("runs", "commit_range", "TEXT"),
+            ("runs", "run_duration", "INTEGER"),
+            ("runs", "pinned_cpus", "TEXT"),
+            ("runs", "smoke_test_passed", "BOOLEAN"),
+            ("runs", "config_hash", "TEXT"),
+            ("jobs", "failed_criterion", "TEXT"),
+            ("jobs", "raw_time_output", "TEXT"),
+            ("jobs", "sample_index", "INTEGER"),
+            ("jobs", "memory_timeline_path", "TEXT"),
+            ("runs", "build_error", "TEXT"),
+            ("runs", "assumevalid", "TEXT"),
+            ("runs", "par", "INTEGER"),
+            ("runs", "notes", "TEXT"),
+            ("runs", "from_release", "BOOLEAN NOT NULL DEFAULT 0"),
+            ("runs", "tag", "TEXT"),
+            ("runs", "remote_host", "TEXT"),
+            ("runs", "throttled", "BOOLEAN"),
+            ("runs", "max_temperature_c", "REAL"),
+            ("runs", "compiler", "TEXT"),
+            ("runs", "build_flags", "TEXT"),
+            ("runs", "libc", "TEXT"),
+            ("runs", "datadir_reused", "BOOLEAN NOT NULL DEFAULT 0"),
+        ];
+        for (table, column, definition) in migrations {
+            self.add_column_if_missing(table, column, definition)?;
+        }
+        Ok(())
+    }
+
+    fn add_column_if_missing(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>("name"))?
+            .filter_map(|name| name.ok())
+            .any(|name| name == column);
+
+        if !exists {
+            self.conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                params![],
+            )?;
+            info!("Added column {} to table {}", column, table);
+        }
+        Ok(())
+    }
+
     pub fn record_run(&self, run: Run) -> Result<i64> {
         self.conn.execute(
-            "INSERT INTO runs (run_date, was_master, commit_id, commit_date) VALUES (?, ?, ?, ?)",
-            params![run.run_date, run.was_master, run.commit_id, run.commit_date],
+            "INSERT INTO runs (
+                run_date, was_master, commit_id, commit_date, commit_index, tree_hash, num_cores,
+                cpu_model, container_image, container_digest, signature_verified, commit_order,
+                commit_range, assumevalid, par, from_release, tag, remote_host
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                run.run_date,
+                run.was_master,
+                run.commit_id,
+                run.commit_date,
+                run.commit_index,
+                run.tree_hash,
+                run.num_cores,
+                run.cpu_model,
+                run.container_image,
+                run.container_digest,
+                run.signature_verified,
+                run.commit_order,
+                run.commit_range,
+                run.assumevalid,
+                run.par,
+                run.from_release,
+                run.tag,
+                run.remote_host
+            ],
         )?;
         debug!(
             "Recorded run on date: {:?} with commit_id: {}, commit_date: {} and was_master: {}",
@@ -104,7 +361,314 @@ impl Database {
         Ok(self.conn.last_insert_rowid())
     }
 
-    pub fn record_job(&self, run_id: i64, result: TimeResult) -> Result<i64> {
+    /// The tree hash of the most recently recorded run, if it has one.
+    pub fn get_last_tree_hash(&self) -> Result<Option<String>> {
+        let tree_hash = self
+            .conn
+            .query_row(
+                "SELECT tree_hash FROM runs ORDER BY run_id DESC LIMIT 1",
+                params![],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(tree_hash.flatten())
+    }
+
+    pub fn set_artifact_path(&self, run_id: i64, artifact_path: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET artifact_path = ? WHERE run_id = ?",
+            params![artifact_path, run_id],
+        )?;
+        debug!("Recorded artifact {} for run {}", artifact_path, run_id);
+        Ok(())
+    }
+
+    /// Record the version of each tool used for a run, `None` if it couldn't be determined.
+    pub fn record_binary_versions(
+        &self,
+        run_id: i64,
+        versions: &[(String, Option<String>)],
+    ) -> Result<()> {
+        for (binary, version) in versions {
+            self.conn.execute(
+                "INSERT INTO run_binaries (run_id, binary, version) VALUES (?, ?, ?)",
+                params![run_id, binary, version],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Record whether the run's build started from a clean source tree, unknown without build
+    /// jobs, and its ccache hits and misses.
+    pub fn set_build_state(
+        &self,
+        run_id: i64,
+        clean_build: Option<bool>,
+        ccache: Option<CcacheStats>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET clean_build = ?, ccache_hits = ?, ccache_misses = ? WHERE run_id = ?",
+            params![
+                clean_build,
+                ccache.map(|stats| stats.hits),
+                ccache.map(|stats| stats.misses),
+                run_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_toolchain(&self, run_id: i64, toolchain: &Toolchain) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET compiler = ?, build_flags = ?, libc = ? WHERE run_id = ?",
+            params![
+                toolchain.compiler,
+                toolchain.build_flags,
+                toolchain.libc,
+                run_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Mark a run as starting from the data dir the previous run of its sweep left behind.
+    pub fn set_datadir_reused(&self, run_id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET datadir_reused = 1 WHERE run_id = ?",
+            params![run_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_smoke_test_passed(&self, run_id: i64, passed: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET smoke_test_passed = ? WHERE run_id = ?",
+            params![passed, run_id],
+        )?;
+        Ok(())
+    }
+
+    /// Record the CPUs the run's jobs were pinned to, as a comma separated list.
+    pub fn set_pinned_cpus(&self, run_id: i64, cpus: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET pinned_cpus = ? WHERE run_id = ?",
+            params![cpus, run_id],
+        )?;
+        Ok(())
+    }
+
+    /// Record whether the CPU was thermally throttled during a run and how hot it got.
+    pub fn set_thermal(
+        &self,
+        run_id: i64,
+        throttled: bool,
+        max_temperature_c: Option<f64>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET throttled = ?, max_temperature_c = ? WHERE run_id = ?",
+            params![throttled, max_temperature_c, run_id],
+        )?;
+        Ok(())
+    }
+
+    /// Mark a run as failing to build, dropping any results its jobs recorded before the failure.
+    pub fn set_build_error(&self, run_id: i64, build_error: &str) -> Result<()> {
+        self.delete_jobs(run_id)?;
+        self.conn.execute(
+            "UPDATE runs SET build_error = ? WHERE run_id = ?",
+            params![build_error, run_id],
+        )?;
+        Ok(())
+    }
+
+    /// Remove a run and everything recorded for it.
+    pub fn delete_run(&self, run_id: i64) -> Result<()> {
+        self.check_not_append_only()?;
+        self.delete_jobs(run_id)?;
+        self.conn
+            .execute("DELETE FROM run_binaries WHERE run_id = ?", params![run_id])?;
+        self.conn
+            .execute("DELETE FROM runs WHERE run_id = ?", params![run_id])?;
+        debug!("Deleted run {}", run_id);
+        Ok(())
+    }
+
+    fn delete_jobs(&self, run_id: i64) -> Result<()> {
+        self.check_not_append_only()?;
+        let jobs = "SELECT job_id FROM jobs WHERE run_id = ?1";
+        self.conn.execute(
+            &format!(
+                "DELETE FROM job_deltas WHERE job_id IN ({0}) OR baseline_job_id IN ({0})",
+                jobs
+            ),
+            params![run_id],
+        )?;
+        for table in ["job_metrics", "job_env"] {
+            self.conn.execute(
+                &format!("DELETE FROM {} WHERE job_id IN ({})", table, jobs),
+                params![run_id],
+            )?;
+        }
+        self.conn
+            .execute("DELETE FROM jobs WHERE run_id = ?", params![run_id])?;
+        Ok(())
+    }
+
+    /// Collapse the successful jobs of runs before `cutoff` into per-day means, minimums and
+    /// maximums in `jobs_archive`, then delete every job of those runs. The runs themselves are
+    /// kept. Archiving again with the same cutoff changes nothing, and jobs imported into an
+    /// already archived day are merged into its aggregates. Returns the number of jobs archived
+    /// and the number of days they fell on.
+    pub fn archive_older_than(&self, cutoff: i64) -> Result<(usize, usize)> {
+        self.check_not_append_only()?;
+        let tx = self.conn.unchecked_transaction()?;
+        let aggregates: Vec<String> = ARCHIVED_COLUMNS
+            .iter()
+            .map(|column| format!("AVG(jobs.{0}), MIN(jobs.{0}), MAX(jobs.{0})", column))
+            .collect();
+        let columns: Vec<String> = ARCHIVED_COLUMNS
+            .iter()
+            .map(|column| format!("{0}_mean, {0}_min, {0}_max", column))
+            .collect();
+        let merges: Vec<String> = ARCHIVED_COLUMNS
+            .iter()
+            .map(|column| {
+                format!(
+                    "{0}_mean = COALESCE(({0}_mean * job_count + excluded.{0}_mean * excluded.job_count)
+                        / (job_count + excluded.job_count), {0}_mean, excluded.{0}_mean),
+                    {0}_min = MIN(COALESCE({0}_min, excluded.{0}_min), COALESCE(excluded.{0}_min, {0}_min)),
+                    {0}_max = MAX(COALESCE({0}_max, excluded.{0}_max), COALESCE(excluded.{0}_max, {0}_max))",
+                    column
+                )
+            })
+            .collect();
+        let days = tx.execute(
+            &format!(
+                "INSERT INTO jobs_archive (job_name, day, was_master, job_count, {})
+                SELECT jobs.job_name, runs.run_date - runs.run_date % 86400 AS day, runs.was_master,
+                    COUNT(*), {}
+                FROM jobs INNER JOIN runs ON jobs.run_id = runs.run_id
+                WHERE runs.run_date < ?1 AND {} AND {}
+                GROUP BY jobs.job_name, day, runs.was_master
+                ON CONFLICT (job_name, day, was_master) DO UPDATE SET
+                    job_count = job_count + excluded.job_count, {}",
+                columns.join(", "),
+                aggregates.join(", "),
+                TIMING_JOBS_FILTER,
+                FULL_RUNS_FILTER,
+                merges.join(",\n")
+            ),
+            params![cutoff],
+        )?;
+        let jobs: usize = tx.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM jobs INNER JOIN runs ON jobs.run_id = runs.run_id
+                WHERE runs.run_date < ?1 AND {} AND {}",
+                TIMING_JOBS_FILTER, FULL_RUNS_FILTER
+            ),
+            params![cutoff],
+            |row| row.get(0),
+        )?;
+        let run_ids = "SELECT run_id FROM runs WHERE run_date < ?1";
+        let job_ids = format!("SELECT job_id FROM jobs WHERE run_id IN ({})", run_ids);
+        tx.execute(
+            &format!(
+                "DELETE FROM job_deltas WHERE job_id IN ({0}) OR baseline_job_id IN ({0})",
+                job_ids
+            ),
+            params![cutoff],
+        )?;
+        for table in ["job_metrics", "job_env"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE job_id IN ({})", table, job_ids),
+                params![cutoff],
+            )?;
+        }
+        tx.execute(
+            &format!("DELETE FROM jobs WHERE run_id IN ({})", run_ids),
+            params![cutoff],
+        )?;
+        tx.commit()?;
+        info!("Archived {} jobs into {} days", jobs, days);
+        Ok((jobs, days))
+    }
+
+    /// Fetch the archived daily means of every job name, each oldest first, as jobs of
+    /// placeholder runs dated at the start of their day. They have no commit, so `commit_id` is
+    /// empty.
+    pub fn get_archived_jobs_grouped(&self) -> Result<BTreeMap<String, Vec<(Job, Run)>>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT * FROM jobs_archive WHERE day >= ? ORDER BY day ASC")?;
+        let mut grouped: BTreeMap<String, Vec<(Job, Run)>> = BTreeMap::new();
+        for job_with_run in stmt.query_map([self.cutoff()], archived_job_from_row)? {
+            let job_with_run = job_with_run?;
+            grouped
+                .entry(job_with_run.0.result.command.clone())
+                .or_default()
+                .push(job_with_run);
+        }
+        Ok(grouped)
+    }
+
+    /// Fetch the archived daily means of `job_name`, as [`Database::get_archived_jobs_grouped`].
+    pub fn get_archived_jobs(&self, job_name: &str) -> Result<Vec<(Job, Run)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM jobs_archive WHERE job_name = ? AND day >= ? ORDER BY day ASC",
+        )?;
+        let jobs_with_runs = stmt
+            .query_map(params![job_name, self.cutoff()], archived_job_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(jobs_with_runs)
+    }
+
+    /// Append a note to a run, on its own line after when it was added.
+    pub fn add_note(&self, run_id: i64, note: &str) -> Result<()> {
+        let line = format!(
+            "{} {}",
+            util::unix_timestamp_to_iso(chrono::Utc::now().timestamp()),
+            note.replace('\n', " ")
+        );
+        let updated = self.conn.execute(
+            "UPDATE runs SET notes = COALESCE(notes || char(10), '') || ? WHERE run_id = ?",
+            params![line, run_id],
+        )?;
+        if updated == 0 {
+            bail!("No run found with id {}", run_id);
+        }
+        Ok(())
+    }
+
+    /// Record the SHA256 of the config file the run used.
+    pub fn set_config_hash(&self, run_id: i64, config_hash: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET config_hash = ? WHERE run_id = ?",
+            params![config_hash, run_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_run_duration(&self, run_id: i64, run_duration: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET run_duration = ? WHERE run_id = ?",
+            params![run_duration, run_id],
+        )?;
+        Ok(())
+    }
+
+    /// Record a job's results. Profiled jobs store their `perf` data path and are excluded from
+    /// timing queries as profiling perturbs the measurements.
+    pub fn record_job(
+        &self,
+        run_id: i64,
+        job_name: &str,
+        result: TimeResult,
+        stats: Option<&JobStats>,
+        profile_path: Option<&str>,
+    ) -> Result<i64> {
+        let user_time = stats.map(|stats| stats.user_time);
+        let max_rss = stats.map(|stats| stats.max_rss);
+        let elapsed = stats.and_then(|stats| stats.elapsed);
         self.conn.execute(
             "INSERT INTO jobs (
                 run_id,
@@ -118,12 +682,27 @@ impl Database {
                 voluntary_context_switches,
                 involuntary_context_switches,
                 file_system_outputs,
-                exit_status
+                exit_status,
+                page_size_bytes,
+                signals_delivered,
+                profile_path,
+                elapsed,
+                user_time_min,
+                user_time_max,
+                user_time_stddev,
+                max_rss_min,
+                max_rss_max,
+                max_rss_stddev,
+                elapsed_min,
+                elapsed_max,
+                elapsed_stddev,
+                read_bytes,
+                write_bytes
             ) VALUES
-            (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
             params![
                 run_id,
-                result.command,
+                job_name,
                 result.user_time,
                 result.system_time,
                 result.percent_of_cpu,
@@ -133,52 +712,101 @@ impl Database {
                 result.voluntary_context_switches,
                 result.involuntary_context_switches,
                 result.file_system_outputs,
-                result.exit_status
+                result.exit_status,
+                result.page_size_bytes,
+                result.signals_delivered,
+                profile_path,
+                result.elapsed,
+                user_time.map(|s| s.min),
+                user_time.map(|s| s.max),
+                user_time.map(|s| s.stddev),
+                max_rss.map(|s| s.min),
+                max_rss.map(|s| s.max),
+                max_rss.map(|s| s.stddev),
+                elapsed.map(|s| s.min),
+                elapsed.map(|s| s.max),
+                elapsed.map(|s| s.stddev),
+                result.read_bytes,
+                result.write_bytes
             ],
         )?;
         debug!("Recorded job: {:?}", result);
         Ok(self.conn.last_insert_rowid())
     }
 
-    pub fn get_jobs_by_name(&self, job_name: &String) -> Result<Vec<(Job, Run)>> {
-        let mut stmt = self.conn.prepare(
-            "
-            SELECT jobs.*, runs.commit_id, runs.run_date, runs.was_master
-            FROM jobs
-            INNER JOIN runs ON jobs.run_id = runs.run_id
-            WHERE job_name = ?
-            ORDER BY jobs.run_id ASC
-        ",
+    /// Record the success criterion a job failed despite exiting 0, excluding it from results.
+    pub fn set_failed_criterion(&self, job_id: i64, criterion: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE jobs SET failed_criterion = ? WHERE job_id = ?",
+            params![criterion, job_id],
         )?;
+        Ok(())
+    }
 
-        let job_iter = stmt.query_map([job_name], |row| {
-            Ok((
-                Job {
-                    job_id: row.get(0)?,
-                    run_id: row.get(1)?,
-                    result: TimeResult {
-                        command: row.get(2)?,
-                        user_time: row.get(3)?,
-                        system_time: row.get(4)?,
-                        percent_of_cpu: row.get(5)?,
-                        max_resident_set_size_kb: row.get(6)?,
-                        major_page_faults: row.get(7)?,
-                        minor_page_faults: row.get(8)?,
-                        voluntary_context_switches: row.get(9)?,
-                        involuntary_context_switches: row.get(10)?,
-                        file_system_outputs: row.get(11)?,
-                        exit_status: row.get(12)?,
-                    },
-                },
-                Run {
-                    id: Some(row.get(1)?),
-                    run_date: row.get(14)?,
-                    commit_id: row.get(13)?,
-                    commit_date: row.get(15)?,
-                    was_master: row.get(16)?,
-                },
-            ))
-        })?;
+    /// Store the time report a job's results were parsed from, so `verify` can parse it again.
+    pub fn set_raw_time_output(&self, job_id: i64, output: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE jobs SET raw_time_output = ? WHERE job_id = ?",
+            params![output, job_id],
+        )?;
+        Ok(())
+    }
+
+    /// Number a job recorded as one of several executions of the same job in its run.
+    pub fn set_sample_index(&self, job_id: i64, sample_index: u32) -> Result<()> {
+        self.conn.execute(
+            "UPDATE jobs SET sample_index = ? WHERE job_id = ?",
+            params![sample_index, job_id],
+        )?;
+        Ok(())
+    }
+
+    /// Reference the CSV file of RSS samples taken while a job ran.
+    pub fn set_memory_timeline_path(&self, job_id: i64, path: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE jobs SET memory_timeline_path = ? WHERE job_id = ?",
+            params![path, job_id],
+        )?;
+        Ok(())
+    }
+
+    /// Record additional named metrics for a job which don't have their own column.
+    pub fn record_job_metrics(&self, job_id: i64, metrics: &[(String, f64)]) -> Result<()> {
+        for (name, value) in metrics {
+            self.conn.execute(
+                "INSERT INTO job_metrics (job_id, name, value) VALUES (?, ?, ?)",
+                params![job_id, name, value],
+            )?;
+        }
+        debug!("Recorded {} metrics for job {}", metrics.len(), job_id);
+        Ok(())
+    }
+
+    /// Record the environment a job ran with, as name and value pairs.
+    pub fn record_job_env(&self, job_id: i64, env: &[(String, String)]) -> Result<()> {
+        for (name, value) in env {
+            self.conn.execute(
+                "INSERT INTO job_env (job_id, name, value) VALUES (?, ?, ?)",
+                params![job_id, name, value],
+            )?;
+        }
+        debug!(
+            "Recorded {} environment variables for job {}",
+            env.len(),
+            job_id
+        );
+        Ok(())
+    }
+
+    pub fn get_jobs_by_name(&self, job_name: &String) -> Result<Vec<(Job, Run)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "{} WHERE job_name = ? AND runs.run_date >= ? AND {} AND {} ORDER BY jobs.run_id ASC",
+            jobs_with_runs_query(),
+            TIMING_JOBS_FILTER,
+            FULL_RUNS_FILTER
+        ))?;
+
+        let job_iter = stmt.query_map(params![job_name, self.cutoff()], job_with_run_from_row)?;
 
         let mut jobs_with_runs = Vec::new();
         for job in job_iter {
@@ -188,4 +816,480 @@ impl Database {
 
         Ok(jobs_with_runs)
     }
+
+    /// Fetch successful jobs named `job_name` run against any commit starting with `commit`.
+    pub fn get_jobs_by_commit(&self, commit: &str, job_name: &str) -> Result<Vec<(Job, Run)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "{} WHERE runs.commit_id LIKE ? || '%' AND job_name = ? AND {} AND {}
+            ORDER BY jobs.run_id ASC",
+            jobs_with_runs_query(),
+            TIMING_JOBS_FILTER,
+            FULL_RUNS_FILTER
+        ))?;
+
+        let jobs_with_runs = stmt
+            .query_map(params![commit, job_name], job_with_run_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        debug!(
+            "Got {} jobs for commit {} and job {}",
+            jobs_with_runs.len(),
+            commit,
+            job_name
+        );
+
+        Ok(jobs_with_runs)
+    }
+
+    /// Fetch the successful, unprofiled jobs of a run.
+    pub fn get_jobs_for_run(&self, run_id: i64) -> Result<Vec<(Job, Run)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "{} WHERE jobs.run_id = ? AND {} ORDER BY jobs.job_id ASC",
+            jobs_with_runs_query(),
+            TIMING_JOBS_FILTER
+        ))?;
+        let jobs_with_runs = stmt
+            .query_map([run_id], job_with_run_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(jobs_with_runs)
+    }
+
+    /// Record the percent change of a job's metric relative to a baseline job.
+    pub fn record_job_delta(
+        &self,
+        job_id: i64,
+        baseline_job_id: i64,
+        metric: &str,
+        percent_change: f64,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO job_deltas (job_id, baseline_job_id, metric, percent_change)
+            VALUES (?, ?, ?, ?)",
+            params![job_id, baseline_job_id, metric, percent_change],
+        )?;
+        Ok(())
+    }
+
+    /// Fetch the successful, unprofiled jobs of every job name in one query, each oldest first.
+    pub fn get_all_jobs_grouped(&self) -> Result<BTreeMap<String, Vec<(Job, Run)>>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "{} WHERE runs.run_date >= ? AND {} AND {} ORDER BY jobs.run_id ASC",
+            jobs_with_runs_query(),
+            TIMING_JOBS_FILTER,
+            FULL_RUNS_FILTER
+        ))?;
+        let mut grouped: BTreeMap<String, Vec<(Job, Run)>> = BTreeMap::new();
+        for job_with_run in stmt.query_map([self.cutoff()], job_with_run_from_row)? {
+            let job_with_run = job_with_run?;
+            grouped
+                .entry(job_with_run.0.result.command.clone())
+                .or_default()
+                .push(job_with_run);
+        }
+        debug!("Got jobs for {} job names", grouped.len());
+        Ok(grouped)
+    }
+
+    /// Fetch every unprofiled job, including failed ones, oldest first.
+    pub fn get_all_jobs(&self) -> Result<Vec<(Job, Run)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "{} WHERE jobs.profile_path IS NULL AND runs.run_date >= ? ORDER BY jobs.job_id ASC",
+            jobs_with_runs_query()
+        ))?;
+        let jobs_with_runs = stmt
+            .query_map([self.cutoff()], job_with_run_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(jobs_with_runs)
+    }
+
+    /// Fetch every job with a stored time report, along with the report, oldest first.
+    pub fn get_jobs_with_raw_time_output(&self) -> Result<Vec<(Job, Run, String)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "{} WHERE jobs.raw_time_output IS NOT NULL ORDER BY jobs.job_id ASC",
+            jobs_with_runs_query()
+        ))?;
+        let jobs = stmt
+            .query_map([], |row| {
+                let (job, run) = job_with_run_from_row(row)?;
+                Ok((job, run, row.get("raw_time_output")?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(jobs)
+    }
+
+    /// The id of a run of `commit_id` started at `run_date`, if one was recorded.
+    pub fn find_run(&self, commit_id: &str, run_date: i64) -> Result<Option<i64>> {
+        let run_id = self
+            .conn
+            .query_row(
+                "SELECT run_id FROM runs WHERE commit_id = ? AND run_date = ?",
+                params![commit_id, run_date],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(run_id)
+    }
+
+    /// Whether `run_id` already recorded `job_name`, or that sample of it.
+    pub fn has_job(&self, run_id: i64, job_name: &str, sample_index: Option<u32>) -> Result<bool> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM jobs WHERE run_id = ? AND job_name = ? AND sample_index IS ?",
+            params![run_id, job_name, sample_index],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    /// Fetch all runs, oldest first, with the number of jobs recorded for each.
+    pub fn get_runs(&self) -> Result<Vec<(Run, i64)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "
+            SELECT {}, COUNT(jobs.job_id) AS job_count
+            FROM runs
+            LEFT JOIN jobs ON jobs.run_id = runs.run_id
+            WHERE runs.run_date >= ?
+            GROUP BY runs.run_id
+            ORDER BY runs.run_id ASC
+        ",
+            RUN_COLUMNS
+        ))?;
+
+        let runs = stmt
+            .query_map([self.cutoff()], |row| {
+                Ok((run_from_row(row)?, row.get("job_count")?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        debug!("Got {} runs", runs.len());
+
+        Ok(runs)
+    }
+
+    pub fn get_run(&self, run_id: i64) -> Result<Run> {
+        self.conn
+            .query_row(
+                &format!("SELECT {} FROM runs WHERE run_id = ?", RUN_COLUMNS),
+                params![run_id],
+                run_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("No run found with id {}", run_id))
+    }
+
+    /// Every column of the rows in `table` whose `column` is `id`, as JSON objects so columns
+    /// added by later migrations are included without listing them.
+    pub fn get_rows_json(
+        &self,
+        table: &str,
+        column: &str,
+        id: i64,
+    ) -> Result<Vec<serde_json::Map<String, serde_json::Value>>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT * FROM {} WHERE {} = ?", table, column))?;
+        let names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+        let rows = stmt
+            .query_map(params![id], |row| {
+                names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        let value = match row.get_ref(i)? {
+                            ValueRef::Null | ValueRef::Blob(_) => serde_json::Value::Null,
+                            ValueRef::Integer(n) => n.into(),
+                            ValueRef::Real(x) => x.into(),
+                            ValueRef::Text(text) => {
+                                String::from_utf8_lossy(text).into_owned().into()
+                            }
+                        };
+                        Ok((name.clone(), value))
+                    })
+                    .collect()
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    /// Mean wall time of the successful runs of `job_name`, `None` if it has none recorded.
+    pub fn get_mean_elapsed(&self, job_name: &str) -> Result<Option<f64>> {
+        let mean = self.conn.query_row(
+            &format!(
+                "SELECT AVG(elapsed) FROM jobs WHERE job_name = ? AND elapsed IS NOT NULL AND {}",
+                TIMING_JOBS_FILTER
+            ),
+            params![job_name],
+            |row| row.get(0),
+        )?;
+        Ok(mean)
+    }
+
+    pub fn get_job_names(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT job_name FROM jobs ORDER BY job_name ASC")?;
+        let names = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(names)
+    }
+}
+
+/// Columns of `runs` in the form expected by `run_from_row`. `commit_date` is stored as TEXT.
+const RUN_COLUMNS: &str = "runs.run_id, runs.run_date, runs.commit_id, runs.commit_index,
+    runs.tree_hash, runs.was_master, runs.num_cores, runs.cpu_model, runs.container_image,
+    runs.container_digest, runs.signature_verified, runs.commit_order,
+    runs.commit_range, runs.run_duration, runs.assumevalid, runs.par, runs.notes,
+    runs.from_release, runs.tag, runs.remote_host, runs.throttled, runs.max_temperature_c,
+    runs.datadir_reused, CAST(runs.commit_date AS INTEGER) AS commit_date";
+
+fn jobs_with_runs_query() -> String {
+    format!(
+        "SELECT jobs.*, {} FROM jobs INNER JOIN runs ON jobs.run_id = runs.run_id",
+        RUN_COLUMNS
+    )
+}
+
+/// Only successful, unprofiled jobs are comparable timing results
+const TIMING_JOBS_FILTER: &str =
+    "jobs.exit_status = 0 AND jobs.failed_criterion IS NULL AND jobs.profile_path IS NULL";
+
+/// Runs which reused the data dir of the run before measure incremental work, so aren't
+/// comparable with the full syncs which graphs, checks and reports compare
+const FULL_RUNS_FILTER: &str = "runs.datadir_reused = 0";
+
+/// Numeric columns of `jobs` which `jobs_archive` keeps daily aggregates of, one per [`Metric`].
+///
+/// [`Metric`]: crate::result::Metric
+const ARCHIVED_COLUMNS: [&str; 12] = [
+    "user_time",
+    "system_time",
+    "percent_of_cpu",
+    "max_resident_set_size_kb",
+    "major_page_faults",
+    "minor_page_faults",
+    "voluntary_context_switches",
+    "involuntary_context_switches",
+    "file_system_outputs",
+    "elapsed",
+    "read_bytes",
+    "write_bytes",
+];
+
+/// A `jobs_archive` row as a job holding the day's means, in a run dated at the start of the day.
+fn archived_job_from_row(row: &Row) -> rusqlite::Result<(Job, Run)> {
+    let mean = |column: &str| row.get::<_, Option<f64>>(format!("{}_mean", column).as_str());
+    let int_mean = |column: &str| -> rusqlite::Result<i64> {
+        Ok(mean(column)?.unwrap_or_default().round() as i64)
+    };
+    let day = row.get("day")?;
+    let result = TimeResult {
+        command: row.get("job_name")?,
+        user_time: mean("user_time")?.unwrap_or_default(),
+        system_time: mean("system_time")?.unwrap_or_default(),
+        percent_of_cpu: int_mean("percent_of_cpu")? as i32,
+        max_resident_set_size_kb: int_mean("max_resident_set_size_kb")?,
+        major_page_faults: int_mean("major_page_faults")?,
+        minor_page_faults: int_mean("minor_page_faults")?,
+        voluntary_context_switches: int_mean("voluntary_context_switches")?,
+        involuntary_context_switches: int_mean("involuntary_context_switches")?,
+        file_system_outputs: int_mean("file_system_outputs")?,
+        elapsed: mean("elapsed")?,
+        read_bytes: mean("read_bytes")?.map(|bytes| bytes.round() as i64),
+        write_bytes: mean("write_bytes")?.map(|bytes| bytes.round() as i64),
+        ..Default::default()
+    };
+    Ok((
+        Job {
+            job_id: 0,
+            run_id: 0,
+            result,
+            stats: None,
+            sample_index: None,
+        },
+        Run {
+            id: None,
+            run_date: day,
+            commit_id: String::new(),
+            commit_date: day,
+            commit_index: None,
+            tree_hash: None,
+            container_image: None,
+            container_digest: None,
+            signature_verified: None,
+            commit_order: None,
+            commit_range: None,
+            tag: None,
+            run_duration: None,
+            assumevalid: None,
+            par: None,
+            notes: None,
+            from_release: false,
+            was_master: row.get("was_master")?,
+            num_cores: None,
+            cpu_model: None,
+            remote_host: None,
+            throttled: None,
+            max_temperature_c: None,
+            datadir_reused: false,
+        },
+    ))
+}
+
+fn job_with_run_from_row(row: &Row) -> rusqlite::Result<(Job, Run)> {
+    Ok((
+        Job {
+            job_id: row.get("job_id")?,
+            run_id: row.get("run_id")?,
+            result: time_result_from_row(row)?,
+            stats: job_stats_from_row(row)?,
+            sample_index: row.get("sample_index")?,
+        },
+        run_from_row(row)?,
+    ))
+}
+
+fn spread_from_row(row: &Row, prefix: &str) -> rusqlite::Result<Option<Spread>> {
+    let min: Option<f64> = row.get(format!("{}_min", prefix).as_str())?;
+    let max: Option<f64> = row.get(format!("{}_max", prefix).as_str())?;
+    let stddev: Option<f64> = row.get(format!("{}_stddev", prefix).as_str())?;
+    Ok(min
+        .zip(max)
+        .zip(stddev)
+        .map(|((min, max), stddev)| Spread { min, max, stddev }))
+}
+
+fn job_stats_from_row(row: &Row) -> rusqlite::Result<Option<JobStats>> {
+    let user_time = spread_from_row(row, "user_time")?;
+    let max_rss = spread_from_row(row, "max_rss")?;
+    let elapsed = spread_from_row(row, "elapsed")?;
+    Ok(user_time.zip(max_rss).map(|(user_time, max_rss)| JobStats {
+        user_time,
+        max_rss,
+        elapsed,
+    }))
+}
+
+fn run_from_row(row: &Row) -> rusqlite::Result<Run> {
+    Ok(Run {
+        id: Some(row.get("run_id")?),
+        run_date: row.get("run_date")?,
+        commit_id: row.get("commit_id")?,
+        commit_date: row.get("commit_date")?,
+        commit_index: row.get("commit_index")?,
+        tree_hash: row.get("tree_hash")?,
+        container_image: row.get("container_image")?,
+        container_digest: row.get("container_digest")?,
+        signature_verified: row.get("signature_verified")?,
+        commit_order: row.get("commit_order")?,
+        commit_range: row.get("commit_range")?,
+        tag: row.get("tag")?,
+        run_duration: row.get("run_duration")?,
+        assumevalid: row.get("assumevalid")?,
+        par: row.get("par")?,
+        notes: row.get("notes")?,
+        from_release: row.get("from_release")?,
+        was_master: row.get("was_master")?,
+        num_cores: row.get("num_cores")?,
+        cpu_model: row.get("cpu_model")?,
+        remote_host: row.get("remote_host")?,
+        throttled: row.get("throttled")?,
+        max_temperature_c: row.get("max_temperature_c")?,
+        datadir_reused: row.get("datadir_reused")?,
+    })
+}
+
+fn time_result_from_row(row: &Row) -> rusqlite::Result<TimeResult> {
+    Ok(TimeResult {
+        command: row.get("job_name")?,
+        user_time: row.get("user_time")?,
+        system_time: row.get("system_time")?,
+        percent_of_cpu: row.get("percent_of_cpu")?,
+        max_resident_set_size_kb: row.get("max_resident_set_size_kb")?,
+        major_page_faults: row.get("major_page_faults")?,
+        minor_page_faults: row.get("minor_page_faults")?,
+        voluntary_context_switches: row.get("voluntary_context_switches")?,
+        involuntary_context_switches: row.get("involuntary_context_switches")?,
+        file_system_outputs: row.get("file_system_outputs")?,
+        exit_status: row.get("exit_status")?,
+        page_size_bytes: row.get("page_size_bytes")?,
+        signals_delivered: row.get("signals_delivered")?,
+        elapsed: row.get("elapsed")?,
+        read_bytes: row.get("read_bytes")?,
+        write_bytes: row.get("write_bytes")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{job_with_run, DAY, FIRST_COMMIT_DATE};
+    use tempdir::TempDir;
+
+    fn record(db: &Database, run_date: i64, user_time: f64) {
+        let (_, run) = job_with_run(0, run_date);
+        let run_id = db.record_run(run).unwrap();
+        let result = TimeResult {
+            command: "IBD".to_string(),
+            user_time,
+            ..Default::default()
+        };
+        db.record_job(run_id, "IBD", result, None, None).unwrap();
+    }
+
+    fn user_times(jobs_with_runs: &[(Job, Run)]) -> Vec<(i64, f64)> {
+        jobs_with_runs
+            .iter()
+            .map(|(job, run)| (run.run_date, job.result.user_time))
+            .collect()
+    }
+
+    fn archived_spread(db: &Database) -> (i64, f64, f64) {
+        db.conn
+            .query_row(
+                "SELECT job_count, user_time_min, user_time_max FROM jobs_archive",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn archives_jobs_older_than_cutoff() {
+        let dir = TempDir::new("archive").unwrap();
+        let db = Database::create_or_load(
+            &dir.path().to_string_lossy(),
+            "db.sqlite",
+            DbDurability::Normal,
+        )
+        .unwrap();
+        let day = FIRST_COMMIT_DATE - FIRST_COMMIT_DATE % DAY;
+        let cutoff = day + DAY;
+        record(&db, day + 100, 10.0);
+        record(&db, day + 200, 20.0);
+        record(&db, cutoff, 30.0);
+
+        assert_eq!(db.archive_older_than(cutoff).unwrap(), (2, 1));
+        assert_eq!(
+            user_times(&db.get_archived_jobs("IBD").unwrap()),
+            [(day, 15.0)]
+        );
+        assert_eq!(archived_spread(&db), (2, 10.0, 20.0));
+        assert_eq!(user_times(&db.get_all_jobs().unwrap()), [(cutoff, 30.0)]);
+
+        // Archiving again changes nothing
+        assert_eq!(db.archive_older_than(cutoff).unwrap(), (0, 0));
+        assert_eq!(
+            user_times(&db.get_archived_jobs("IBD").unwrap()),
+            [(day, 15.0)]
+        );
+        assert_eq!(archived_spread(&db), (2, 10.0, 20.0));
+
+        // A job imported into an archived day is merged into its aggregates
+        record(&db, day + 300, 45.0);
+        assert_eq!(db.archive_older_than(cutoff).unwrap(), (1, 1));
+        assert_eq!(
+            user_times(&db.get_archived_jobs("IBD").unwrap()),
+            [(day, 25.0)]
+        );
+        assert_eq!(archived_spread(&db), (3, 10.0, 45.0));
+        assert_eq!(user_times(&db.get_all_jobs().unwrap()), [(cutoff, 30.0)]);
+    }
 }
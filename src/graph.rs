@@ -1,151 +1,1314 @@
-use anyhow::Result;
-use log::{debug, info};
-use plotters::{prelude::*, style::full_palette::PURPLE};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Datelike, Duration};
+use clap::ValueEnum;
+use log::{debug, info, warn};
+use plotters::{
+    coord::{
+        ranged1d::{AsRangedCoord, ValueFormatter},
+        Shift,
+    },
+    element::DashedPathElement,
+    prelude::*,
+    style::{
+        full_palette::{ORANGE, PURPLE},
+        text_anchor::{HPos, Pos, VPos},
+    },
+};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
 
-use crate::database::Database;
+use crate::database::{Database, Job, Run};
+use crate::result::{mean_and_stddev, Metric};
+use crate::util;
 
-pub fn plot_job_metrics(db: &Database, output_path: &str) -> Result<()> {
-    let job_name = "IBD".to_string();
-    info!("Starting graph for {}", job_name);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum XAxis {
+    /// Date of the run
+    Date,
+    /// Commit sequence number
+    Commit,
+    /// Tag of runs from a sweep of tags, in the order of their commits' dates
+    Tag,
+}
+
+/// Calendar period to group date points into before plotting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Aggregate {
+    /// Plot every run
+    Daily,
+    /// Mean per ISO week
+    Weekly,
+    /// Mean per calendar month
+    Monthly,
+}
+
+/// How `--deviation` plots each point against the mean of the job's history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Deviation {
+    /// Standard deviations from the mean
+    Zscore,
+    /// Percent above or below the mean
+    Percent,
+}
+
+/// Standard deviations from the mean beyond which `--deviation` highlights a point.
+const OUTLIER_STDDEVS: f64 = 2.0;
+
+/// Colour scheme of the generated graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Theme {
+    /// Dark lines on a white background
+    Light,
+    /// Light lines on a dark background, for embedding in dark dashboards
+    Dark,
+}
+
+/// Colours used to draw a graph in a [`Theme`]. Gridlines are drawn in a faded `foreground`.
+struct Palette {
+    background: RGBColor,
+    foreground: RGBColor,
+    master: RGBColor,
+    non_master: RGBColor,
+    secondary_master: RGBColor,
+    secondary_non_master: RGBColor,
+    /// Rings around `--deviation` outliers
+    outlier: RGBColor,
+}
+
+impl Theme {
+    fn palette(&self) -> Palette {
+        match self {
+            Theme::Light => Palette {
+                background: WHITE,
+                foreground: BLACK,
+                master: RED,
+                non_master: BLUE,
+                secondary_master: GREEN,
+                secondary_non_master: PURPLE,
+                outlier: ORANGE,
+            },
+            Theme::Dark => Palette {
+                background: RGBColor(30, 30, 30),
+                foreground: RGBColor(220, 220, 220),
+                master: RGBColor(255, 99, 71),
+                non_master: RGBColor(100, 149, 237),
+                secondary_master: RGBColor(50, 205, 50),
+                secondary_non_master: RGBColor(218, 112, 214),
+                outlier: RGBColor(255, 215, 0),
+            },
+        }
+    }
+}
+
+/// Metric plotted on the secondary y-axis, `none` to plot only the primary metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecondaryMetric(pub Option<Metric>);
+
+impl std::str::FromStr for SecondaryMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(SecondaryMetric(None)),
+            _ => Ok(SecondaryMetric(Some(Metric::try_from(s.to_string())?))),
+        }
+    }
+}
+
+pub struct GraphOptions<'a> {
+    pub job_name: &'a str,
+    /// Metric on the primary y-axis
+    pub metric: Metric,
+    /// Metric on the secondary y-axis, if any
+    pub secondary: Option<Metric>,
+    pub log_scale: bool,
+    pub x_axis: XAxis,
+    pub aggregate: Aggregate,
+    pub whiskers: bool,
+    pub error_bars: bool,
+    pub theme: Theme,
+    /// Label the y-axes with plain numbers rather than human readable units
+    pub raw: bool,
+    /// Dates and labels of external events, e.g. releases, marked on date x-axes
+    pub events: &'a [(i64, String)],
+    /// Plot the primary metric's deviation from its mean instead of its values
+    pub deviation: Option<Deviation>,
+}
+
+/// Read the dates and labels of events to mark on graphs from `path`. A `.csv` file has a
+/// `date,label` line per event, anything else is read as TOML of `"date" = "label"` pairs.
+pub fn read_events(path: &Path) -> Result<Vec<(i64, String)>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read annotations {}: {}", path.display(), e))?;
+    let pairs: Vec<(String, String)> = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("csv") => contents
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .filter(|(_, line)| *line != "date,label")
+            .map(|(number, line)| {
+                let (date, label) = line.split_once(',').ok_or_else(|| {
+                    anyhow!("Line {} of {} isn't date,label", number, path.display())
+                })?;
+                Ok((date.to_string(), label.trim().trim_matches('"').to_string()))
+            })
+            .collect::<Result<_>>()?,
+        _ => toml::from_str::<BTreeMap<String, String>>(&contents)
+            .map_err(|e| anyhow!("Failed to parse annotations {}: {}", path.display(), e))?
+            .into_iter()
+            .collect(),
+    };
+    let mut events = pairs
+        .into_iter()
+        .map(|(date, label)| {
+            let date = util::parse_date_with_format(&date, None)
+                .map_err(|e| anyhow!("Bad annotation date '{}': {}", date.trim(), e))?;
+            Ok((date, label))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    events.sort();
+    Ok(events)
+}
+
+/// Summary of the points falling into one period, keyed by the start of the period.
+#[derive(Debug, PartialEq)]
+struct Bucket {
+    x: i64,
+    mean: f64,
+    min: f64,
+    max: f64,
+}
+
+/// Unix timestamp of midnight UTC at the start of the period containing `timestamp`.
+fn period_start(timestamp: i64, aggregate: Aggregate) -> i64 {
+    let date = DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .date_naive();
+    let start = match aggregate {
+        Aggregate::Daily => date,
+        Aggregate::Weekly => date - Duration::days(date.weekday().num_days_from_monday().into()),
+        Aggregate::Monthly => date.with_day(1).unwrap_or(date),
+    };
+    start.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp()
+}
+
+fn bucket_points(points: &[(i64, f64)], aggregate: Aggregate) -> Vec<Bucket> {
+    let mut sorted = points.to_vec();
+    sorted.sort_by_key(|(x, _)| *x);
+
+    let mut groups: Vec<(i64, Vec<f64>)> = Vec::new();
+    for (x, y) in sorted {
+        let start = period_start(x, aggregate);
+        match groups.last_mut() {
+            Some((group_start, values)) if *group_start == start => values.push(y),
+            _ => groups.push((start, vec![y])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(x, values)| Bucket {
+            x,
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        })
+        .collect()
+}
+
+/// Data points for one graph, split by whether the run was on master.
+struct Series {
+    master: Vec<(i64, f64)>,
+    non_master: Vec<(i64, f64)>,
+    /// Per-period ranges, only populated once the series has been aggregated
+    master_buckets: Vec<Bucket>,
+    non_master_buckets: Vec<Bucket>,
+    /// ±1 stddev around points with a recorded spread, as (x, low, value, high)
+    error_bars: Vec<(i64, f64, f64, f64)>,
+}
+
+impl Series {
+    fn new(
+        jobs_with_runs: &[(Job, Run)],
+        x: impl Fn(&Run) -> i64,
+        value: impl Fn(&Job) -> Option<f64>,
+    ) -> Self {
+        let (master, non_master): (Vec<_>, Vec<_>) =
+            jobs_with_runs.iter().partition(|(_, run)| run.was_master);
+        // Runs which recorded each execution of the job as a sample get the mean of them
+        let points = |jobs: Vec<&(Job, Run)>| {
+            let mut runs: Vec<(Option<i32>, i64, f64, usize)> = Vec::new();
+            // Jobs which didn't measure the metric are left out rather than plotted at 0
+            for (job, run) in jobs {
+                let Some(value) = value(job) else {
+                    continue;
+                };
+                match runs.last_mut() {
+                    Some((id, _, sum, samples)) if id.is_some() && *id == run.id => {
+                        *sum += value;
+                        *samples += 1;
+                    }
+                    _ => runs.push((run.id, x(run), value, 1)),
+                }
+            }
+            runs.into_iter()
+                .map(|(_, x, sum, samples)| (x, sum / samples as f64))
+                .collect()
+        };
+        Series {
+            master: points(master),
+            non_master: points(non_master),
+            master_buckets: Vec::new(),
+            non_master_buckets: Vec::new(),
+            error_bars: Vec::new(),
+        }
+    }
 
-    let jobs_with_runs = db.get_jobs_by_name(&job_name)?;
+    /// Add error bars for jobs which recorded a nonzero stddev for `metric`, clamping the lower
+    /// end to `floor`.
+    fn add_error_bars(
+        &mut self,
+        jobs_with_runs: &[(Job, Run)],
+        x: impl Fn(&Run) -> i64,
+        metric: Metric,
+        floor: f64,
+    ) {
+        self.error_bars = jobs_with_runs
+            .iter()
+            .filter_map(|(job, run)| {
+                let stddev = job.stats?.spread(metric)?.stddev;
+                let value = metric.value(&job.result)?;
+                (stddev > 0.0).then(|| (x(run), (value - stddev).max(floor), value, value + stddev))
+            })
+            .collect();
+    }
+
+    /// Replace the points with the mean of each period.
+    fn aggregate(&mut self, aggregate: Aggregate) {
+        if aggregate == Aggregate::Daily {
+            return;
+        }
+        let means = |buckets: &[Bucket]| buckets.iter().map(|b| (b.x, b.mean)).collect();
+        self.master_buckets = bucket_points(&self.master, aggregate);
+        self.non_master_buckets = bucket_points(&self.non_master, aggregate);
+        self.master = means(&self.master_buckets);
+        self.non_master = means(&self.non_master_buckets);
+    }
+
+    fn max(&self) -> f64 {
+        self.master
+            .iter()
+            .chain(&self.non_master)
+            .map(|(_, y)| *y)
+            .chain(
+                self.master_buckets
+                    .iter()
+                    .chain(&self.non_master_buckets)
+                    .map(|b| b.max),
+            )
+            .chain(self.error_bars.iter().map(|(_, _, _, high)| *high))
+            .fold(0.0, f64::max)
+    }
+
+    /// Replace each point with its deviation from the mean of all the points, returning the
+    /// deviation `OUTLIER_STDDEVS` standard deviations away, or `None` if the points don't vary.
+    fn deviate(&mut self, deviation: Deviation) -> Option<f64> {
+        let values: Vec<f64> = self
+            .master
+            .iter()
+            .chain(&self.non_master)
+            .map(|(_, y)| *y)
+            .collect();
+        let (mean, stddev) = mean_and_stddev(&values)?;
+        let scale = match deviation {
+            Deviation::Zscore => stddev,
+            Deviation::Percent => mean.abs() / 100.0,
+        };
+        for (_, y) in self.master.iter_mut().chain(&mut self.non_master) {
+            *y = match scale > 0.0 {
+                true => (*y - mean) / scale,
+                false => 0.0,
+            };
+        }
+        (stddev > 0.0 && scale > 0.0).then(|| OUTLIER_STDDEVS * stddev / scale)
+    }
+
+    /// Largest distance of any point, period or error bar from zero.
+    fn max_abs(&self) -> f64 {
+        self.master
+            .iter()
+            .chain(&self.non_master)
+            .map(|(_, y)| *y)
+            .chain(
+                self.master_buckets
+                    .iter()
+                    .chain(&self.non_master_buckets)
+                    .flat_map(|b| [b.min, b.max]),
+            )
+            .map(f64::abs)
+            .fold(0.0, f64::max)
+    }
+
+    /// Drop points which can't be drawn on a log axis, returning the smallest remaining value.
+    fn retain_positive(&mut self) -> f64 {
+        let before = self.master.len() + self.non_master.len();
+        self.master.retain(|(_, y)| *y > 0.0);
+        self.non_master.retain(|(_, y)| *y > 0.0);
+        let dropped = before - self.master.len() - self.non_master.len();
+        if dropped > 0 {
+            warn!(
+                "Dropped {} non-positive values from log scale graph",
+                dropped
+            );
+        }
+        self.master
+            .iter()
+            .chain(&self.non_master)
+            .map(|(_, y)| *y)
+            .fold(f64::INFINITY, f64::min)
+    }
+}
+
+/// Plot the runs of `options.job_name` dated between the midnights starting `from` and ending
+/// `to`, or its full history if neither is given.
+pub fn plot_job_metrics(
+    db: &Database,
+    output_path: &str,
+    options: &GraphOptions,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<()> {
+    let job_name = options.job_name;
+    let mut jobs_with_runs = db.get_archived_jobs(job_name)?;
+    jobs_with_runs.extend(db.get_jobs_by_name(&job_name.to_string())?);
     debug!(
         "Got {} jobs from the database for {}",
         jobs_with_runs.len(),
         job_name
     );
+    let jobs_with_runs = select_runs(jobs_with_runs, options, from, to)?;
+    plot_jobs(&jobs_with_runs, output_path, options)
+}
 
-    let file_path = format!(
+/// The runs of `options.job_name` which belong on its graph: those dated between the midnights
+/// starting `from` and ending `to`, and only those with a tag on a tag x-axis.
+fn select_runs(
+    mut jobs_with_runs: Vec<(Job, Run)>,
+    options: &GraphOptions,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Vec<(Job, Run)>> {
+    let job_name = options.job_name;
+    if options.x_axis == XAxis::Tag {
+        // Runs which weren't selected by a tag have no place on the axis
+        jobs_with_runs.retain(|(_, run)| run.tag.is_some());
+        if jobs_with_runs.len() < 2 {
+            bail!(
+                "Only {} runs of {} have a tag, at least two are needed to plot",
+                jobs_with_runs.len(),
+                job_name
+            );
+        }
+    }
+    if from.is_some() || to.is_some() {
+        jobs_with_runs.retain(|(_, run)| {
+            from.is_none_or(|from| run.run_date >= from)
+                && to.is_none_or(|to| run.run_date < to + 24 * 60 * 60)
+        });
+        if jobs_with_runs.len() < 2 {
+            bail!(
+                "Only {} runs of {} between {} and {}, at least two are needed to plot",
+                jobs_with_runs.len(),
+                job_name,
+                from.map_or("the first run".to_string(), util::unix_timestamp_to_hr),
+                to.map_or("the last run".to_string(), util::unix_timestamp_to_hr),
+            );
+        }
+    }
+    Ok(jobs_with_runs)
+}
+
+/// Path of the PNG graph of `job_name` in `output_path`.
+fn graph_path(output_path: &str, job_name: &str) -> String {
+    format!(
         "{}/{}.png",
         output_path,
         job_name.replace("./", "").replace(' ', "_")
-    );
+    )
+}
+
+/// Plot every job in `grouped` on up to `threads` threads, with the options `options` gives for
+/// each job name, selecting runs between `from` and `to` as `plot_job_metrics` does. A graph which
+/// fails or panics doesn't stop the others, the failures are reported once all have been
+/// attempted. Jobs whose names map to the same file are not plotted.
+pub fn plot_all_jobs<F>(
+    grouped: &BTreeMap<String, Vec<(Job, Run)>>,
+    output_path: &str,
+    threads: usize,
+    from: Option<i64>,
+    to: Option<i64>,
+    options: F,
+) -> Result<()>
+where
+    F: for<'a> Fn(&'a str) -> GraphOptions<'a> + Sync,
+{
+    let mut by_path: HashMap<String, Vec<&str>> = HashMap::new();
+    for job_name in grouped.keys() {
+        by_path
+            .entry(graph_path(output_path, job_name))
+            .or_default()
+            .push(job_name);
+    }
+    let mut results: Vec<(&str, Result<()>)> = Vec::new();
+    let mut queue = Vec::new();
+    for (job_name, jobs_with_runs) in grouped {
+        match by_path[&graph_path(output_path, job_name)].as_slice() {
+            [_] => queue.push((job_name.as_str(), jobs_with_runs)),
+            names => results.push((
+                job_name,
+                Err(anyhow!(
+                    "jobs {} would all be written to the same file",
+                    names.join(", ")
+                )),
+            )),
+        }
+    }
+
+    let queue = Mutex::new(queue.into_iter());
+    let results = Mutex::new(results);
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                let Some((job_name, jobs_with_runs)) = next else {
+                    break;
+                };
+                let result = catch_panic(|| {
+                    let options = options(job_name);
+                    let jobs_with_runs = select_runs(jobs_with_runs.clone(), &options, from, to)?;
+                    plot_jobs(&jobs_with_runs, output_path, &options)
+                });
+                results
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push((job_name, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap_or_else(PoisonError::into_inner);
+    results.sort_by_key(|(job_name, _)| *job_name);
+    let mut failed = 0;
+    for (job_name, result) in &results {
+        match result {
+            Ok(()) => println!("{}: {}", job_name, graph_path(output_path, job_name)),
+            Err(e) => {
+                failed += 1;
+                println!("{}: failed, {}", job_name, e);
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{} of {} graphs failed", failed, results.len());
+    }
+    Ok(())
+}
+
+/// Run `f`, turning a panic into an error so one graph which panics doesn't take the others down.
+fn catch_panic<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    std::panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown cause");
+        Err(anyhow!("panicked: {}", message))
+    })
+}
+
+/// Plot already fetched jobs, which must all be runs of `options.job_name`.
+pub fn plot_jobs(
+    jobs_with_runs: &[(Job, Run)],
+    output_path: &str,
+    options: &GraphOptions,
+) -> Result<()> {
+    let job_name = options.job_name;
+    info!("Starting graph for {}", job_name);
+    let jobs_with_runs = &*with_latest_validation_settings(jobs_with_runs, job_name);
+
+    let file_path = graph_path(output_path, job_name);
     debug!("Using filepath: {:?} for graph", file_path);
     let root = BitMapBackend::new(&file_path, (1920, 1080)).into_drawing_area();
-    root.fill(&WHITE)?;
+    root.fill(&options.theme.palette().background)?;
 
-    // Calculate the maximum user time to set the y-axis limit
-    let max_user_time = jobs_with_runs
-        .iter()
-        .map(|(job, _)| job.result.user_time)
-        .fold(0.0, f64::max);
+    let x_axis = match options.x_axis {
+        XAxis::Commit
+            if jobs_with_runs
+                .iter()
+                .any(|(_, run)| run.commit_index.is_none()) =>
+        {
+            warn!("Some runs have no commit index recorded, plotting against date instead");
+            XAxis::Date
+        }
+        XAxis::Tag if jobs_with_runs.iter().any(|(_, run)| run.tag.is_none()) => {
+            warn!("Some runs have no tag recorded, plotting against date instead");
+            XAxis::Date
+        }
+        x_axis => x_axis,
+    };
+    let tags = tag_order(jobs_with_runs);
+    let x = |run: &Run| match x_axis {
+        XAxis::Date => run.run_date,
+        XAxis::Commit => run.commit_index.unwrap_or_default(),
+        XAxis::Tag => tags
+            .iter()
+            .position(|tag| run.tag.as_ref() == Some(tag))
+            .unwrap_or_default() as i64,
+    };
+    let aggregate = if x_axis != XAxis::Date && options.aggregate != Aggregate::Daily {
+        warn!("Aggregation needs a date x-axis, plotting every run instead");
+        Aggregate::Daily
+    } else {
+        options.aggregate
+    };
 
-    // Calculate the maximum RSS to set the y-axis limit
-    let max_rss = jobs_with_runs
-        .iter()
-        .map(|(job, _)| job.result.max_resident_set_size_kb as f64)
-        .fold(0.0, f64::max);
+    let mut metric_series = Series::new(jobs_with_runs, x, |job| options.metric.value(&job.result));
+    let mut secondary_series = options
+        .secondary
+        .map(|secondary| Series::new(jobs_with_runs, x, |job| secondary.value(&job.result)));
+    let log_scale = if options.log_scale && options.deviation.is_some() {
+        warn!("Deviations from the mean are negative, plotting them on a linear axis");
+        false
+    } else {
+        options.log_scale
+    };
+    // Drop non-positive values before averaging so they can't drag a period's mean down
+    let min_positive = log_scale.then(|| metric_series.retain_positive());
+    // Deviations are from the mean of every run, not of the periods they are aggregated into
+    let outlier_limit = options
+        .deviation
+        .and_then(|deviation| metric_series.deviate(deviation));
+    metric_series.aggregate(aggregate);
+    if let Some(series) = &mut secondary_series {
+        series.aggregate(aggregate);
+    }
+    if options.error_bars && options.deviation.is_some() {
+        warn!("Error bars are not drawn on deviation graphs");
+    } else if options.error_bars {
+        if aggregate == Aggregate::Daily {
+            let floor = min_positive.unwrap_or(0.0);
+            metric_series.add_error_bars(jobs_with_runs, x, options.metric, floor);
+        } else {
+            warn!("Error bars are not drawn on aggregated graphs, use --whiskers instead");
+        }
+    }
 
-    let min_date = jobs_with_runs
+    let min_x = jobs_with_runs
         .iter()
-        .map(|(_, run)| run.run_date)
+        .map(|(_, run)| match aggregate {
+            Aggregate::Daily => x(run),
+            _ => period_start(x(run), aggregate),
+        })
         .min()
         .unwrap_or(0);
-    let max_date = jobs_with_runs
+    let max_x = jobs_with_runs
         .iter()
-        .map(|(_, run)| run.run_date)
+        .map(|(_, run)| x(run))
         .max()
         .unwrap_or(0);
 
+    let events = match x_axis {
+        XAxis::Date => options
+            .events
+            .iter()
+            .filter(|(date, _)| (min_x..=max_x).contains(date))
+            .cloned()
+            .collect(),
+        XAxis::Commit | XAxis::Tag => {
+            if !options.events.is_empty() {
+                warn!("Annotations are only drawn against a date x-axis");
+            }
+            Vec::new()
+        }
+    };
+    let data = ChartData {
+        metric_series: &metric_series,
+        secondary_series: secondary_series.as_ref(),
+        annotations: annotations(jobs_with_runs, x),
+        events,
+        tags,
+        outlier_limit,
+    };
+    if options.deviation.is_some() {
+        let extent = nice_axis_max(metric_series.max_abs().max(outlier_limit.unwrap_or(0.0)));
+        draw_chart(&root, options, x_axis, min_x..max_x, -extent..extent, &data)?;
+    } else if let Some(min_value) = min_positive {
+        let max_value = metric_series.max();
+        let y_range = if min_value <= max_value {
+            min_value..max_value
+        } else {
+            1.0..10.0
+        };
+        draw_chart(
+            &root,
+            options,
+            x_axis,
+            min_x..max_x,
+            y_range.log_scale(),
+            &data,
+        )?;
+    } else {
+        draw_chart(
+            &root,
+            options,
+            x_axis,
+            min_x..max_x,
+            0.0..nice_axis_max(metric_series.max()),
+            &data,
+        )?;
+    }
+
+    root.present()?;
+    info!("Plot for {} created at {}", job_name, file_path);
+
+    Ok(())
+}
+
+/// Scatter-plot the `(x, y)` values of two metrics of `job_name`'s runs to `file_path`.
+pub fn plot_correlation(
+    pairs: &[(f64, f64)],
+    file_path: &Path,
+    job_name: &str,
+    x_metric: Metric,
+    y_metric: Metric,
+) -> Result<()> {
+    let palette = Theme::Light.palette();
+    let font = |size: u32| ("sans-serif", size).into_font().color(&palette.foreground);
+    let root = BitMapBackend::new(file_path, (1920, 1080)).into_drawing_area();
+    root.fill(&palette.background)?;
+
+    let max =
+        |value: fn(&(f64, f64)) -> f64| nice_axis_max(pairs.iter().map(value).fold(0.0, f64::max));
     let mut chart = ChartBuilder::on(&root)
         .caption(
-            format!("User Time and Max RSS for {}", job_name),
-            ("sans-serif", 50),
+            format!(
+                "{} vs {} for {}",
+                x_metric.label(),
+                y_metric.label(),
+                job_name
+            ),
+            font(50),
         )
         .x_label_area_size(50)
         .y_label_area_size(80)
-        .right_y_label_area_size(80)
         .margin(10)
-        .build_cartesian_2d(min_date..max_date, 0.0..max_user_time)?
-        .set_secondary_coord(min_date..max_date, 0.0..max_rss);
-
+        .build_cartesian_2d(0.0..max(|(x, _)| *x), 0.0..max(|(_, y)| *y))?;
     chart
         .configure_mesh()
-        .x_labels(10)
-        .x_label_formatter(&|x| format!("{}", x))
-        .y_desc("User Time (s)")
-        .axis_desc_style(("sans-serif", 30))
+        .bold_line_style(palette.foreground.mix(0.2))
+        .light_line_style(palette.foreground.mix(0.1))
+        .axis_style(palette.foreground)
+        .label_style(font(15))
+        .x_label_formatter(&|x| util::format_metric(x_metric, *x))
+        .y_label_formatter(&|y| util::format_metric(y_metric, *y))
+        .x_desc(axis_desc(x_metric, false))
+        .y_desc(axis_desc(y_metric, false))
+        .axis_desc_style(font(30))
         .draw()?;
+    chart.draw_series(
+        pairs
+            .iter()
+            .map(|point| Circle::new(*point, 5, palette.master.filled())),
+    )?;
 
-    chart
-        .configure_secondary_axes()
-        .y_desc("Max RSS (KB)")
-        .axis_desc_style(("sans-serif", 30))
-        .draw()?;
+    root.present()?;
+    info!(
+        "Correlation plot for {} created at {}",
+        job_name,
+        file_path.display()
+    );
+    Ok(())
+}
 
-    // Collect data points for master and non-master jobs
-    let master_points_user_time: Vec<_> = jobs_with_runs
+/// Only the runs made with the `assumevalid` and `par` settings of the latest run recording them,
+/// as a full verification run isn't comparable with an assumevalid one. Runs from before the
+/// settings were recorded, and archived days, don't record them and are kept with any.
+fn with_latest_validation_settings<'a>(
+    jobs_with_runs: &'a [(Job, Run)],
+    job_name: &str,
+) -> Cow<'a, [(Job, Run)]> {
+    let recorded = |run: &Run| {
+        (run.assumevalid.is_some() || run.par.is_some()).then(|| (run.assumevalid.clone(), run.par))
+    };
+    let Some((_, latest)) = jobs_with_runs
         .iter()
-        .filter(|(_, run)| run.was_master)
-        .map(|(job, run)| (run.run_date, job.result.user_time))
-        .collect();
+        .filter_map(|(_, run)| Some((run.run_date, recorded(run)?)))
+        .max_by_key(|(run_date, _)| *run_date)
+    else {
+        return Cow::Borrowed(jobs_with_runs);
+    };
+    let compatible = |run: &Run| recorded(run).is_none_or(|settings| settings == latest);
+    if jobs_with_runs.iter().all(|(_, run)| compatible(run)) {
+        return Cow::Borrowed(jobs_with_runs);
+    }
+    warn!(
+        "Runs of {} were made with different validation settings, plotting only those made with \
+        the latest, assumevalid={} par={}",
+        job_name,
+        latest.0.as_deref().unwrap_or("default"),
+        latest
+            .1
+            .map_or("default".to_string(), |par| par.to_string())
+    );
+    Cow::Owned(
+        jobs_with_runs
+            .iter()
+            .filter(|(_, run)| compatible(run))
+            .cloned()
+            .collect(),
+    )
+}
+
+/// What [`draw_chart`] plots.
+struct ChartData<'a> {
+    metric_series: &'a Series,
+    secondary_series: Option<&'a Series>,
+    /// x position and label of each annotated or throttled run
+    annotations: Vec<(i64, String)>,
+    /// Date and label of each external event within the x range
+    events: Vec<(i64, String)>,
+    /// Tags in the order they are placed on a tag x-axis
+    tags: Vec<String>,
+    /// Deviation beyond which `--deviation` highlights points
+    outlier_limit: Option<f64>,
+}
 
-    let non_master_points_user_time: Vec<_> = jobs_with_runs
+/// The distinct tags of tagged runs, ordered by the date of the commit each tag selected.
+fn tag_order(jobs_with_runs: &[(Job, Run)]) -> Vec<String> {
+    let mut tagged: Vec<(i64, &String)> = jobs_with_runs
         .iter()
-        .filter(|(_, run)| !run.was_master)
-        .map(|(job, run)| (run.run_date, job.result.user_time))
+        .filter_map(|(_, run)| run.tag.as_ref().map(|tag| (run.commit_date, tag)))
         .collect();
+    tagged.sort();
+    let mut tags: Vec<String> = Vec::new();
+    for (_, tag) in tagged {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    tags
+}
 
-    let master_points_rss: Vec<_> = jobs_with_runs
+/// The latest note of each annotated run, without when it was added, and a mark on each run
+/// during which the CPU was thermally throttled.
+fn annotations(jobs_with_runs: &[(Job, Run)], x: impl Fn(&Run) -> i64) -> Vec<(i64, String)> {
+    let mut annotations: Vec<(i64, String)> = jobs_with_runs
         .iter()
-        .filter(|(_, run)| run.was_master)
-        .map(|(job, run)| (run.run_date, job.result.max_resident_set_size_kb as f64))
+        .filter_map(|(_, run)| {
+            let note = run
+                .notes
+                .as_deref()
+                .and_then(|notes| notes.lines().last())
+                .map(|latest| latest.split_once(' ').map_or(latest, |(_, note)| note));
+            // Throttled runs are marked too, as their times are likely inflated
+            let label = match (note, run.throttled == Some(true)) {
+                (Some(note), true) => format!("{} (throttled)", note),
+                (Some(note), false) => note.to_string(),
+                (None, true) => "throttled".to_string(),
+                (None, false) => return None,
+            };
+            Some((x(run), label.chars().take(60).collect()))
+        })
         .collect();
+    annotations.dedup();
+    annotations
+}
 
-    let non_master_points_rss: Vec<_> = jobs_with_runs
+/// Upper bound for a linear axis starting at zero: `max` plus 5% headroom so the highest point
+/// doesn't touch the border, rounded up to a multiple of a 1, 2, 2.5 or 5 step giving about ten
+/// ticks. An empty or all-zero series gets a unit axis.
+fn nice_axis_max(max: f64) -> f64 {
+    if !max.is_finite() || max <= 0.0 {
+        return 1.0;
+    }
+    let padded = max * 1.05;
+    let rough_step = padded / 10.0;
+    let magnitude = 10f64.powf(rough_step.log10().floor());
+    let step = [1.0, 2.0, 2.5, 5.0, 10.0]
         .iter()
-        .filter(|(_, run)| !run.was_master)
-        .map(|(job, run)| (run.run_date, job.result.max_resident_set_size_kb as f64))
-        .collect();
+        .map(|multiple| multiple * magnitude)
+        .find(|step| *step >= rough_step)
+        .unwrap_or(10.0 * magnitude);
+    (padded / step).ceil() * step
+}
 
-    // Plot master jobs user time
-    chart
-        .draw_series(LineSeries::new(master_points_user_time.clone(), &RED))?
-        .label("Master User Time")
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
+/// Axis description for `metric`, without the unit when tick labels carry their own.
+fn axis_desc(metric: Metric, raw: bool) -> &'static str {
+    match raw {
+        true => metric.label(),
+        false => metric
+            .label()
+            .trim_end_matches(" (s)")
+            .trim_end_matches(" (KB)"),
+    }
+}
+
+fn draw_chart<Y>(
+    root: &DrawingArea<BitMapBackend, Shift>,
+    options: &GraphOptions,
+    x_axis: XAxis,
+    x_range: Range<i64>,
+    y_range: Y,
+    data: &ChartData,
+) -> Result<()>
+where
+    Y: AsRangedCoord<Value = f64>,
+    Y::CoordDescType: ValueFormatter<f64>,
+{
+    let ChartData {
+        metric_series,
+        secondary_series,
+        annotations,
+        events,
+        tags,
+        outlier_limit,
+    } = data;
+    let metric = options.metric;
+    let mid_x = x_range.start + (x_range.end - x_range.start) / 2;
+    let palette = options.theme.palette();
+    let font = |size: u32| ("sans-serif", size).into_font().color(&palette.foreground);
+    let caption = match options.secondary {
+        Some(secondary) => format!(
+            "{} and {} for {}",
+            metric.label(),
+            secondary.label(),
+            options.job_name
+        ),
+        None => format!("{} for {}", metric.label(), options.job_name),
+    };
+    let caption = match options.deviation {
+        Some(_) => format!("Deviation from the mean of {}", caption),
+        None => caption,
+    };
+    // Without a secondary metric the secondary axis is left undrawn and its label area empty
+    let secondary_max = secondary_series.map_or(1.0, |series| nice_axis_max(series.max()));
+    let mut chart = ChartBuilder::on(root)
+        .caption(caption, font(50))
+        .x_label_area_size(50)
+        .y_label_area_size(80)
+        .right_y_label_area_size(if secondary_series.is_some() { 80 } else { 0 })
+        .margin(10)
+        .build_cartesian_2d(x_range.clone(), y_range)?
+        .set_secondary_coord(x_range.clone(), 0.0..secondary_max);
 
-    // Plot non-master jobs user time
     chart
-        .draw_series(PointSeries::of_element(
-            non_master_points_user_time.clone(),
-            5,
-            &BLUE,
-            &|c, _s, _st| {
-                return EmptyElement::at(c)
-                    + Text::new(format!("{:?}", c), (0, 15), ("sans-serif", 15).into_font());
-            },
-        ))?
-        .label("Non-Master User Time")
-        .legend(|(x, y)| Circle::new((x + 10, y), 5, BLUE.filled()));
+        .configure_mesh()
+        .bold_line_style(palette.foreground.mix(0.2))
+        .light_line_style(palette.foreground.mix(0.1))
+        .axis_style(palette.foreground)
+        .label_style(font(15))
+        .x_labels(10)
+        .x_label_formatter(&|x| match x_axis {
+            XAxis::Date => util::unix_timestamp_to_hr(*x),
+            XAxis::Commit => format!("{}", x),
+            XAxis::Tag => usize::try_from(*x)
+                .ok()
+                .and_then(|x| tags.get(x))
+                .cloned()
+                .unwrap_or_default(),
+        })
+        .x_desc(match x_axis {
+            XAxis::Date => "Run Date",
+            XAxis::Commit => "Commit",
+            XAxis::Tag => "Tag",
+        })
+        .y_label_formatter(&|y| match (options.deviation, options.raw) {
+            (Some(Deviation::Zscore), _) => format!("{:+.1}σ", y),
+            (Some(Deviation::Percent), _) => format!("{:+.0}%", y),
+            (None, true) => format!("{}", y),
+            (None, false) => util::format_metric(metric, *y),
+        })
+        .y_desc(match options.deviation {
+            Some(_) => format!("{} vs mean", metric.label()),
+            None => axis_desc(metric, options.raw).to_string(),
+        })
+        .axis_desc_style(font(30))
+        .draw()?;
 
-    // Plot master jobs RSS
+    if let Some(secondary) = options.secondary {
+        chart
+            .configure_secondary_axes()
+            .axis_style(palette.foreground)
+            .label_style(font(15))
+            .y_label_formatter(&|y| match options.raw {
+                true => format!("{}", y),
+                false => util::format_metric(secondary, *y),
+            })
+            .y_desc(axis_desc(secondary, options.raw))
+            .axis_desc_style(font(30))
+            .draw()?;
+    }
+
+    if options.deviation.is_some() {
+        let line = |y: f64| vec![(x_range.start, y), (x_range.end, y)];
+        chart.draw_series(std::iter::once(PathElement::new(
+            line(0.0),
+            palette.foreground.stroke_width(2),
+        )))?;
+        if let Some(limit) = outlier_limit {
+            for y in [-limit, *limit] {
+                chart.draw_series(std::iter::once(DashedPathElement::new(
+                    line(y),
+                    6,
+                    4,
+                    palette.outlier.mix(0.6),
+                )))?;
+            }
+        }
+    }
+
+    // Plot master jobs metric
     chart
-        .draw_secondary_series(LineSeries::new(master_points_rss.clone(), &GREEN))?
-        .label("Master Max RSS")
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], GREEN));
+        .draw_series(LineSeries::new(
+            metric_series.master.clone(),
+            &palette.master,
+        ))?
+        .label(format!("Master {}", metric.label()))
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], palette.master));
 
-    // Plot non-master jobs RSS
+    // Plot non-master jobs metric
     chart
-        .draw_secondary_series(PointSeries::of_element(
-            non_master_points_rss.clone(),
+        .draw_series(PointSeries::of_element(
+            metric_series.non_master.clone(),
             5,
-            &PURPLE,
-            &|c, _s, _st| {
-                return EmptyElement::at(c)
-                    + Text::new(format!("{:?}", c), (0, 15), ("sans-serif", 15).into_font());
-            },
+            &palette.non_master,
+            &|c, _s, _st| EmptyElement::at(c) + Text::new(format!("{:?}", c), (0, 15), font(15)),
         ))?
-        .label("Non-Master Max RSS")
-        .legend(|(x, y)| Circle::new((x + 10, y), 5, PURPLE.filled()));
+        .label(format!("Non-Master {}", metric.label()))
+        .legend(|(x, y)| Circle::new((x + 10, y), 5, palette.non_master.filled()));
+
+    if options.whiskers {
+        chart.draw_series(
+            metric_series
+                .master_buckets
+                .iter()
+                .map(|b| ErrorBar::new_vertical(b.x, b.min, b.mean, b.max, palette.master, 10)),
+        )?;
+        chart.draw_series(
+            metric_series
+                .non_master_buckets
+                .iter()
+                .map(|b| ErrorBar::new_vertical(b.x, b.min, b.mean, b.max, palette.non_master, 10)),
+        )?;
+    }
+
+    if let Some(limit) = outlier_limit {
+        chart
+            .draw_series(
+                metric_series
+                    .master
+                    .iter()
+                    .chain(&metric_series.non_master)
+                    .filter(|(_, y)| y.abs() > *limit)
+                    .map(|point| Circle::new(*point, 9, palette.outlier.stroke_width(3))),
+            )?
+            .label(format!("Beyond ±{}σ", OUTLIER_STDDEVS))
+            .legend(|(x, y)| Circle::new((x + 10, y), 5, palette.outlier.stroke_width(3)));
+    }
+
+    chart.draw_series(
+        metric_series
+            .error_bars
+            .iter()
+            .map(|(x, low, value, high)| {
+                ErrorBar::new_vertical(*x, *low, *value, *high, palette.foreground, 10)
+            }),
+    )?;
+
+    if let Some((secondary, series)) = options.secondary.zip(*secondary_series) {
+        // Plot master jobs secondary metric
+        chart
+            .draw_secondary_series(LineSeries::new(
+                series.master.clone(),
+                &palette.secondary_master,
+            ))?
+            .label(format!("Master {}", secondary.label()))
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], palette.secondary_master));
+
+        // Plot non-master jobs secondary metric
+        chart
+            .draw_secondary_series(PointSeries::of_element(
+                series.non_master.clone(),
+                5,
+                &palette.secondary_non_master,
+                &|c, _s, _st| {
+                    EmptyElement::at(c) + Text::new(format!("{:?}", c), (0, 15), font(15))
+                },
+            ))?
+            .label(format!("Non-Master {}", secondary.label()))
+            .legend(|(x, y)| Circle::new((x + 10, y), 5, palette.secondary_non_master.filled()));
+    }
+
+    // Mark annotated and throttled runs with a line across the chart, labelled as above
+    for (x, note) in annotations.iter() {
+        chart.draw_secondary_series(std::iter::once(PathElement::new(
+            vec![(*x, 0.0), (*x, secondary_max)],
+            palette.foreground.mix(0.4),
+        )))?;
+        // Keep labels of runs in the right half inside the chart
+        let anchor = match *x > mid_x {
+            true => Pos::new(HPos::Right, VPos::Top),
+            false => Pos::new(HPos::Left, VPos::Top),
+        };
+        chart.draw_secondary_series(std::iter::once(Text::new(
+            format!(" {} ", note),
+            (*x, secondary_max),
+            font(15).pos(anchor),
+        )))?;
+    }
+
+    // Mark external events with a subtle dashed line, labelled along the bottom so the labels
+    // don't collide with those of annotated runs
+    let event_colour = RGBColor(128, 128, 128);
+    for (x, label) in events.iter() {
+        chart.draw_secondary_series(std::iter::once(DashedPathElement::new(
+            vec![(*x, 0.0), (*x, secondary_max)],
+            6,
+            4,
+            event_colour.mix(0.6),
+        )))?;
+        let anchor = match *x > mid_x {
+            true => Pos::new(HPos::Right, VPos::Bottom),
+            false => Pos::new(HPos::Left, VPos::Bottom),
+        };
+        chart.draw_secondary_series(std::iter::once(Text::new(
+            format!(" {} ", label),
+            (*x, 0.0),
+            ("sans-serif", 15)
+                .into_font()
+                .color(&event_colour)
+                .pos(anchor),
+        )))?;
+    }
 
     chart
         .configure_series_labels()
-        .background_style(WHITE.mix(0.8))
-        .border_style(BLACK)
+        .background_style(palette.background.mix(0.8))
+        .border_style(palette.foreground)
+        .label_font(font(15))
         .draw()?;
 
-    root.present()?;
-    info!("Plot for {} created at {}", job_name, file_path);
-
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{job_with_run, DAY, FIRST_COMMIT_DATE};
+
+    #[test]
+    fn buckets_points_by_calendar_period() {
+        // 2024-01-01 is a Monday
+        let monday = 1704067200;
+        let day = 86400;
+        let points = [
+            (monday + 8 * day, 4.0),
+            (monday + 2 * day, 3.0),
+            (monday, 1.0),
+            (monday + 40 * day, 10.0),
+        ];
+
+        let weekly = bucket_points(&points, Aggregate::Weekly);
+        assert_eq!(weekly.len(), 3);
+        assert_eq!(
+            weekly[0],
+            Bucket {
+                x: monday,
+                mean: 2.0,
+                min: 1.0,
+                max: 3.0
+            }
+        );
+        assert_eq!(weekly[1].x, monday + 7 * day);
+
+        let monthly = bucket_points(&points, Aggregate::Monthly);
+        assert_eq!(monthly.len(), 2);
+        assert_eq!(monthly[0].x, monday);
+        assert_eq!(monthly[0].mean, 8.0 / 3.0);
+        assert_eq!(monthly[1].x, monday + 31 * day);
+    }
+
+    #[test]
+    fn parses_secondary_metric() {
+        let parse = |s: &str| s.parse::<SecondaryMetric>().map(|secondary| secondary.0);
+        assert_eq!(parse("none").unwrap(), None);
+        assert_eq!(parse("elapsed").unwrap(), Some(Metric::Elapsed));
+        assert_eq!(parse("max_rss").unwrap(), Some(Metric::MaxRss));
+        assert!(parse("bogus").is_err());
+    }
+
+    #[test]
+    fn reads_event_annotations() {
+        let dir = tempdir::TempDir::new("annotations").unwrap();
+        let csv = dir.path().join("events.csv");
+        std::fs::write(
+            &csv,
+            "date,label\n2024-04-20,Halving\n\n# releases\n2024-04-10,\"v27.0\"\n",
+        )
+        .unwrap();
+        let toml = dir.path().join("events.toml");
+        std::fs::write(
+            &toml,
+            "\"2024-04-20\" = \"Halving\"\n\"2024-04-10\" = \"v27.0\"\n",
+        )
+        .unwrap();
+        let expected = vec![
+            (1712707200, "v27.0".to_string()),
+            (1713571200, "Halving".to_string()),
+        ];
+        assert_eq!(read_events(&csv).unwrap(), expected);
+        assert_eq!(read_events(&toml).unwrap(), expected);
+
+        std::fs::write(&csv, "2024-04-20 Halving\n").unwrap();
+        assert!(read_events(&csv).is_err());
+    }
+
+    #[test]
+    fn pads_axis_to_nice_bounds() {
+        assert_eq!(nice_axis_max(0.0), 1.0);
+        assert_eq!(nice_axis_max(f64::NEG_INFINITY), 1.0);
+        assert_eq!(nice_axis_max(100.0), 120.0);
+        assert_eq!(nice_axis_max(3421.0), 4000.0);
+        assert_eq!(nice_axis_max(2300.0), 2500.0);
+        assert!((nice_axis_max(0.42) - 0.45).abs() < 1e-9);
+    }
+
+    #[test]
+    fn deviates_points_from_their_mean() {
+        let series = |master: Vec<(i64, f64)>, non_master: Vec<(i64, f64)>| Series {
+            master,
+            non_master,
+            master_buckets: Vec::new(),
+            non_master_buckets: Vec::new(),
+            error_bars: Vec::new(),
+        };
+
+        let mut zscores = series(vec![(1, 8.0), (2, 12.0)], vec![(3, 10.0)]);
+        assert_eq!(zscores.deviate(Deviation::Zscore), Some(2.0));
+        assert_eq!(zscores.master, [(1, -1.0), (2, 1.0)]);
+        assert_eq!(zscores.non_master, [(3, 0.0)]);
+
+        let mut percents = series(vec![(1, 8.0), (2, 12.0)], vec![(3, 10.0)]);
+        assert_eq!(percents.deviate(Deviation::Percent), Some(40.0));
+        assert_eq!(percents.master, [(1, -20.0), (2, 20.0)]);
+        assert_eq!(percents.max_abs(), 20.0);
+
+        // Points which never vary have nothing to stand out from
+        let mut flat = series(vec![(1, 5.0), (2, 5.0)], Vec::new());
+        assert_eq!(flat.deviate(Deviation::Zscore), None);
+        assert_eq!(flat.master, [(1, 0.0), (2, 0.0)]);
+    }
+
+    #[test]
+    fn plots_runs_with_the_latest_validation_settings() {
+        let with_settings = |run_id, assumevalid: Option<&str>, par| {
+            let (job, mut run) = job_with_run(run_id, FIRST_COMMIT_DATE + i64::from(run_id) * DAY);
+            run.assumevalid = assumevalid.map(String::from);
+            run.par = par;
+            (job, run)
+        };
+        let run_ids = |jobs_with_runs: &[(Job, Run)]| -> Vec<Option<i32>> {
+            jobs_with_runs.iter().map(|(_, run)| run.id).collect()
+        };
+
+        // Runs from before the settings were recorded plot alongside any
+        let adopted = [
+            with_settings(1, None, None),
+            with_settings(2, Some("0"), None),
+        ];
+        assert!(matches!(
+            with_latest_validation_settings(&adopted, "IBD"),
+            Cow::Borrowed(_)
+        ));
+
+        let changed = [
+            with_settings(1, None, None),
+            with_settings(2, Some("0"), Some(4)),
+            with_settings(3, Some("0"), None),
+            with_settings(4, None, None),
+        ];
+        assert_eq!(
+            run_ids(&with_latest_validation_settings(&changed, "IBD")),
+            [Some(1), Some(3), Some(4)]
+        );
+    }
+
+    #[test]
+    fn selects_runs_in_range_with_a_tag() {
+        let tagged = |run_id, tag: Option<&str>| {
+            let (job, mut run) = job_with_run(run_id, FIRST_COMMIT_DATE + i64::from(run_id) * DAY);
+            run.tag = tag.map(String::from);
+            (job, run)
+        };
+        let run_ids = |jobs_with_runs: Vec<(Job, Run)>| -> Vec<Option<i32>> {
+            jobs_with_runs.iter().map(|(_, run)| run.id).collect()
+        };
+        let jobs_with_runs = vec![
+            tagged(1, Some("v25.0")),
+            tagged(2, None),
+            tagged(3, Some("v26.0")),
+            tagged(4, Some("v27.0")),
+        ];
+        let options = |x_axis| GraphOptions {
+            job_name: "IBD",
+            metric: Metric::UserTime,
+            secondary: None,
+            log_scale: false,
+            x_axis,
+            aggregate: Aggregate::Daily,
+            whiskers: false,
+            error_bars: false,
+            theme: Theme::Light,
+            raw: false,
+            events: &[],
+            deviation: None,
+        };
+
+        // Untagged runs have no place on a tag axis
+        assert_eq!(
+            run_ids(select_runs(jobs_with_runs.clone(), &options(XAxis::Tag), None, None).unwrap()),
+            [Some(1), Some(3), Some(4)]
+        );
+        assert_eq!(
+            run_ids(
+                select_runs(
+                    jobs_with_runs.clone(),
+                    &options(XAxis::Date),
+                    Some(FIRST_COMMIT_DATE + 2 * DAY),
+                    Some(FIRST_COMMIT_DATE + 3 * DAY),
+                )
+                .unwrap()
+            ),
+            [Some(2), Some(3)]
+        );
+        assert!(select_runs(
+            jobs_with_runs,
+            &options(XAxis::Tag),
+            Some(FIRST_COMMIT_DATE + 4 * DAY),
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn turns_panics_into_errors() {
+        assert_eq!(catch_panic(|| Ok(1)).unwrap(), 1);
+        let err = catch_panic::<()>(|| panic!("bad {}", "data")).unwrap_err();
+        assert_eq!(err.to_string(), "panicked: bad data");
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::{bail, Context, Result};
+use fs2::FileExt;
+use log::{debug, warn};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the lockfile kept in the bitcoin data dir while a benchmark is running.
+pub const LOCKFILE_NAME: &str = ".bitcoin-bencher.lock";
+
+/// Exclusive advisory lock on a data dir, released when dropped. As the lock is held by the OS
+/// it is reclaimed automatically if the process crashes.
+pub struct DataDirLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl DataDirLock {
+    pub fn acquire(data_dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(data_dir)
+            .with_context(|| format!("Failed to create data dir {}", data_dir.display()))?;
+        let path = data_dir.join(LOCKFILE_NAME);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open lockfile {}", path.display()))?;
+
+        if file.try_lock_exclusive().is_err() {
+            let mut pid = String::new();
+            file.read_to_string(&mut pid).ok();
+            bail!(
+                "Another benchmark is running (pid {}) using data dir {}",
+                pid.trim(),
+                data_dir.display()
+            );
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        debug!("Acquired lock {}", path.display());
+
+        Ok(DataDirLock { file, path })
+    }
+}
+
+impl Drop for DataDirLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.unlock() {
+            warn!("Failed to release lock {}: {}", self.path.display(), e);
+        } else {
+            debug!("Released lock {}", self.path.display());
+        }
+    }
+}
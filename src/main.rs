@@ -1,22 +1,37 @@
-use anyhow::Result;
-use cli::{BenchCommands, Cli, Commands, RunCommands};
+use anyhow::{anyhow, Result};
+use cli::{BenchCommands, Cli, Commands, GraphCommands, RunCommands};
 use config::Config;
 use database::Database;
 use env_logger::Env;
-use graph::plot_job_metrics;
+use graph::{plot_all_jobs, plot_job_metrics, GraphOptions};
 use log::{error, info};
+use records::RecordFormat;
+use report::ExportFormat;
 
-use crate::bench::{BenchOptions, Multi, Single};
+use crate::bench::{ArtifactOptions, BenchOptions, CommitRange, Multi, Release, Single, Tags};
 
 extern crate exitcode;
 
 mod bench;
+mod bench_log;
+mod bundle;
+mod check;
 mod cli;
 mod config;
 mod database;
 mod graph;
+mod lock;
+mod manifest;
+mod notify;
+mod records;
+mod remote;
+mod report;
 mod result;
+mod selftest;
+#[cfg(test)]
+mod test_util;
 mod util;
+mod verify;
 
 fn main() -> Result<()> {
     // Setup logging
@@ -35,64 +50,399 @@ fn main() -> Result<()> {
     });
 
     // Check required binaries exist on PATH
-    if let Err(e) = util::check_binaries_exist(&config) {
+    if let Err(e) = util::check_binaries_exist(&config, cli.allow_missing_binaries) {
         error!("Error checking binaries: {}", e);
         std::process::exit(exitcode::UNAVAILABLE);
     }
 
+    // Read commands may look back over only part of the history
+    let since = match &cli.command {
+        Some(
+            Commands::List { since, .. }
+            | Commands::Summary { since, .. }
+            | Commands::Export { since, .. },
+        ) => *since,
+        Some(Commands::Graph(
+            GraphCommands::Generate { plot, .. } | GraphCommands::All { plot, .. },
+        )) => plot.since,
+        _ => None,
+    };
+
     // Setup db
-    let database =
-        Database::create_or_load(&cli.bench_data_dir.to_string_lossy(), &cli.bench_db_name)
-            .unwrap_or_else(|e| {
-                error!("Error getting database: {}", e);
-                std::process::exit(exitcode::CANTCREAT);
-            });
+    let database = Database::create_or_load(
+        &cli.bench_data_dir.to_string_lossy(),
+        &cli.bench_db_name,
+        config.settings.db_durability,
+    )
+    .unwrap_or_else(|e| {
+        error!("Error getting database: {}", e);
+        std::process::exit(exitcode::CANTCREAT);
+    })
+    .with_append_only(cli.append_only || config.settings.append_only)
+    .with_since(since.map(|since| {
+        util::timestamp_before(chrono::Utc::now().timestamp(), since).unwrap_or_else(|e| {
+            error!("Invalid --since: {}", e);
+            std::process::exit(exitcode::USAGE);
+        })
+    }));
 
     // Handle CLI commands
     match &cli.command {
-        Some(Commands::Bench(BenchCommands::Run { run_command })) => {
-            match run_command {
-                RunCommands::Once { src_dir, commit } => {
-                    let single_options = BenchOptions::Single(Single {
+        Some(Commands::Bench(BenchCommands::Run {
+            run_command,
+            manifest,
+            keep_artifacts,
+            compress_artifacts,
+            only_if_changed,
+            force,
+            clone,
+            use_isolated_cpus,
+            profile_memory,
+            mark_master,
+            mark_non_master,
+            commit_depth,
+            explain,
+            max_runtime,
+        })) => {
+            let (src_dir, bench_type, options) = match run_command {
+                RunCommands::Once { src_dir, commit } => (
+                    src_dir,
+                    bench::BenchType::Single,
+                    BenchOptions::Single(Single {
                         commit: commit.clone(),
-                    });
-                    let mut bencher = bench::Bencher::new(
-                        &mut config,
-                        &database,
-                        src_dir,
-                        bench::BenchType::Single,
-                        single_options,
-                    )?;
-                    if let Err(e) = bencher.run() {
-                        error!("{}", e);
-                        std::process::exit(exitcode::SOFTWARE);
-                    }
-                    info!("Finished running benchmarks");
-                }
+                    }),
+                ),
                 RunCommands::Daily {
                     start,
                     end,
                     src_dir,
-                } => {
-                    // Run benchmarks daily
-                    let multi_options = BenchOptions::Multi(Multi { start, end });
-                    let mut bencher = bench::Bencher::new(
-                        &mut config,
-                        &database,
-                        src_dir,
-                        bench::BenchType::Multi,
-                        multi_options,
-                    )?;
-                    if let Err(e) = bencher.run() {
-                        error!("{}", e);
-                        std::process::exit(exitcode::SOFTWARE);
-                    }
-                    info!("Finished running daily benchmarks");
+                    date_format,
+                    collect_all,
+                    limit_runs,
+                    yes,
+                    ..
+                } => (
+                    src_dir,
+                    bench::BenchType::Multi,
+                    BenchOptions::Multi(Multi {
+                        start,
+                        end,
+                        date_format: date_format.as_ref(),
+                        collect_all: *collect_all,
+                        limit_runs: *limit_runs,
+                        confirmed: *yes,
+                    }),
+                ),
+                RunCommands::Commits {
+                    src_dir,
+                    commits_from_tags: Some(pattern),
+                    collect_all,
+                    limit_runs,
+                    ..
+                } => (
+                    src_dir,
+                    bench::BenchType::Tags,
+                    BenchOptions::Tags(Tags {
+                        pattern: pattern.clone(),
+                        collect_all: *collect_all,
+                        limit_runs: *limit_runs,
+                    }),
+                ),
+                RunCommands::Commits {
+                    src_dir,
+                    start,
+                    end,
+                    commit_range,
+                    every,
+                    order,
+                    collect_all,
+                    limit_runs,
+                    ..
+                } => (
+                    src_dir,
+                    bench::BenchType::Commits,
+                    BenchOptions::Commits(CommitRange {
+                        range: match (commit_range, start, end) {
+                            (Some(range), _, _) => range.clone(),
+                            (None, Some(start), Some(end)) => format!("{}..{}", start, end),
+                            _ => unreachable!("clap requires start and end without --commit-range"),
+                        },
+                        every: *every,
+                        order: *order,
+                        collect_all: *collect_all,
+                        limit_runs: *limit_runs,
+                    }),
+                ),
+                RunCommands::Release {
+                    release_dir,
+                    version,
+                    sha256,
+                    url_template,
+                } => (
+                    release_dir,
+                    bench::BenchType::Release,
+                    BenchOptions::Release(Release {
+                        version: version.clone(),
+                        sha256: sha256.clone(),
+                        url_template: url_template.clone(),
+                    }),
+                ),
+            };
+            let artifacts = keep_artifacts.as_ref().map(|dir| ArtifactOptions {
+                dir: dir.clone(),
+                compress: *compress_artifacts,
+            });
+            let mut bencher =
+                bench::Bencher::new(&mut config, &database, src_dir, bench_type, options)?
+                    .with_artifacts(artifacts)
+                    .with_only_if_changed(*only_if_changed && !*force)
+                    .with_clone(*clone)
+                    .with_isolated_cpus(*use_isolated_cpus)
+                    .with_profile_memory(*profile_memory)
+                    .with_master_override(match (mark_master, mark_non_master) {
+                        (true, _) => Some(true),
+                        (_, true) => Some(false),
+                        _ => None,
+                    })
+                    .with_commit_depth(*commit_depth)
+                    .with_explain(*explain)
+                    .with_max_runtime(*max_runtime);
+            let result = bencher.run();
+            let runs = bencher.runs().to_vec();
+
+            if let Some(manifest_path) = manifest {
+                if let Err(e) = manifest::write_manifest(manifest_path, &config, &runs) {
+                    error!("Error writing manifest: {}", e);
+                    std::process::exit(exitcode::CANTCREAT);
                 }
             }
+            if let Err(e) = result {
+                error!("{}", e);
+                std::process::exit(exitcode::SOFTWARE);
+            }
+            info!("Finished running benchmarks");
+        }
+        Some(Commands::Graph(GraphCommands::Generate {
+            job,
+            from,
+            to,
+            annotations,
+            plot,
+        })) => {
+            let events = annotations
+                .as_deref()
+                .map_or(Ok(Vec::new()), graph::read_events);
+            if let Err(e) = events.and_then(|events| {
+                plot_job_metrics(
+                    &database,
+                    &cli.bench_data_dir.to_string_lossy(),
+                    &GraphOptions {
+                        events: &events,
+                        ..plot.options(job)
+                    },
+                    *from,
+                    *to,
+                )
+            }) {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::Graph(GraphCommands::All {
+            jobs,
+            from,
+            to,
+            plot,
+        })) => {
+            let mut grouped = database.get_archived_jobs_grouped()?;
+            for (job_name, jobs_with_runs) in database.get_all_jobs_grouped()? {
+                grouped.entry(job_name).or_default().extend(jobs_with_runs);
+            }
+            let threads = jobs.unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, |threads| threads.get())
+            });
+            if let Err(e) = plot_all_jobs(
+                &grouped,
+                &cli.bench_data_dir.to_string_lossy(),
+                threads,
+                *from,
+                *to,
+                |job_name| plot.options(job_name),
+            ) {
+                error!("{}", e);
+                std::process::exit(exitcode::SOFTWARE);
+            }
         }
-        Some(Commands::Graph(_)) => {
-            plot_job_metrics(&database, &cli.bench_data_dir.to_string_lossy())?;
+        Some(Commands::List { format, raw, .. }) => {
+            if let Err(e) = report::list_runs(&database, *format, *raw, config.headline()) {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::Summary { format, raw, .. }) => {
+            if let Err(e) = report::summarise_jobs(&database, *format, *raw, config.headline()) {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::Check {
+            job_name,
+            thresholds,
+        }) => {
+            if let Err(e) = check::check_regressions(&database, job_name, thresholds) {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::Stability {
+            job_name,
+            metric,
+            recent,
+            threshold,
+        }) => {
+            if let Err(e) = report::report_stability(
+                &database,
+                job_name.as_deref(),
+                *metric,
+                *recent,
+                *threshold,
+            ) {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::Correlate {
+            job_name,
+            metric_a,
+            metric_b,
+            plot,
+        }) => {
+            let plot_dir = plot.then_some(cli.bench_data_dir.as_path());
+            if let Err(e) =
+                report::report_correlation(&database, job_name, *metric_a, *metric_b, plot_dir)
+            {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::Suspects {
+            src_dir,
+            job_name,
+            from_run,
+            to_run,
+        }) => {
+            if let Err(e) = report::list_suspects(&database, src_dir, job_name, *from_run, *to_run)
+            {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::CompareMachines {
+            commit,
+            job_name,
+            metric,
+        }) => {
+            if let Err(e) = report::compare_machines(&database, commit, job_name, *metric) {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::Export {
+            format,
+            base,
+            head,
+            metrics,
+            raw,
+            output,
+            ..
+        }) => {
+            let mut out = util::open_output(output.as_deref()).unwrap_or_else(|e| {
+                error!("{}", e);
+                std::process::exit(exitcode::CANTCREAT);
+            });
+            let result = match (raw, format) {
+                (_, Some(ExportFormat::Influx)) if base.is_some() || head.is_some() => Err(
+                    anyhow!("Influx export includes every job, it doesn't take --base or --head"),
+                ),
+                (_, Some(ExportFormat::Influx)) => records::export_influx(&database, &mut out),
+                (true, None | Some(ExportFormat::Json)) => {
+                    records::export_records(&database, RecordFormat::Json, &mut out)
+                }
+                (true, Some(ExportFormat::Csv)) => {
+                    records::export_records(&database, RecordFormat::Csv, &mut out)
+                }
+                (true, Some(ExportFormat::Md)) => {
+                    Err(anyhow!("Markdown export doesn't support --raw"))
+                }
+                (false, format) => report::export_comparison(
+                    &database,
+                    &mut out,
+                    format.unwrap_or(ExportFormat::Md),
+                    base.as_deref(),
+                    head.as_deref(),
+                    metrics,
+                    config.headline(),
+                ),
+            };
+            if let Err(e) = result {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::Import { path, format }) => {
+            if let Err(e) = records::import_records(&database, path, *format) {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::Bundle { run_id, output }) => {
+            if let Err(e) = bundle::write_bundle(&database, &config, *run_id, output) {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::Archive { before }) => {
+            let cutoff = before.or_else(|| {
+                config.settings.archive_after_days.map(|days| {
+                    let now = chrono::Utc::now().timestamp();
+                    now - now % 86400 - i64::from(days) * 86400
+                })
+            });
+            let Some(cutoff) = cutoff else {
+                error!("Give archive a --before date or set archive_after_days in the config");
+                std::process::exit(exitcode::USAGE);
+            };
+            match database.archive_older_than(cutoff) {
+                Ok((jobs, days)) => println!(
+                    "archived {} jobs from before {} into {} daily rows",
+                    jobs,
+                    util::unix_timestamp_to_hr(cutoff),
+                    days
+                ),
+                Err(e) => {
+                    error!("{}", e);
+                    std::process::exit(exitcode::DATAERR);
+                }
+            }
+        }
+        Some(Commands::Verify) => {
+            if let Err(e) = verify::verify_jobs(&database) {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        Some(Commands::Annotate { run_id, note }) => {
+            if let Err(e) = database.add_note(*run_id, note) {
+                error!("{}", e);
+                std::process::exit(exitcode::DATAERR);
+            }
+            info!("Added note to run {}", run_id);
+        }
+        Some(Commands::SelfTest { graph }) => {
+            if let Err(e) = selftest::run_self_test(&database, &cli.bench_data_dir, *graph) {
+                error!("{}", e);
+                std::process::exit(exitcode::SOFTWARE);
+            }
         }
         None => {}
     }
@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use log::info;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::config::{Config, JobCommand};
+use crate::util;
+
+/// Everything needed to reproduce a set of benchmark runs.
+#[derive(Serialize, Debug)]
+pub struct Manifest {
+    pub tool_version: &'static str,
+    pub created: String,
+    pub config_sha256: String,
+    pub machine: MachineInfo,
+    pub runs: Vec<ManifestRun>,
+    pub jobs: Vec<ManifestJob>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MachineInfo {
+    pub hostname: Option<String>,
+    pub kernel_release: Option<String>,
+    pub cpu_model: Option<String>,
+    pub num_cores: Option<i64>,
+    pub cpu_governor: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ManifestRun {
+    pub run_id: i64,
+    pub commit_id: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ManifestJob {
+    pub name: String,
+    pub command: JobCommand,
+    pub env: Vec<String>,
+    pub bench: bool,
+}
+
+impl MachineInfo {
+    pub fn current() -> Self {
+        MachineInfo {
+            hostname: util::get_hostname().ok(),
+            kernel_release: util::get_kernel_release().ok(),
+            cpu_model: util::get_cpu_model().ok(),
+            num_cores: util::get_nproc().ok().and_then(|n| n.parse().ok()),
+            cpu_governor: util::get_cpu_governor().ok(),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn new(config: &Config, runs: &[(i64, String)]) -> Self {
+        Manifest {
+            tool_version: env!("CARGO_PKG_VERSION"),
+            created: chrono::Utc::now().to_rfc3339(),
+            config_sha256: config.hash.clone(),
+            machine: MachineInfo::current(),
+            runs: runs
+                .iter()
+                .map(|(run_id, commit_id)| ManifestRun {
+                    run_id: *run_id,
+                    commit_id: commit_id.clone(),
+                })
+                .collect(),
+            jobs: config
+                .jobs
+                .jobs
+                .iter()
+                .map(|job| ManifestJob {
+                    name: job.name.clone(),
+                    command: job.command.clone(),
+                    env: job.env.clone().unwrap_or_default(),
+                    bench: job.bench,
+                })
+                .collect(),
+        }
+    }
+}
+
+pub fn write_manifest(path: &Path, config: &Config, runs: &[(i64, String)]) -> Result<()> {
+    let manifest = Manifest::new(config, runs);
+    let contents = serde_json::to_string_pretty(&manifest)?;
+    std::fs::write(path, contents)
+        .with_context(|| format!("Failed to write manifest to {}", path.display()))?;
+    info!("Wrote manifest to {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,359 @@
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::check::Regression;
+use crate::config::NotifierConfig;
+use crate::database::Job;
+use crate::result::Metric;
+
+/// Something which happened during a benchmark run, serialised with an `event` field naming it.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A run finished, though some of its jobs may have failed
+    RunCompleted {
+        run_id: i64,
+        commit: String,
+        failed_jobs: Vec<String>,
+        /// Why the run stopped early, if it did
+        error: Option<String>,
+        jobs: Vec<JobMetrics>,
+    },
+    /// Jobs of a master run grew past their `compare_previous` thresholds
+    Regression {
+        run_id: i64,
+        commit: String,
+        regressions: Vec<Regression>,
+    },
+}
+
+/// Every metric of one recorded job, keyed by metric name.
+#[derive(Debug, Serialize)]
+pub struct JobMetrics {
+    pub job: String,
+    pub metrics: BTreeMap<String, f64>,
+}
+
+impl From<&Job> for JobMetrics {
+    fn from(job: &Job) -> Self {
+        JobMetrics {
+            job: job.result.command.clone(),
+            metrics: Metric::value_variants()
+                .iter()
+                .filter_map(|metric| Some((metric.name(), metric.value(&job.result)?)))
+                .collect(),
+        }
+    }
+}
+
+impl Event {
+    /// One line summary, used as an email subject.
+    pub fn subject(&self) -> String {
+        match self {
+            Event::RunCompleted {
+                run_id,
+                commit,
+                failed_jobs,
+                error,
+                ..
+            } => match (error, failed_jobs.len()) {
+                (Some(_), _) => format!("Run {} of {} stopped early", run_id, commit),
+                (None, 0) => format!("Run {} of {} completed", run_id, commit),
+                (None, failed) => format!(
+                    "Run {} of {} completed with {} failed job(s)",
+                    run_id, commit, failed
+                ),
+            },
+            Event::Regression {
+                run_id,
+                commit,
+                regressions,
+            } => format!(
+                "{} regression(s) in run {} of {}",
+                regressions.len(),
+                run_id,
+                commit
+            ),
+        }
+    }
+
+    /// Plain text details of the event, used as an email body.
+    pub fn body(&self) -> String {
+        let mut lines = Vec::new();
+        match self {
+            Event::RunCompleted {
+                failed_jobs,
+                error,
+                jobs,
+                ..
+            } => {
+                if let Some(error) = error {
+                    lines.push(format!("error: {}", error));
+                }
+                if !failed_jobs.is_empty() {
+                    lines.push(format!("failed jobs: {}", failed_jobs.join(", ")));
+                }
+                for job in jobs {
+                    lines.push(String::new());
+                    lines.push(job.job.clone());
+                    for (metric, value) in &job.metrics {
+                        lines.push(format!("  {:<30} {:.2}", metric, value));
+                    }
+                }
+            }
+            Event::Regression { regressions, .. } => {
+                for regression in regressions {
+                    lines.push(format!(
+                        "{} {}: {:.2} -> {:.2} ({:+.2}%, threshold {:.2}%) against {}",
+                        regression.job,
+                        regression.metric.name(),
+                        regression.baseline,
+                        regression.value,
+                        regression.percent,
+                        regression.threshold,
+                        regression.baseline_commit
+                    ));
+                }
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// A destination for [`Event`]s. New kinds of notifier implement this and are added to
+/// [`NotifierConfig`] and [`from_config`].
+pub trait Notifier {
+    /// Where the notifier sends events, for log messages
+    fn describe(&self) -> String;
+
+    fn notify(&self, event: &Event) -> Result<()>;
+}
+
+/// POSTs each event as JSON.
+pub struct Webhook {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl Webhook {
+    pub fn new(url: &str, timeout: Duration) -> Self {
+        Webhook {
+            url: url.to_string(),
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+        }
+    }
+}
+
+impl Notifier for Webhook {
+    fn describe(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    fn notify(&self, event: &Event) -> Result<()> {
+        self.agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&serde_json::to_string(event)?)
+            .map_err(|e| anyhow!("Request failed: {}", e))?;
+        Ok(())
+    }
+}
+
+/// Emails each event's subject and body.
+pub struct Email {
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    transport: SmtpTransport,
+}
+
+impl Email {
+    pub fn new(
+        smtp_server: &str,
+        port: Option<u16>,
+        starttls: bool,
+        insecure: bool,
+        credentials: Option<Credentials>,
+        from: &str,
+        to: &[String],
+    ) -> Result<Self> {
+        let invalid = |e| anyhow!("Invalid SMTP server '{}': {}", smtp_server, e);
+        let mut builder = match (starttls, insecure) {
+            (true, _) => SmtpTransport::starttls_relay(smtp_server).map_err(invalid)?,
+            (false, false) => SmtpTransport::relay(smtp_server).map_err(invalid)?,
+            (false, true) => {
+                warn!(
+                    "Emailing through {} in plaintext as insecure is set",
+                    smtp_server
+                );
+                SmtpTransport::builder_dangerous(smtp_server)
+            }
+        };
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+        if let Some(credentials) = credentials {
+            builder = builder.credentials(credentials);
+        }
+        let parse = |address: &str| {
+            address
+                .parse()
+                .map_err(|e| anyhow!("Invalid email address '{}': {}", address, e))
+        };
+        Ok(Email {
+            from: parse(from)?,
+            to: to.iter().map(|to| parse(to)).collect::<Result<_>>()?,
+            transport: builder.build(),
+        })
+    }
+}
+
+impl Notifier for Email {
+    fn describe(&self) -> String {
+        let to: Vec<String> = self.to.iter().map(|to| to.to_string()).collect();
+        format!("email to {}", to.join(", "))
+    }
+
+    fn notify(&self, event: &Event) -> Result<()> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(event.subject());
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message.body(event.body())?;
+        self.transport
+            .send(&message)
+            .map_err(|e| anyhow!("Sending email failed: {}", e))?;
+        Ok(())
+    }
+}
+
+/// Build the notifiers configured under `[[notifiers]]`.
+pub fn from_config(configs: &[NotifierConfig]) -> Result<Vec<Box<dyn Notifier>>> {
+    configs
+        .iter()
+        .map(|config| -> Result<Box<dyn Notifier>> {
+            match config {
+                NotifierConfig::Webhook { url, timeout } => {
+                    Ok(Box::new(Webhook::new(url, Duration::from_secs(*timeout))))
+                }
+                NotifierConfig::Email {
+                    smtp_server,
+                    port,
+                    starttls,
+                    insecure,
+                    username,
+                    password_env,
+                    from,
+                    to,
+                } => {
+                    let credentials = match username {
+                        Some(username) => {
+                            let password = match password_env {
+                                Some(var) => std::env::var(var).with_context(|| {
+                                    format!("SMTP password variable {} is not set", var)
+                                })?,
+                                None => String::new(),
+                            };
+                            Some(Credentials::new(username.clone(), password))
+                        }
+                        None => None,
+                    };
+                    Ok(Box::new(Email::new(
+                        smtp_server,
+                        *port,
+                        *starttls,
+                        *insecure,
+                        credentials,
+                        from,
+                        to,
+                    )?))
+                }
+            }
+        })
+        .collect()
+}
+
+/// Send `event` to every notifier, warning about those which fail so notifying never fails a
+/// benchmark.
+pub fn notify_all(notifiers: &[Box<dyn Notifier>], event: &Event) {
+    for notifier in notifiers {
+        match notifier.notify(event) {
+            Ok(()) => info!("Sent '{}' to {}", event.subject(), notifier.describe()),
+            Err(e) => warn!("Could not notify {}: {}", notifier.describe(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn posts_events_to_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(body).unwrap()
+        });
+
+        let event = Event::RunCompleted {
+            run_id: 7,
+            commit: "abc123".to_string(),
+            failed_jobs: vec!["unit-tests".to_string()],
+            error: None,
+            jobs: vec![JobMetrics {
+                job: "make".to_string(),
+                metrics: BTreeMap::from([("user-time".to_string(), 12.5)]),
+            }],
+        };
+        assert_eq!(
+            event.subject(),
+            "Run 7 of abc123 completed with 1 failed job(s)"
+        );
+        Webhook::new(&url, Duration::from_secs(5))
+            .notify(&event)
+            .unwrap();
+
+        let body: serde_json::Value = serde_json::from_str(&server.join().unwrap()).unwrap();
+        assert_eq!(body["event"], "run_completed");
+        assert_eq!(body["run_id"], 7);
+        assert_eq!(body["failed_jobs"][0], "unit-tests");
+        assert_eq!(body["jobs"][0]["metrics"]["user-time"], 12.5);
+
+        // A webhook which can't be reached is an error for notify_all to warn about
+        assert!(Webhook::new(&url, Duration::from_secs(1))
+            .notify(&event)
+            .is_err());
+    }
+}
@@ -0,0 +1,513 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::path::Path;
+
+use crate::database::{Database, Job, Run};
+use crate::result::TimeResult;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RecordFormat {
+    Json,
+    Csv,
+}
+
+/// A job and the run it belongs to, flattened for moving results between databases.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct JobRecord {
+    pub run_date: i64,
+    pub commit_id: String,
+    pub commit_date: i64,
+    pub commit_index: Option<i64>,
+    pub was_master: bool,
+    pub tree_hash: Option<String>,
+    pub num_cores: Option<i64>,
+    pub cpu_model: Option<String>,
+    pub remote_host: Option<String>,
+    pub throttled: Option<bool>,
+    pub max_temperature_c: Option<f64>,
+    #[serde(default)]
+    pub datadir_reused: bool,
+    pub job_name: String,
+    pub sample_index: Option<u32>,
+    pub exit_status: i32,
+    pub user_time: f64,
+    pub system_time: f64,
+    pub percent_of_cpu: i32,
+    pub max_resident_set_size_kb: i64,
+    pub major_page_faults: i64,
+    pub minor_page_faults: i64,
+    pub voluntary_context_switches: i64,
+    pub involuntary_context_switches: i64,
+    pub file_system_outputs: i64,
+    pub elapsed: Option<f64>,
+    pub read_bytes: Option<i64>,
+    pub write_bytes: Option<i64>,
+}
+
+/// Column order of the CSV format, matching the fields of [`JobRecord`].
+const CSV_COLUMNS: [&str; 27] = [
+    "run_date",
+    "commit_id",
+    "commit_date",
+    "commit_index",
+    "was_master",
+    "tree_hash",
+    "num_cores",
+    "cpu_model",
+    "remote_host",
+    "throttled",
+    "max_temperature_c",
+    "datadir_reused",
+    "job_name",
+    "sample_index",
+    "exit_status",
+    "user_time",
+    "system_time",
+    "percent_of_cpu",
+    "max_resident_set_size_kb",
+    "major_page_faults",
+    "minor_page_faults",
+    "voluntary_context_switches",
+    "involuntary_context_switches",
+    "file_system_outputs",
+    "elapsed",
+    "read_bytes",
+    "write_bytes",
+];
+
+/// Fields of [`JobRecord`] written as fields of the InfluxDB line protocol, the measurements of
+/// the job's [`TimeResult`].
+const INFLUX_FIELDS: [&str; 13] = [
+    "exit_status",
+    "user_time",
+    "system_time",
+    "percent_of_cpu",
+    "max_resident_set_size_kb",
+    "major_page_faults",
+    "minor_page_faults",
+    "voluntary_context_switches",
+    "involuntary_context_switches",
+    "file_system_outputs",
+    "elapsed",
+    "read_bytes",
+    "write_bytes",
+];
+
+/// Columns which are always text, even if they happen to look like numbers.
+const CSV_TEXT_COLUMNS: [&str; 5] = [
+    "commit_id",
+    "tree_hash",
+    "cpu_model",
+    "remote_host",
+    "job_name",
+];
+
+impl JobRecord {
+    fn new(job: &Job, run: &Run) -> Self {
+        let result = &job.result;
+        JobRecord {
+            run_date: run.run_date,
+            commit_id: run.commit_id.clone(),
+            commit_date: run.commit_date,
+            commit_index: run.commit_index,
+            was_master: run.was_master,
+            tree_hash: run.tree_hash.clone(),
+            num_cores: run.num_cores,
+            cpu_model: run.cpu_model.clone(),
+            remote_host: run.remote_host.clone(),
+            throttled: run.throttled,
+            max_temperature_c: run.max_temperature_c,
+            datadir_reused: run.datadir_reused,
+            job_name: result.command.clone(),
+            sample_index: job.sample_index,
+            exit_status: result.exit_status,
+            user_time: result.user_time,
+            system_time: result.system_time,
+            percent_of_cpu: result.percent_of_cpu,
+            max_resident_set_size_kb: result.max_resident_set_size_kb,
+            major_page_faults: result.major_page_faults,
+            minor_page_faults: result.minor_page_faults,
+            voluntary_context_switches: result.voluntary_context_switches,
+            involuntary_context_switches: result.involuntary_context_switches,
+            file_system_outputs: result.file_system_outputs,
+            elapsed: result.elapsed,
+            read_bytes: result.read_bytes,
+            write_bytes: result.write_bytes,
+        }
+    }
+
+    fn run(&self) -> Run {
+        Run {
+            id: None,
+            run_date: self.run_date,
+            commit_id: self.commit_id.clone(),
+            commit_date: self.commit_date,
+            commit_index: self.commit_index,
+            tree_hash: self.tree_hash.clone(),
+            container_image: None,
+            container_digest: None,
+            signature_verified: None,
+            commit_order: None,
+            commit_range: None,
+            tag: None,
+            run_duration: None,
+            assumevalid: None,
+            par: None,
+            notes: None,
+            from_release: false,
+            was_master: self.was_master,
+            num_cores: self.num_cores,
+            cpu_model: self.cpu_model.clone(),
+            remote_host: self.remote_host.clone(),
+            throttled: self.throttled,
+            max_temperature_c: self.max_temperature_c,
+            datadir_reused: self.datadir_reused,
+        }
+    }
+
+    fn result(&self) -> TimeResult {
+        TimeResult {
+            command: self.job_name.clone(),
+            user_time: self.user_time,
+            system_time: self.system_time,
+            percent_of_cpu: self.percent_of_cpu,
+            max_resident_set_size_kb: self.max_resident_set_size_kb,
+            major_page_faults: self.major_page_faults,
+            minor_page_faults: self.minor_page_faults,
+            voluntary_context_switches: self.voluntary_context_switches,
+            involuntary_context_switches: self.involuntary_context_switches,
+            file_system_outputs: self.file_system_outputs,
+            exit_status: self.exit_status,
+            elapsed: self.elapsed,
+            read_bytes: self.read_bytes,
+            write_bytes: self.write_bytes,
+            ..Default::default()
+        }
+    }
+}
+
+fn csv_escape(cell: &str) -> String {
+    if cell.contains([',', '"', '\n']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// Split CSV text into rows of cells, handling quoted cells.
+fn csv_parse(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            ('"', _) => in_quotes = !in_quotes,
+            (',', false) => row.push(std::mem::take(&mut cell)),
+            ('\n', false) => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            ('\r', false) => {}
+            _ => cell.push(c),
+        }
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    rows
+}
+
+fn to_csv(records: &[JobRecord]) -> Result<String> {
+    let mut csv = CSV_COLUMNS.join(",") + "\n";
+    for record in records {
+        let Value::Object(fields) = serde_json::to_value(record)? else {
+            bail!("Job record did not serialize to an object");
+        };
+        let cells: Vec<String> = CSV_COLUMNS
+            .iter()
+            .map(|column| match &fields[*column] {
+                Value::Null => String::new(),
+                Value::String(s) => csv_escape(s),
+                value => value.to_string(),
+            })
+            .collect();
+        csv.push_str(&cells.join(","));
+        csv.push('\n');
+    }
+    Ok(csv)
+}
+
+/// Escape a tag value of the InfluxDB line protocol, in which commas, equals signs and spaces are
+/// delimiters.
+fn influx_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// One line of InfluxDB line protocol per record, in the `bench` measurement tagged with the job,
+/// commit and machine, timestamped with the run date in nanoseconds.
+fn to_influx(records: &[JobRecord]) -> Result<String> {
+    let mut lines = String::new();
+    for record in records {
+        let Value::Object(values) = serde_json::to_value(record)? else {
+            bail!("Job record did not serialize to an object");
+        };
+        let machine = record
+            .cpu_model
+            .as_ref()
+            .zip(record.num_cores)
+            .map(|(cpu_model, num_cores)| format!("{} ({} cores)", cpu_model, num_cores));
+        // Tags are sorted by key, as Influx recommends, and empty tags aren't allowed
+        let tags: Vec<String> = [
+            ("commit", Some(&record.commit_id)),
+            ("job", Some(&record.job_name)),
+            ("machine", machine.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| {
+            value
+                .filter(|value| !value.is_empty())
+                .map(|value| (key, value))
+        })
+        .map(|(key, value)| format!("{}={}", key, influx_escape(value)))
+        .collect();
+        let fields: Vec<String> = INFLUX_FIELDS
+            .iter()
+            .filter_map(|field| match &values[*field] {
+                Value::Number(n) if n.is_f64() => Some(format!("{}={}", field, n)),
+                Value::Number(n) => Some(format!("{}={}i", field, n)),
+                _ => None,
+            })
+            .collect();
+        lines.push_str(&format!(
+            "bench,{} {} {}\n",
+            tags.join(","),
+            fields.join(","),
+            i128::from(record.run_date) * 1_000_000_000
+        ));
+    }
+    Ok(lines)
+}
+
+fn from_csv(text: &str) -> Result<Vec<JobRecord>> {
+    let mut rows = csv_parse(text).into_iter();
+    let header = rows.next().ok_or_else(|| anyhow!("CSV file is empty"))?;
+    rows.enumerate()
+        .map(|(i, row)| {
+            if row.len() != header.len() {
+                bail!(
+                    "CSV row {} has {} cells, expected {}",
+                    i + 2,
+                    row.len(),
+                    header.len()
+                );
+            }
+            let mut fields = Map::new();
+            for (column, cell) in header.iter().zip(row) {
+                let value = if cell.is_empty() {
+                    Value::Null
+                } else if CSV_TEXT_COLUMNS.contains(&column.as_str()) {
+                    Value::String(cell)
+                } else {
+                    serde_json::from_str(&cell).with_context(|| {
+                        format!("Invalid {} '{}' in row {}", column, cell, i + 2)
+                    })?
+                };
+                fields.insert(column.clone(), value);
+            }
+            serde_json::from_value(Value::Object(fields))
+                .with_context(|| format!("Invalid record in row {}", i + 2))
+        })
+        .collect()
+}
+
+/// Every recorded unprofiled job as a record.
+fn all_records(db: &Database) -> Result<Vec<JobRecord>> {
+    Ok(db
+        .get_all_jobs()?
+        .iter()
+        .map(|(job, run)| JobRecord::new(job, run))
+        .collect())
+}
+
+/// Print every recorded unprofiled job, in a form `import` can read back.
+pub fn export_records(db: &Database, format: RecordFormat, out: &mut dyn Write) -> Result<()> {
+    let records = all_records(db)?;
+    match format {
+        RecordFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&records)?)?,
+        RecordFormat::Csv => write!(out, "{}", to_csv(&records)?)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Print every recorded unprofiled job as InfluxDB line protocol.
+pub fn export_influx(db: &Database, out: &mut dyn Write) -> Result<()> {
+    write!(out, "{}", to_influx(&all_records(db)?)?)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Insert records exported from another database, skipping jobs, or samples of them, already
+/// recorded for the same commit and run date.
+pub fn import_records(db: &Database, path: &Path, format: RecordFormat) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let records = match format {
+        RecordFormat::Json => serde_json::from_str(&text)?,
+        RecordFormat::Csv => from_csv(&text)?,
+    };
+
+    let (mut imported, mut skipped) = (0, 0);
+    for record in records {
+        let run_id = match db.find_run(&record.commit_id, record.run_date)? {
+            Some(run_id) if db.has_job(run_id, &record.job_name, record.sample_index)? => {
+                skipped += 1;
+                continue;
+            }
+            Some(run_id) => run_id,
+            None => {
+                let run_id = db.record_run(record.run())?;
+                if let Some(throttled) = record.throttled {
+                    db.set_thermal(run_id, throttled, record.max_temperature_c)?;
+                }
+                if record.datadir_reused {
+                    db.set_datadir_reused(run_id)?;
+                }
+                run_id
+            }
+        };
+        let job_id = db.record_job(run_id, &record.job_name, record.result(), None, None)?;
+        if let Some(sample_index) = record.sample_index {
+            db.set_sample_index(job_id, sample_index)?;
+        }
+        imported += 1;
+    }
+
+    info!(
+        "Imported {} jobs from {}, skipped {} duplicates",
+        imported,
+        path.display(),
+        skipped
+    );
+    println!("imported: {}, skipped duplicates: {}", imported, skipped);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DbDurability;
+    use tempdir::TempDir;
+
+    /// A successful IBD record, for tests to override the fields they're about.
+    fn sample_record() -> JobRecord {
+        JobRecord {
+            run_date: 1713456789,
+            commit_id: "abc".to_string(),
+            commit_date: 1713400000,
+            commit_index: None,
+            was_master: true,
+            tree_hash: None,
+            num_cores: Some(8),
+            cpu_model: Some("Ryzen 9, 3.2GHz".to_string()),
+            remote_host: None,
+            throttled: None,
+            max_temperature_c: None,
+            datadir_reused: false,
+            job_name: "IBD".to_string(),
+            sample_index: None,
+            exit_status: 0,
+            user_time: 12.5,
+            system_time: 1.0,
+            percent_of_cpu: 99,
+            max_resident_set_size_kb: 1920,
+            major_page_faults: 3,
+            minor_page_faults: 86,
+            voluntary_context_switches: 2,
+            involuntary_context_switches: 7,
+            file_system_outputs: 16,
+            elapsed: None,
+            read_bytes: None,
+            write_bytes: Some(4096),
+        }
+    }
+
+    #[test]
+    fn csv_round_trips_records() {
+        let record = JobRecord {
+            commit_id: "0123456789".to_string(),
+            commit_index: Some(42),
+            cpu_model: Some("CPU \"fast\", 3.2GHz".to_string()),
+            remote_host: Some("bench@10.0.0.2".to_string()),
+            throttled: Some(true),
+            max_temperature_c: Some(91.5),
+            sample_index: Some(2),
+            system_time: 1.25,
+            elapsed: Some(13.1),
+            ..sample_record()
+        };
+        let csv = to_csv(std::slice::from_ref(&record)).unwrap();
+        assert_eq!(from_csv(&csv).unwrap(), vec![record]);
+    }
+
+    #[test]
+    fn writes_influx_line_protocol() {
+        assert_eq!(
+            to_influx(&[sample_record()]).unwrap(),
+            "bench,commit=abc,job=IBD,machine=Ryzen\\ 9\\,\\ 3.2GHz\\ (8\\ cores) \
+            exit_status=0i,user_time=12.5,system_time=1.0,percent_of_cpu=99i,\
+            max_resident_set_size_kb=1920i,major_page_faults=3i,minor_page_faults=86i,\
+            voluntary_context_switches=2i,involuntary_context_switches=7i,\
+            file_system_outputs=16i,write_bytes=4096i 1713456789000000000\n"
+        );
+    }
+
+    #[test]
+    fn imports_each_sample_once() {
+        let dir = TempDir::new("records").unwrap();
+        let db = Database::create_or_load(
+            &dir.path().to_string_lossy(),
+            "db.sqlite",
+            DbDurability::Normal,
+        )
+        .unwrap();
+        let samples: Vec<JobRecord> = (0..2)
+            .map(|sample_index| JobRecord {
+                num_cores: None,
+                cpu_model: None,
+                remote_host: Some("bench".to_string()),
+                throttled: Some(true),
+                max_temperature_c: Some(91.5),
+                datadir_reused: true,
+                sample_index: Some(sample_index),
+                user_time: 12.5 + f64::from(sample_index),
+                write_bytes: None,
+                ..sample_record()
+            })
+            .collect();
+        let path = dir.path().join("records.json");
+        std::fs::write(&path, serde_json::to_string(&samples).unwrap()).unwrap();
+
+        import_records(&db, &path, RecordFormat::Json).unwrap();
+        import_records(&db, &path, RecordFormat::Json).unwrap();
+        assert_eq!(all_records(&db).unwrap(), samples);
+    }
+}
@@ -0,0 +1,325 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// GNU time is looked for at these paths on the remote host, in order.
+const REMOTE_TIME_PATHS: [&str; 2] = ["/usr/bin/time", "/usr/local/bin/gtime"];
+
+/// A host which jobs run on over SSH, in a checkout of the source at `src_dir`.
+#[derive(Debug)]
+pub struct Remote {
+    /// SSH destination, e.g. `bench@box`
+    pub host: String,
+    pub src_dir: PathBuf,
+    /// Path of GNU time on the host
+    pub time: String,
+    pub num_cores: u32,
+    pub cpu_model: Option<String>,
+}
+
+/// Quote `s` as a single word for a POSIX shell.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// SSH arguments running `script` with `sh` in `src_dir` on `host`. Jobs never read stdin and
+/// passwords can't be prompted for, so neither is attempted.
+fn ssh_args(host: &str, src_dir: &Path, script: &str) -> Vec<String> {
+    let script = format!(
+        "cd {} && {}",
+        shell_quote(&src_dir.to_string_lossy()),
+        script
+    );
+    let mut args = ssh_options(host);
+    args.push(format!("sh -c {}", shell_quote(&script)));
+    args
+}
+
+fn ssh_options(host: &str) -> Vec<String> {
+    vec![
+        "-n".to_string(),
+        "-o".to_string(),
+        "BatchMode=yes".to_string(),
+        host.to_string(),
+    ]
+}
+
+impl Remote {
+    /// Check `host` can be reached and has GNU time and a source checkout at `src_dir`, and read
+    /// its CPUs.
+    pub fn connect(host: &str, src_dir: &Path) -> Result<Self> {
+        let mut remote = Remote {
+            host: host.to_string(),
+            src_dir: src_dir.to_path_buf(),
+            time: String::new(),
+            num_cores: 0,
+            cpu_model: None,
+        };
+        let reached = Command::new("ssh")
+            .args(ssh_options(host))
+            .arg("true")
+            .output()
+            .context("Failed to execute ssh")?;
+        if !reached.status.success() {
+            bail!(
+                "Could not reach {} over SSH: {}",
+                host,
+                String::from_utf8_lossy(&reached.stderr).trim()
+            );
+        }
+        remote
+            .output("git rev-parse --git-dir >/dev/null")
+            .with_context(|| format!("No git checkout at {} on {}", src_dir.display(), host))?;
+
+        let candidates: Vec<String> = REMOTE_TIME_PATHS.iter().map(|p| shell_quote(p)).collect();
+        remote.time = remote
+            .output(&format!(
+                "for time in {}; do \"$time\" --version 2>&1 | grep -q GNU && echo \"$time\" && \
+                exit 0; done; exit 1",
+                candidates.join(" ")
+            ))
+            // The host was reached above, so a failure here means no GNU time
+            .map_err(|_| {
+                anyhow!(
+                    "GNU time not found on {} at {}",
+                    host,
+                    REMOTE_TIME_PATHS.join(" or ")
+                )
+            })?;
+
+        let cpus = remote.output("nproc; grep -m1 'model name' /proc/cpuinfo || true")?;
+        let mut lines = cpus.lines();
+        remote.num_cores = lines
+            .next()
+            .and_then(|n| n.trim().parse().ok())
+            .context(format!("Could not read the number of CPUs of {}", host))?;
+        remote.cpu_model = lines
+            .next()
+            .and_then(|line| line.split_once(':'))
+            .map(|(_, model)| model.trim().to_string());
+        info!(
+            "Running jobs on {} in {} with {}",
+            host,
+            src_dir.display(),
+            remote.time
+        );
+        Ok(remote)
+    }
+
+    /// `ssh` running `script` on the host in the source directory.
+    pub fn command(&self, script: &str) -> Command {
+        let mut command = Command::new("ssh");
+        command.args(ssh_args(&self.host, &self.src_dir, script));
+        command
+    }
+
+    /// A line for a local shell running `script` on the host in the source directory.
+    pub fn shell_line(&self, script: &str) -> String {
+        std::iter::once("ssh".to_string())
+            .chain(ssh_args(&self.host, &self.src_dir, script))
+            .map(|arg| shell_quote(&arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Run `script` on the host, returning its trimmed stdout.
+    pub fn output(&self, script: &str) -> Result<String> {
+        let output = self
+            .command(script)
+            .stderr(Stdio::piped())
+            .output()
+            .context("Failed to execute ssh")?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match output.status.code() {
+            Some(0) => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+            // ssh itself exits 255 when the connection fails
+            Some(255) => bail!("Could not reach {} over SSH: {}", self.host, stderr.trim()),
+            _ => bail!(
+                "'{}' failed on {} with {}: {}",
+                script,
+                self.host,
+                output.status,
+                stderr.trim()
+            ),
+        }
+    }
+
+    /// Check out `commit_id` on the host, fetching it first if the host doesn't have it.
+    pub fn checkout(&self, commit_id: &str) -> Result<()> {
+        let commit = shell_quote(commit_id);
+        self.output(&format!(
+            "{{ git cat-file -e {0}^{{commit}} 2>/dev/null || git fetch --quiet origin; }} && \
+            git checkout --quiet --detach {0}",
+            commit
+        ))
+        .with_context(|| format!("Failed to check out {} on {}", commit_id, self.host))?;
+        info!("Checked out commit {} on {}", commit_id, self.host);
+        Ok(())
+    }
+
+    /// Create the parent directories of `paths` on the host and remove any files left at them.
+    pub fn prepare_files(&self, paths: &[&str]) -> Result<()> {
+        let quoted: Vec<String> = paths.iter().map(|path| shell_quote(path)).collect();
+        let parents: Vec<String> = paths
+            .iter()
+            .filter_map(|path| Path::new(path).parent())
+            .map(|parent| shell_quote(&parent.to_string_lossy()))
+            .collect();
+        self.output(&format!(
+            "mkdir -p {} && rm -f {}",
+            parents.join(" "),
+            quoted.join(" ")
+        ))?;
+        Ok(())
+    }
+
+    /// `--version` of each of `binaries` on the host, as `util::get_binary_versions` reads them
+    /// locally.
+    pub fn binary_versions(&self, binaries: &[String]) -> Vec<(String, Option<String>)> {
+        binaries
+            .iter()
+            .map(|binary| {
+                let version = self
+                    .output(&format!(
+                        "{} --version 2>&1 </dev/null | grep -m1 .",
+                        shell_quote(binary)
+                    ))
+                    .map_err(|e| debug!("Could not get version of {}: {}", binary, e))
+                    .ok()
+                    .map(|line| line.trim().to_string());
+                (binary.clone(), version)
+            })
+            .collect()
+    }
+
+    /// Copy `path` on the host to the same path locally.
+    pub fn fetch_file(&self, path: &str) -> Result<()> {
+        let contents = self
+            .command(&format!("cat {}", shell_quote(path)))
+            .output()
+            .context("Failed to execute ssh")?;
+        if !contents.status.success() {
+            bail!(
+                "Could not fetch {} from {}: {}",
+                path,
+                self.host,
+                String::from_utf8_lossy(&contents.stderr).trim()
+            );
+        }
+        if let Some(parent) = Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, contents.stdout).with_context(|| format!("Failed to write {}", path))
+    }
+
+    /// The pid of the process on the host holding the fcntl lock on `<data_dir>/.lock`, if any.
+    pub fn datadir_lock_holder(&self, data_dir: &Path) -> Result<Option<i32>> {
+        let pid = self.output(&lock_holder_script(data_dir))?;
+        match pid.as_str() {
+            "" => Ok(None),
+            pid => pid
+                .parse()
+                .map(Some)
+                .with_context(|| format!("Invalid pid '{}' from {}", pid, self.host)),
+        }
+    }
+
+    /// Remove everything inside `dir` on the host.
+    pub fn erase_dir_contents(&self, dir: &Path) -> Result<()> {
+        self.output(&format!(
+            "if [ -d {0} ]; then find {0} -mindepth 1 -maxdepth 1 -exec rm -rf {{}} +; fi",
+            shell_quote(&dir.to_string_lossy())
+        ))?;
+        Ok(())
+    }
+}
+
+/// A script printing the pid holding the lock on `<data_dir>/.lock`, looked up in `/proc/locks`
+/// by the file's inode as fcntl locks can't be queried from a shell. Lines of processes waiting
+/// for a lock, marked `->`, are skipped.
+fn lock_holder_script(data_dir: &Path) -> String {
+    format!(
+        "inode=$(stat -c %i {} 2>/dev/null) || exit 0; awk -v inode=\"$inode\" \
+        '$2 != \"->\" {{ split($6, id, \":\"); if (id[3] == inode) {{ print $5; exit }} }}' \
+        /proc/locks",
+        shell_quote(&data_dir.join(".lock").to_string_lossy())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::AsRawFd;
+    use tempdir::TempDir;
+
+    #[test]
+    fn builds_ssh_commands() {
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+
+        let remote = Remote {
+            host: "bench@box".to_string(),
+            src_dir: PathBuf::from("/home/bench/bit coin"),
+            time: "/usr/bin/time".to_string(),
+            num_cores: 8,
+            cpu_model: None,
+        };
+        let command = remote.command("make -j8");
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .collect();
+        assert_eq!(
+            args,
+            [
+                "-n",
+                "-o",
+                "BatchMode=yes",
+                "bench@box",
+                r"sh -c 'cd '\''/home/bench/bit coin'\'' && make -j8'"
+            ]
+        );
+
+        // The local shell undoes one level of quoting, leaving the same arguments for ssh
+        let line = remote.shell_line("make -j8");
+        let output = Command::new("sh")
+            .args([
+                "-c",
+                &format!("printf '%s\\n' {}", line.strip_prefix("'ssh' ").unwrap()),
+            ])
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .collect::<Vec<_>>(),
+            args
+        );
+    }
+
+    #[test]
+    fn finds_datadir_lock_holder() {
+        let dir = TempDir::new("remote_lock").unwrap();
+        let holder = || {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(lock_holder_script(dir.path()))
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+        assert_eq!(holder(), "");
+
+        let lock = std::fs::File::create(dir.path().join(".lock")).unwrap();
+        assert_eq!(holder(), "");
+        // SAFETY: an all zero flock is valid, and the fd is open for as long as `lock` lives
+        let mut flock: libc::flock = unsafe { std::mem::zeroed() };
+        flock.l_type = libc::F_WRLCK as _;
+        flock.l_whence = libc::SEEK_SET as _;
+        assert_eq!(
+            unsafe { libc::fcntl(lock.as_raw_fd(), libc::F_SETLK, &flock) },
+            0
+        );
+        assert_eq!(holder(), std::process::id().to_string());
+    }
+}
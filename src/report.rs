@@ -0,0 +1,814 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use log::info;
+use log::warn;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use crate::check::{fetch_baseline, fetch_same_machine_baseline, mean_per_run, percent_change};
+use crate::database::{Database, Job, Run};
+use crate::graph;
+use crate::result::{mean_and_stddev, Metric};
+use crate::util;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable table
+    Table,
+    /// JSON array of records
+    Json,
+}
+
+/// The primary job's headline metric for a run, compared against the previous master run.
+#[derive(Serialize, Debug, Clone)]
+pub struct Headline {
+    pub job_name: String,
+    pub metric: Metric,
+    pub value: f64,
+    pub change_percent: Option<f64>,
+}
+
+impl Headline {
+    /// e.g. `IBD 57m 01s (+2.10%)`
+    fn describe(&self, raw: bool) -> String {
+        let value = match raw {
+            true => format_value(self.value),
+            false => util::format_metric(self.metric, self.value),
+        };
+        match self.change_percent {
+            Some(change) => format!("{} {} ({:+.2}%)", self.job_name, value, change),
+            None => format!("{} {}", self.job_name, value),
+        }
+    }
+}
+
+/// Headlines of every run containing the primary job, keyed by run id. Changes are against the
+/// baseline regression checks use, the previous master run on the same machine.
+fn headlines(db: &Database, primary: Option<(&str, Metric)>) -> Result<BTreeMap<i64, Headline>> {
+    let Some((job_name, metric)) = primary else {
+        return Ok(BTreeMap::new());
+    };
+    let jobs_with_runs = mean_per_run(db.get_jobs_by_name(&job_name.to_string())?);
+    Ok(jobs_with_runs
+        .iter()
+        .filter_map(|candidate| {
+            let value = metric.value(&candidate.0.result)?;
+            let headline = Headline {
+                job_name: job_name.to_string(),
+                metric,
+                value,
+                change_percent: fetch_same_machine_baseline(&jobs_with_runs, candidate)
+                    .and_then(|(job, _)| metric.value(&job.result))
+                    .filter(|base| *base > 0.0)
+                    .map(|base| percent_change(base, value)),
+            };
+            Some((candidate.0.run_id, headline))
+        })
+        .collect())
+}
+
+/// A recorded run as output by the `list` command. Dates are ISO-8601 in UTC.
+#[derive(Serialize, Debug)]
+pub struct RunSummary {
+    pub run_id: i64,
+    pub run_date: String,
+    pub commit_id: String,
+    pub commit_date: String,
+    pub commit_index: Option<i64>,
+    pub was_master: bool,
+    pub job_count: i64,
+    /// Wall time of the whole run in seconds
+    pub run_duration: Option<i64>,
+    /// Whether the CPU was thermally throttled during the run, `None` if it wasn't watched
+    pub throttled: Option<bool>,
+    pub max_temperature_c: Option<f64>,
+    /// Whether the run started from the data dir of the run before, measuring incremental work
+    pub datadir_reused: bool,
+    /// The primary job's headline metric, if a job is primary and ran
+    pub headline: Option<Headline>,
+    /// Notes added with `annotate`, each prefixed with when it was added
+    pub notes: Vec<String>,
+}
+
+/// Aggregate user time statistics for a job as output by the `summary` command.
+#[derive(Serialize, Debug)]
+pub struct JobSummary {
+    pub job_name: String,
+    pub runs: usize,
+    pub mean_user_time: f64,
+    pub min_user_time: f64,
+    pub max_user_time: f64,
+    pub latest_commit_id: String,
+    pub latest_run_date: String,
+    /// Wall time in seconds of the latest run containing the job, including its other jobs
+    pub latest_run_duration: Option<i64>,
+    /// The headline of the job's latest run, only for the primary job
+    pub headline: Option<Headline>,
+}
+
+pub fn list_runs(
+    db: &Database,
+    format: OutputFormat,
+    raw: bool,
+    primary: Option<(&str, Metric)>,
+) -> Result<()> {
+    let mut headlines = headlines(db, primary)?;
+    let runs: Vec<RunSummary> = db
+        .get_runs()?
+        .into_iter()
+        .map(|(run, job_count)| RunSummary {
+            headline: run.id.and_then(|id| headlines.remove(&(id as i64))),
+            run_id: run.id.unwrap_or_default() as i64,
+            run_date: util::unix_timestamp_to_iso(run.run_date),
+            commit_id: run.commit_id,
+            commit_date: util::unix_timestamp_to_iso(run.commit_date),
+            commit_index: run.commit_index,
+            was_master: run.was_master,
+            job_count,
+            run_duration: run.run_duration,
+            throttled: run.throttled,
+            max_temperature_c: run.max_temperature_c,
+            datadir_reused: run.datadir_reused,
+            notes: run
+                .notes
+                .map(|notes| notes.lines().map(String::from).collect())
+                .unwrap_or_default(),
+        })
+        .collect();
+
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&runs)?),
+        OutputFormat::Table => {
+            println!(
+                "{:>6} {:<20} {:<40} {:<20} {:>6} {:>5} {:>10} {:>9}  {:<12}  notes",
+                "run",
+                "run date",
+                "commit",
+                "commit date",
+                "master",
+                "jobs",
+                "duration",
+                "throttled",
+                "headline"
+            );
+            for run in &runs {
+                println!(
+                    "{:>6} {:<20} {:<40} {:<20} {:>6} {:>5} {:>10} {:>9}  {:<12}  {}",
+                    run.run_id,
+                    run.run_date,
+                    run.commit_id,
+                    run.commit_date,
+                    run.was_master,
+                    run.job_count,
+                    run.run_duration.map_or("-".to_string(), |secs| match raw {
+                        true => secs.to_string(),
+                        false => util::format_duration(secs as f64),
+                    }),
+                    match run.throttled {
+                        Some(true) => "yes",
+                        Some(false) => "no",
+                        None => "-",
+                    },
+                    run.headline
+                        .as_ref()
+                        .map_or("-".to_string(), |headline| headline.describe(raw)),
+                    match (run.notes.as_slice(), run.datadir_reused) {
+                        ([], false) => "-".to_string(),
+                        ([], true) => "datadir reused".to_string(),
+                        (notes, false) => notes.join(" | "),
+                        (notes, true) => format!("datadir reused | {}", notes.join(" | ")),
+                    }
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn summarise_jobs(
+    db: &Database,
+    format: OutputFormat,
+    raw: bool,
+    primary: Option<(&str, Metric)>,
+) -> Result<()> {
+    let mut headlines = headlines(db, primary)?;
+    let mut summaries = Vec::new();
+    for job_name in db.get_job_names()? {
+        let jobs_with_runs = db.get_jobs_by_name(&job_name)?;
+        let Some((latest_job, latest)) = jobs_with_runs.last() else {
+            continue;
+        };
+        let headline = match primary {
+            Some((primary_job, _)) if primary_job == job_name => {
+                headlines.remove(&latest_job.run_id)
+            }
+            _ => None,
+        };
+        let user_times: Vec<f64> = jobs_with_runs
+            .iter()
+            .map(|(job, _)| job.result.user_time)
+            .collect();
+        summaries.push(JobSummary {
+            job_name: job_name.clone(),
+            runs: user_times.len(),
+            mean_user_time: user_times.iter().sum::<f64>() / user_times.len() as f64,
+            min_user_time: user_times.iter().cloned().fold(f64::INFINITY, f64::min),
+            max_user_time: user_times.iter().cloned().fold(0.0, f64::max),
+            latest_commit_id: latest.commit_id.clone(),
+            latest_run_date: util::unix_timestamp_to_iso(latest.run_date),
+            latest_run_duration: latest.run_duration,
+            headline,
+        });
+    }
+
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&summaries)?),
+        OutputFormat::Table => {
+            let seconds = |secs: f64| match raw {
+                true => format!("{:.2}", secs),
+                false => util::format_metric(Metric::UserTime, secs),
+            };
+            let (mean, min, max) = match raw {
+                true => ("mean (s)", "min (s)", "max (s)"),
+                false => ("mean", "min", "max"),
+            };
+            println!(
+                "{:<24} {:>6} {:>12} {:>12} {:>12} {:<20} {:>10}",
+                "job", "runs", mean, min, max, "latest run", "run time"
+            );
+            for summary in &summaries {
+                println!(
+                    "{:<24} {:>6} {:>12} {:>12} {:>12} {:<20} {:>10}",
+                    summary.job_name,
+                    summary.runs,
+                    seconds(summary.mean_user_time),
+                    seconds(summary.min_user_time),
+                    seconds(summary.max_user_time),
+                    summary.latest_run_date,
+                    summary
+                        .latest_run_duration
+                        .map_or("-".to_string(), |secs| match raw {
+                            true => secs.to_string(),
+                            false => util::format_duration(secs as f64),
+                        })
+                );
+            }
+            if let Some(headline) = summaries.iter().find_map(|s| s.headline.as_ref()) {
+                println!("\nheadline: {}", headline.describe(raw));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn compare_machines(db: &Database, commit: &str, job_name: &str, metric: Metric) -> Result<()> {
+    let jobs_with_runs = db.get_jobs_by_commit(commit, job_name)?;
+    if jobs_with_runs.is_empty() {
+        bail!(
+            "No successful {} jobs found for commit {}",
+            job_name,
+            commit
+        );
+    }
+
+    // Group values by machine identity, runs recorded before it was captured have none
+    let mut machines: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    let mut unidentified = 0;
+    for (job, run) in &jobs_with_runs {
+        let Some(value) = metric.value(&job.result) else {
+            continue;
+        };
+        match (&run.cpu_model, run.num_cores) {
+            (Some(cpu_model), Some(num_cores)) => machines
+                .entry(match &run.remote_host {
+                    Some(host) => format!("{} ({} cores) on {}", cpu_model, num_cores, host),
+                    None => format!("{} ({} cores)", cpu_model, num_cores),
+                })
+                .or_default()
+                .push(value),
+            _ => unidentified += 1,
+        }
+    }
+
+    if machines.is_empty() {
+        bail!(
+            "None of the {} recorded {} jobs for commit {} have machine information; \
+            re-run the benchmark with this version to record num_cores and cpu_model",
+            unidentified,
+            job_name,
+            commit
+        );
+    }
+    if unidentified > 0 {
+        info!(
+            "Ignoring {} jobs recorded without machine information",
+            unidentified
+        );
+    }
+
+    let means: Vec<(String, usize, f64)> = machines
+        .into_iter()
+        .map(|(machine, values)| {
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            (machine, values.len(), mean)
+        })
+        .collect();
+    let best = means
+        .iter()
+        .map(|(_, _, mean)| *mean)
+        .fold(f64::INFINITY, f64::min);
+
+    println!("{} for {} at commit {}", metric.label(), job_name, commit);
+    println!(
+        "{:<60} {:>6} {:>16} {:>10}",
+        "machine", "runs", "mean", "relative"
+    );
+    for (machine, runs, mean) in &means {
+        let relative = if best > 0.0 { mean / best } else { 1.0 };
+        println!(
+            "{:<60} {:>6} {:>16.2} {:>9.2}x",
+            machine, runs, mean, relative
+        );
+    }
+
+    Ok(())
+}
+
+/// Coefficient of variation (stddev / mean) of `values`, as a percentage.
+pub fn coefficient_of_variation(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+    let (mean, stddev) = mean_and_stddev(values)?;
+    if mean == 0.0 {
+        return None;
+    }
+    Some(stddev / mean * 100.0)
+}
+
+/// Report how noisy `metric` has been across the most recent master runs of each job.
+pub fn report_stability(
+    db: &Database,
+    job_name: Option<&str>,
+    metric: Metric,
+    recent: usize,
+    threshold: f64,
+) -> Result<()> {
+    let job_names = match job_name {
+        Some(name) => vec![name.to_string()],
+        None => db.get_job_names()?,
+    };
+
+    println!(
+        "{} stability over the last {} master runs (threshold {:.2}%)",
+        metric.label(),
+        recent,
+        threshold
+    );
+    println!("{:<24} {:>6} {:>10}  status", "job", "runs", "cv (%)");
+    let mut unreliable = Vec::new();
+    let mut measured = 0;
+    for job_name in job_names {
+        let values: Vec<f64> = db
+            .get_jobs_by_name(&job_name)?
+            .iter()
+            .filter(|(_, run)| run.was_master)
+            .rev()
+            .take(recent)
+            .filter_map(|(job, _)| metric.value(&job.result))
+            .collect();
+
+        match coefficient_of_variation(&values) {
+            Some(cv) => {
+                measured += 1;
+                let status = if cv > threshold {
+                    unreliable.push(job_name.clone());
+                    "unreliable for gating"
+                } else {
+                    "stable"
+                };
+                println!(
+                    "{:<24} {:>6} {:>10.2}  {}",
+                    job_name,
+                    values.len(),
+                    cv,
+                    status
+                );
+            }
+            None => println!(
+                "{:<24} {:>6} {:>10}  not enough data",
+                job_name,
+                values.len(),
+                "-"
+            ),
+        }
+    }
+
+    println!(
+        "{} of {} measured jobs are stable{}",
+        measured - unreliable.len(),
+        measured,
+        if unreliable.is_empty() {
+            String::new()
+        } else {
+            format!(", unreliable: {}", unreliable.join(", "))
+        }
+    );
+    Ok(())
+}
+
+/// Pearson correlation coefficient of `pairs`, `None` if either side is constant or there are
+/// fewer than two pairs.
+pub fn pearson_correlation(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.len() < 2 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let (mut covariance, mut variance_x, mut variance_y) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance_x += (x - mean_x).powi(2);
+        variance_y += (y - mean_y).powi(2);
+    }
+    if variance_x == 0.0 || variance_y == 0.0 {
+        return None;
+    }
+    Some(covariance / (variance_x * variance_y).sqrt())
+}
+
+/// Report how closely `metric_a` and `metric_b` move together across the runs of `job_name`,
+/// optionally scatter-plotting them into `plot_dir`.
+pub fn report_correlation(
+    db: &Database,
+    job_name: &String,
+    metric_a: Metric,
+    metric_b: Metric,
+    plot_dir: Option<&Path>,
+) -> Result<()> {
+    let pairs: Vec<(f64, f64)> = db
+        .get_jobs_by_name(job_name)?
+        .iter()
+        .filter_map(|(job, _)| Some((metric_a.value(&job.result)?, metric_b.value(&job.result)?)))
+        .collect();
+    if pairs.len() < 2 {
+        bail!(
+            "Job {} needs at least 2 recorded runs to correlate, found {}",
+            job_name,
+            pairs.len()
+        );
+    }
+
+    println!(
+        "{} vs {} for {} over {} runs",
+        metric_a.label(),
+        metric_b.label(),
+        job_name,
+        pairs.len()
+    );
+    match pearson_correlation(&pairs) {
+        Some(r) => {
+            let strength = match r.abs() {
+                r if r >= 0.7 => "strong",
+                r if r >= 0.4 => "moderate",
+                r if r >= 0.2 => "weak",
+                _ => "no",
+            };
+            let direction = match (strength, r < 0.0) {
+                ("no", _) => "",
+                (_, true) => " negative",
+                (_, false) => " positive",
+            };
+            println!(
+                "pearson r = {:.3} ({}{} correlation)",
+                r, strength, direction
+            );
+        }
+        None => {
+            let constant = match pairs.iter().all(|(x, _)| *x == pairs[0].0) {
+                true => metric_a,
+                false => metric_b,
+            };
+            println!("pearson r = undefined, {} is constant", constant.label());
+        }
+    }
+
+    if let Some(dir) = plot_dir {
+        let file_path = dir.join(format!(
+            "{}-{}-vs-{}.png",
+            job_name.replace("./", "").replace(' ', "_"),
+            metric_a.name(),
+            metric_b.name()
+        ));
+        graph::plot_correlation(&pairs, &file_path, job_name, metric_a, metric_b)?;
+        println!("scatter plot written to {}", file_path.display());
+    }
+    Ok(())
+}
+
+/// List the commits between two runs as candidates for a change in `job_name`'s results.
+pub fn list_suspects(
+    db: &Database,
+    src_dir: &Path,
+    job_name: &str,
+    from_run: i64,
+    to_run: i64,
+) -> Result<()> {
+    let mut from = db.get_run(from_run)?;
+    let mut to = db.get_run(to_run)?;
+
+    if util::is_ancestor(src_dir, &to.commit_id, &from.commit_id)?
+        && !util::is_ancestor(src_dir, &from.commit_id, &to.commit_id)?
+    {
+        info!("Run {} is newer than run {}, swapping", from_run, to_run);
+        std::mem::swap(&mut from, &mut to);
+    } else if !util::is_ancestor(src_dir, &from.commit_id, &to.commit_id)? {
+        warn!(
+            "{} is not an ancestor of {}, listing commits only reachable from the latter",
+            from.commit_id, to.commit_id
+        );
+    }
+
+    let jobs_with_runs = db.get_jobs_by_name(&job_name.to_string())?;
+    let user_time = |run: &Run| {
+        jobs_with_runs
+            .iter()
+            .find(|(_, job_run)| job_run.id == run.id)
+            .map(|(job, _)| format!("{:.2}s", job.result.user_time))
+            .unwrap_or_else(|| "no result".to_string())
+    };
+
+    let suspects = util::get_git_log(src_dir, &from.commit_id, &to.commit_id)?;
+    println!(
+        "{} user time {} at {} -> {} at {}",
+        job_name,
+        user_time(&from),
+        from.commit_id,
+        user_time(&to),
+        to.commit_id
+    );
+    println!("{} suspect commit(s):", suspects.len());
+    for suspect in suspects {
+        println!("  {}", suspect);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Markdown table for pasting into a pull request
+    Md,
+    /// JSON array of records
+    Json,
+    /// Comma separated records, only with `--raw`
+    Csv,
+    /// InfluxDB line protocol of every recorded job, for Telegraf or `influx write`
+    Influx,
+}
+
+/// One metric of one job in an exported comparison.
+#[derive(Serialize, Debug)]
+pub struct ComparisonRow {
+    pub job_name: String,
+    pub metric: String,
+    pub base_commit_id: Option<String>,
+    pub base: Option<f64>,
+    pub head_commit_id: String,
+    pub head: f64,
+    pub change_percent: Option<f64>,
+    /// Whether this is the primary job's headline metric
+    pub primary: bool,
+}
+
+/// Latest result at `commit` if given, otherwise the latest result overall.
+fn select_job<'a>(
+    jobs_with_runs: &'a [(Job, Run)],
+    commit: Option<&str>,
+) -> Option<&'a (Job, Run)> {
+    jobs_with_runs
+        .iter()
+        .filter(|(_, run)| commit.is_none_or(|commit| run.commit_id.starts_with(commit)))
+        .max_by_key(|(job, _)| job.run_id)
+}
+
+/// Export the latest result of each job, compared against `base` or else against the previous
+/// master run as used by `check`.
+pub fn export_comparison(
+    db: &Database,
+    out: &mut dyn Write,
+    format: ExportFormat,
+    base: Option<&str>,
+    head: Option<&str>,
+    metrics: &[Metric],
+    primary: Option<(&str, Metric)>,
+) -> Result<()> {
+    let mut rows = Vec::new();
+    let mut headline = None;
+    let mut header: Option<(Run, Option<Run>)> = None;
+    for job_name in db.get_job_names()? {
+        let jobs_with_runs = db.get_jobs_by_name(&job_name)?;
+        let Some(candidate) = select_job(&jobs_with_runs, head) else {
+            continue;
+        };
+        let baseline = match base {
+            Some(_) => select_job(&jobs_with_runs, base),
+            None => fetch_baseline(&jobs_with_runs, candidate),
+        };
+
+        if header
+            .as_ref()
+            .is_none_or(|(latest, _)| latest.run_date < candidate.1.run_date)
+        {
+            header = Some((
+                db.get_run(candidate.0.run_id)?,
+                baseline
+                    .map(|(job, _)| db.get_run(job.run_id))
+                    .transpose()?,
+            ));
+        }
+
+        let change = |metric: &Metric| {
+            let value = metric.value(&candidate.0.result)?;
+            let base_value = baseline.and_then(|(job, _)| metric.value(&job.result));
+            let change_percent = base_value
+                .filter(|base| *base > 0.0)
+                .map(|base| (value - base) / base * 100.0);
+            Some((value, base_value, change_percent))
+        };
+        let headline_metric = primary
+            .filter(|(primary_job, _)| *primary_job == job_name)
+            .map(|(_, metric)| metric);
+        if let Some((metric, (value, _, change_percent))) =
+            headline_metric.and_then(|metric| Some((metric, change(&metric)?)))
+        {
+            headline = Some(Headline {
+                job_name: job_name.clone(),
+                metric,
+                value,
+                change_percent,
+            });
+        }
+
+        // Metrics the head job didn't measure are left out rather than shown as 0
+        for metric in metrics {
+            let Some((value, base_value, change_percent)) = change(metric) else {
+                continue;
+            };
+            rows.push(ComparisonRow {
+                job_name: job_name.clone(),
+                metric: metric.label().to_string(),
+                base_commit_id: baseline.map(|(_, run)| run.commit_id.clone()),
+                base: base_value,
+                head_commit_id: candidate.1.commit_id.clone(),
+                head: value,
+                change_percent,
+                primary: headline_metric == Some(*metric),
+            });
+        }
+    }
+
+    let Some((head_run, base_run)) = header else {
+        bail!("No successful jobs recorded to export");
+    };
+
+    match format {
+        ExportFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&rows)?)?,
+        ExportFormat::Csv => bail!("CSV export is only supported with --raw"),
+        ExportFormat::Influx => bail!("Influx export writes every job rather than a comparison"),
+        ExportFormat::Md => {
+            let describe = |run: &Run| {
+                format!(
+                    "`{}` ({})",
+                    &run.commit_id[..run.commit_id.len().min(12)],
+                    util::unix_timestamp_to_iso(run.commit_date)
+                )
+            };
+            writeln!(out, "### Benchmark results\n")?;
+            writeln!(out, "**Head:** {}  ", describe(&head_run))?;
+            match &base_run {
+                Some(run) => writeln!(out, "**Base:** {}\n", describe(run))?,
+                None => writeln!(out, "**Base:** none\n")?,
+            }
+            if let Some(headline) = &headline {
+                writeln!(out, "**Headline:** {}\n", headline.describe(false))?;
+            }
+
+            let table: Vec<Vec<String>> = rows
+                .iter()
+                .map(|row| {
+                    vec![
+                        row.job_name.clone(),
+                        row.metric.clone(),
+                        row.base.map_or("-".to_string(), format_value),
+                        format_value(row.head),
+                        row.change_percent
+                            .map_or("-".to_string(), |change| format!("{:+.2}%", change)),
+                    ]
+                })
+                .collect();
+            write!(
+                out,
+                "{}",
+                markdown_table(
+                    &["job", "metric", "base", "head", "change"],
+                    &[2, 3, 4],
+                    &table
+                )
+            )?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+/// Render a Markdown table with padded columns, right aligning the columns in `numeric`.
+fn markdown_table(headers: &[&str], numeric: &[usize], rows: &[Vec<String>]) -> String {
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            rows.iter()
+                .map(|row| row[i].len())
+                .chain([header.len(), 3])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let render = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+    let pad = |i: usize, cell: &str| {
+        if numeric.contains(&i) {
+            format!("{:>width$}", cell, width = widths[i])
+        } else {
+            format!("{:<width$}", cell, width = widths[i])
+        }
+    };
+
+    let mut table = render(headers.iter().enumerate().map(|(i, h)| pad(i, h)).collect());
+    table.push_str(&render(
+        widths
+            .iter()
+            .enumerate()
+            .map(|(i, width)| {
+                if numeric.contains(&i) {
+                    format!("{}:", "-".repeat(width - 1))
+                } else {
+                    "-".repeat(*width)
+                }
+            })
+            .collect(),
+    ));
+    for row in rows {
+        table.push_str(&render(
+            row.iter()
+                .enumerate()
+                .map(|(i, cell)| pad(i, cell))
+                .collect(),
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn correlates_metrics() {
+        let r = pearson_correlation(&[(1.0, 2.0), (2.0, 4.1), (3.0, 5.9)]).unwrap();
+        assert!(r > 0.99);
+        let r = pearson_correlation(&[(1.0, 3.0), (2.0, 2.0), (3.0, 1.0)]).unwrap();
+        assert!((r + 1.0).abs() < 1e-9);
+        assert_eq!(
+            pearson_correlation(&[(1.0, 5.0), (2.0, 5.0), (3.0, 5.0)]),
+            None
+        );
+        assert_eq!(pearson_correlation(&[(1.0, 5.0)]), None);
+    }
+
+    #[test]
+    fn renders_aligned_markdown_table() {
+        let rows = vec![
+            vec!["IBD".to_string(), "12.50".to_string(), "+1.25%".to_string()],
+            vec!["make".to_string(), "3".to_string(), "-".to_string()],
+        ];
+        let table = markdown_table(&["job", "head", "change"], &[1, 2], &rows);
+        assert_eq!(
+            table,
+            "| job  |  head | change |\n\
+             | ---- | ----: | -----: |\n\
+             | IBD  | 12.50 | +1.25% |\n\
+             | make |     3 |      - |\n"
+        );
+    }
+}
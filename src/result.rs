@@ -1,12 +1,11 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
 use log::debug;
+use serde::{Deserialize, Serialize};
 
 extern crate exitcode;
 
-use std::fs::File;
-use std::io::BufRead;
-
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct TimeResult {
     pub command: String,
     pub user_time: f64,
@@ -19,11 +18,223 @@ pub struct TimeResult {
     pub involuntary_context_switches: i64,
     pub file_system_outputs: i64,
     pub exit_status: i32,
+    pub page_size_bytes: Option<i64>,
+    pub signals_delivered: Option<i64>,
+    /// Wall clock time in seconds
+    pub elapsed: Option<f64>,
+    /// Bytes read from storage, from `/proc/<pid>/io` rather than GNU time
+    pub read_bytes: Option<i64>,
+    /// Bytes written to storage, from `/proc/<pid>/io` rather than GNU time
+    pub write_bytes: Option<i64>,
+}
+
+/// Spread of a metric over repeated executions of a job.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spread {
+    pub min: f64,
+    pub max: f64,
+    /// Sample standard deviation, zero for a single value
+    pub stddev: f64,
+}
+
+impl Spread {
+    pub fn from_values(values: &[f64]) -> Option<Self> {
+        let (_, stddev) = mean_and_stddev(values)?;
+        Some(Spread {
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            stddev,
+        })
+    }
+}
+
+/// Mean and sample standard deviation of `values`, the latter zero for a single value.
+pub fn mean_and_stddev(values: &[f64]) -> Option<(f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = if values.len() > 1 {
+        values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64
+    } else {
+        0.0
+    };
+    Some((mean, variance.sqrt()))
+}
+
+/// Index of the median of `values`, the lower of the middle two for an even count, or 0 if there
+/// are none.
+pub fn median_index(values: &[f64]) -> usize {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    order
+        .get(values.len().saturating_sub(1) / 2)
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Spreads of the headline metrics over the executions of a job.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JobStats {
+    pub user_time: Spread,
+    pub max_rss: Spread,
+    pub elapsed: Option<Spread>,
+}
+
+impl JobStats {
+    pub fn from_results(results: &[TimeResult]) -> Option<Self> {
+        let values = |value: fn(&TimeResult) -> f64| results.iter().map(value).collect::<Vec<_>>();
+        let elapsed: Option<Vec<f64>> = results.iter().map(|r| r.elapsed).collect();
+        Some(JobStats {
+            user_time: Spread::from_values(&values(|r| r.user_time))?,
+            max_rss: Spread::from_values(&values(|r| r.max_resident_set_size_kb as f64))?,
+            elapsed: elapsed.and_then(|elapsed| Spread::from_values(&elapsed)),
+        })
+    }
+
+    /// The spread recorded for `metric`, if it is one of the metrics tracked.
+    pub fn spread(&self, metric: Metric) -> Option<Spread> {
+        match metric {
+            Metric::UserTime => Some(self.user_time),
+            Metric::MaxRss => Some(self.max_rss),
+            Metric::Elapsed => self.elapsed,
+            _ => None,
+        }
+    }
+}
+
+/// A single numeric metric that can be selected from a [`TimeResult`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Metric {
+    UserTime,
+    SystemTime,
+    PercentOfCpu,
+    MaxRss,
+    MajorPageFaults,
+    MinorPageFaults,
+    VoluntaryContextSwitches,
+    InvoluntaryContextSwitches,
+    FileSystemOutputs,
+    Elapsed,
+    ReadBytes,
+    WriteBytes,
+}
+
+impl TryFrom<String> for Metric {
+    type Error = anyhow::Error;
+
+    /// Accepts either `max_rss` or `max-rss` style names.
+    fn try_from(s: String) -> Result<Self> {
+        Metric::from_str(&s.replace('_', "-"), true)
+            .map_err(|e| anyhow!("Invalid metric '{}': {}", s, e))
+    }
+}
+
+impl From<Metric> for String {
+    fn from(metric: Metric) -> Self {
+        metric.name()
+    }
+}
+
+impl Metric {
+    /// The name used for the metric on the command line, e.g. `max-rss`.
+    pub fn name(&self) -> String {
+        self.to_possible_value()
+            .expect("no metric is skipped")
+            .get_name()
+            .to_string()
+    }
+
+    /// The metric's value in `result`, `None` if it wasn't measured rather than a misleading 0.
+    pub fn value(&self, result: &TimeResult) -> Option<f64> {
+        match self {
+            Metric::UserTime => Some(result.user_time),
+            Metric::SystemTime => Some(result.system_time),
+            Metric::PercentOfCpu => Some(result.percent_of_cpu as f64),
+            Metric::MaxRss => Some(result.max_resident_set_size_kb as f64),
+            Metric::MajorPageFaults => Some(result.major_page_faults as f64),
+            Metric::MinorPageFaults => Some(result.minor_page_faults as f64),
+            Metric::VoluntaryContextSwitches => Some(result.voluntary_context_switches as f64),
+            Metric::InvoluntaryContextSwitches => Some(result.involuntary_context_switches as f64),
+            Metric::FileSystemOutputs => Some(result.file_system_outputs as f64),
+            Metric::Elapsed => result.elapsed,
+            Metric::ReadBytes => result.read_bytes.map(|bytes| bytes as f64),
+            Metric::WriteBytes => result.write_bytes.map(|bytes| bytes as f64),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Metric::UserTime => "User Time (s)",
+            Metric::SystemTime => "System Time (s)",
+            Metric::PercentOfCpu => "CPU (%)",
+            Metric::MaxRss => "Max RSS (KB)",
+            Metric::MajorPageFaults => "Major Page Faults",
+            Metric::MinorPageFaults => "Minor Page Faults",
+            Metric::VoluntaryContextSwitches => "Voluntary Context Switches",
+            Metric::InvoluntaryContextSwitches => "Involuntary Context Switches",
+            Metric::FileSystemOutputs => "File System Outputs",
+            Metric::Elapsed => "Elapsed (s)",
+            Metric::ReadBytes => "Bytes Read",
+            Metric::WriteBytes => "Bytes Written",
+        }
+    }
 }
 
+/// Parse GNU time's `h:mm:ss` or `m:ss` elapsed time into seconds.
+fn parse_elapsed(value: &str) -> Result<f64> {
+    value
+        .split(':')
+        .try_fold(0.0, |total, part| Ok(total * 60.0 + part.parse::<f64>()?))
+}
+
+/// Keys which must appear in a time dump for it to be considered complete, with the name used
+/// when reporting them as missing.
+const REQUIRED_FIELDS: [(&str, &str); 3] = [
+    ("Command being timed", "command"),
+    ("User time (seconds)", "user_time"),
+    ("Elapsed (wall clock) time (h:mm:ss or m:ss)", "elapsed"),
+];
+
 impl TimeResult {
-    fn parse_line(&mut self, line: &str) -> Result<()> {
-        let parts: Vec<&str> = line.rsplitn(2, ": ").collect();
+    /// The mean of each measurement of `results`, e.g. the samples of a run recorded with
+    /// `record_individual_samples`, or `None` if there are none. Optional measurements are the
+    /// mean of the results which have them, the rest are taken from the first result.
+    pub fn mean(results: &[TimeResult]) -> Option<Self> {
+        let first = results.first()?;
+        let mean = |value: fn(&TimeResult) -> f64| {
+            let values: Vec<f64> = results.iter().map(value).collect();
+            mean_and_stddev(&values).map_or(0.0, |(mean, _)| mean)
+        };
+        let optional_mean = |value: fn(&TimeResult) -> Option<f64>| {
+            let values: Vec<f64> = results.iter().filter_map(value).collect();
+            mean_and_stddev(&values).map(|(mean, _)| mean)
+        };
+        Some(TimeResult {
+            user_time: mean(|r| r.user_time),
+            system_time: mean(|r| r.system_time),
+            percent_of_cpu: mean(|r| r.percent_of_cpu.into()).round() as i32,
+            max_resident_set_size_kb: mean(|r| r.max_resident_set_size_kb as f64).round() as i64,
+            major_page_faults: mean(|r| r.major_page_faults as f64).round() as i64,
+            minor_page_faults: mean(|r| r.minor_page_faults as f64).round() as i64,
+            voluntary_context_switches: mean(|r| r.voluntary_context_switches as f64).round()
+                as i64,
+            involuntary_context_switches: mean(|r| r.involuntary_context_switches as f64).round()
+                as i64,
+            file_system_outputs: mean(|r| r.file_system_outputs as f64).round() as i64,
+            elapsed: optional_mean(|r| r.elapsed),
+            read_bytes: optional_mean(|r| r.read_bytes.map(|bytes| bytes as f64))
+                .map(|bytes| bytes.round() as i64),
+            write_bytes: optional_mean(|r| r.write_bytes.map(|bytes| bytes as f64))
+                .map(|bytes| bytes.round() as i64),
+            ..first.clone()
+        })
+    }
+
+    /// Parse a single line into the result, returning the key if the line had one.
+    fn parse_line<'a>(&mut self, line: &'a str) -> Result<Option<&'a str>> {
+        let parts: Vec<&'a str> = line.rsplitn(2, ": ").collect();
         if parts.len() == 2 {
             let value = parts[0].trim();
             let key = parts[1].trim();
@@ -32,6 +243,9 @@ impl TimeResult {
                 "Command being timed" => self.command = value.to_string().replace('"', ""),
                 "User time (seconds)" => self.user_time = value.parse()?,
                 "System time (seconds)" => self.system_time = value.parse()?,
+                "Elapsed (wall clock) time (h:mm:ss or m:ss)" => {
+                    self.elapsed = Some(parse_elapsed(value)?)
+                }
                 "Percent of CPU this job got" => {
                     self.percent_of_cpu = value.trim_end_matches('%').parse()?
                 }
@@ -48,24 +262,174 @@ impl TimeResult {
                 }
                 "File system outputs" => self.file_system_outputs = value.parse()?,
                 "Exit status" => self.exit_status = value.parse()?,
+                "Page size (bytes)" => self.page_size_bytes = Some(value.parse()?),
+                "Signals delivered" | "Signals delivered (nsignals)" => {
+                    self.signals_delivered = Some(value.parse()?)
+                }
                 _ => {
                     debug!("Failed to match key: {} against Result struct", key);
                 }
             }
+            return Ok(Some(key));
+        }
+        Ok(None)
+    }
+
+    /// Build a result from the resource usage `wait4` reported for a job run without GNU time.
+    pub fn from_rusage(
+        command: String,
+        usage: &libc::rusage,
+        exit_status: i32,
+        elapsed: f64,
+    ) -> Self {
+        let seconds = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1e6;
+        let (user_time, system_time) = (seconds(usage.ru_utime), seconds(usage.ru_stime));
+        // macOS reports max RSS in bytes rather than kilobytes
+        let max_rss_kb = if cfg!(target_os = "macos") {
+            usage.ru_maxrss / 1024
+        } else {
+            usage.ru_maxrss
+        };
+        TimeResult {
+            command,
+            user_time,
+            system_time,
+            percent_of_cpu: if elapsed > 0.0 {
+                ((user_time + system_time) / elapsed * 100.0).round() as i32
+            } else {
+                0
+            },
+            max_resident_set_size_kb: max_rss_kb,
+            major_page_faults: usage.ru_majflt,
+            minor_page_faults: usage.ru_minflt,
+            voluntary_context_switches: usage.ru_nvcsw,
+            involuntary_context_switches: usage.ru_nivcsw,
+            file_system_outputs: usage.ru_oublock,
+            exit_status,
+            elapsed: Some(elapsed),
+            ..Default::default()
         }
-        Ok(())
     }
 
     pub fn from_file(file_path: &str) -> Result<Self> {
-        let file =
-            File::open(file_path).with_context(|| format!("Failed to open file: {}", file_path))?;
-        let reader = std::io::BufReader::new(file);
+        let text = std::fs::read_to_string(file_path)
+            .with_context(|| format!("Failed to open file: {}", file_path))?;
+        Self::parse(&text, file_path)
+    }
+
+    /// Parse a time dump read from `source`, which is only used in error messages.
+    pub fn parse(text: &str, source: &str) -> Result<Self> {
         let mut result = TimeResult::default();
+        let mut seen = Vec::new();
 
-        for line in reader.lines() {
-            let line = line.with_context(|| "Failed to read line from file")?;
-            result.parse_line(&line)?;
+        for line in text.lines() {
+            if let Some(key) = result.parse_line(line)? {
+                seen.push(key.to_string());
+            }
+        }
+
+        let missing: Vec<&str> = REQUIRED_FIELDS
+            .iter()
+            .filter(|(key, _)| !seen.iter().any(|k| k == key))
+            .map(|(_, name)| *name)
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "Incomplete time output in {}, missing: {}",
+                source,
+                missing.join(", ")
+            );
         }
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn parses_gnu_time_output() {
+        let result = TimeResult::from_file(&fixture("gnu-time.txt")).unwrap();
+        assert_eq!(result.command, "sleep 1");
+        assert_eq!(result.user_time, 1.25);
+        assert_eq!(result.system_time, 0.5);
+        assert_eq!(result.percent_of_cpu, 99);
+        assert_eq!(result.max_resident_set_size_kb, 1920);
+        assert_eq!(result.major_page_faults, 3);
+        assert_eq!(result.minor_page_faults, 86);
+        assert_eq!(result.voluntary_context_switches, 2);
+        assert_eq!(result.involuntary_context_switches, 7);
+        assert_eq!(result.file_system_outputs, 16);
+        assert_eq!(result.exit_status, 0);
+        assert_eq!(result.page_size_bytes, Some(4096));
+        assert_eq!(result.signals_delivered, Some(1));
+        assert_eq!(result.elapsed, Some(1.76));
+    }
+
+    #[test]
+    fn parses_elapsed_with_hours() {
+        assert_eq!(parse_elapsed("1:02:03.5").unwrap(), 3723.5);
+        assert!(parse_elapsed("").is_err());
+    }
+
+    #[test]
+    fn computes_job_stats() {
+        let result = |user_time, max_rss, elapsed| TimeResult {
+            user_time,
+            max_resident_set_size_kb: max_rss,
+            elapsed,
+            ..Default::default()
+        };
+        let single = JobStats::from_results(&[result(2.0, 100, Some(3.0))]).unwrap();
+        assert_eq!(
+            single.user_time,
+            Spread {
+                min: 2.0,
+                max: 2.0,
+                stddev: 0.0
+            }
+        );
+
+        let repeated =
+            JobStats::from_results(&[result(1.0, 100, Some(3.0)), result(3.0, 300, None)]).unwrap();
+        assert_eq!(repeated.user_time.min, 1.0);
+        assert_eq!(repeated.user_time.max, 3.0);
+        assert_eq!(repeated.user_time.stddev, 2.0_f64.sqrt());
+        assert_eq!(repeated.max_rss.max, 300.0);
+        assert_eq!(repeated.elapsed, None);
+        assert!(JobStats::from_results(&[]).is_none());
+
+        // An unmeasured elapsed time has no value rather than 0
+        assert_eq!(
+            Metric::Elapsed.value(&result(1.0, 100, Some(3.0))),
+            Some(3.0)
+        );
+        assert_eq!(Metric::Elapsed.value(&result(1.0, 100, None)), None);
+
+        assert_eq!(median_index(&[3.0, 1.0, 2.0]), 2);
+        assert_eq!(median_index(&[4.0, 1.0, 3.0, 2.0]), 3);
+        assert_eq!(median_index(&[5.0]), 0);
+        assert_eq!(median_index(&[]), 0);
+    }
+
+    #[test]
+    fn rejects_truncated_output() {
+        let err = TimeResult::from_file(&fixture("gnu-time-truncated.txt")).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("missing: elapsed"), "{}", message);
+        assert!(!message.contains("user_time"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_empty_output() {
+        let err = TimeResult::from_file(&fixture("gnu-time-empty.txt")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("missing: command, user_time, elapsed"));
+    }
+}
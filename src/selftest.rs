@@ -0,0 +1,147 @@
+use anyhow::{anyhow, bail, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempdir::TempDir;
+
+use crate::database::{Database, Run};
+use crate::graph::{plot_jobs, Aggregate, GraphOptions, Theme, XAxis};
+use crate::result::{Metric, TimeResult};
+use crate::util;
+
+/// Commit id and job name the self-test records its run under.
+const SELFTEST_NAME: &str = "__selftest__";
+
+/// Benchmark `sleep 1` under GNU time, record it in the database and optionally graph it into
+/// `output_dir`, printing whether each stage passed. The recorded run is deleted afterwards, so
+/// an append-only database is left untouched.
+pub fn run_self_test(db: &Database, output_dir: &Path, graph: bool) -> Result<()> {
+    let dir = TempDir::new("bitcoin-bencher-selftest")?;
+    let outfile = dir.path().join("time.txt");
+
+    let time = stage("find time", find_time)?;
+    stage("run job", || run_job(&time, &outfile))?;
+    let result = stage("parse", || {
+        let result = TimeResult::from_file(&outfile.to_string_lossy())?;
+        if result.exit_status != 0 {
+            bail!("time reported exit status {}", result.exit_status);
+        }
+        Ok(result)
+    })?;
+    if db.is_append_only() {
+        // The self-test run couldn't be deleted again
+        println!("{:<10} skipped, database is in append-only mode", "record");
+        println!("self-test passed");
+        return Ok(());
+    }
+    let run_id = stage("record", || record(db, result))?;
+    let graphed = match graph {
+        true => stage("graph", || plot(db, run_id, output_dir)),
+        false => Ok(()),
+    };
+    let cleaned_up = stage("clean up", || db.delete_run(run_id));
+    graphed.and(cleaned_up)?;
+
+    println!("self-test passed");
+    Ok(())
+}
+
+/// Run `f`, printing the outcome of the stage called `name`.
+fn stage<T>(name: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    match f() {
+        Ok(value) => {
+            println!("{:<10} ok", name);
+            Ok(value)
+        }
+        Err(e) => {
+            println!("{:<10} FAILED: {}", name, e);
+            Err(anyhow!("Self-test failed at stage '{}': {}", name, e))
+        }
+    }
+}
+
+/// The GNU time used for jobs, `gtime` on macOS.
+fn find_time() -> Result<PathBuf> {
+    let time = match std::env::consts::OS {
+        "macos" => "/usr/local/bin/gtime",
+        _ => "/usr/bin/time",
+    };
+    which::which(time).map_err(|e| anyhow!("GNU time not found at {}: {}", time, e))
+}
+
+fn run_job(time: &Path, outfile: &Path) -> Result<()> {
+    let status = Command::new(time)
+        .arg("-v")
+        .arg(format!("--output={}", outfile.display()))
+        .args(["sleep", "1"])
+        .status()
+        .map_err(|e| anyhow!("Failed to execute {}: {}", time.display(), e))?;
+    if !status.success() {
+        bail!("sleep 1 under {} exited with {}", time.display(), status);
+    }
+    Ok(())
+}
+
+/// Record the result under a self-test run and check it reads back.
+fn record(db: &Database, mut result: TimeResult) -> Result<i64> {
+    let now = chrono::Utc::now().timestamp();
+    let run_id = db.record_run(Run {
+        id: None,
+        run_date: now,
+        commit_id: SELFTEST_NAME.to_string(),
+        commit_date: now,
+        commit_index: None,
+        tree_hash: None,
+        container_image: None,
+        container_digest: None,
+        signature_verified: None,
+        commit_order: None,
+        commit_range: None,
+        tag: None,
+        run_duration: None,
+        assumevalid: None,
+        par: None,
+        notes: None,
+        from_release: false,
+        was_master: false,
+        num_cores: util::get_nproc().ok().and_then(|n| n.parse().ok()),
+        cpu_model: util::get_cpu_model().ok(),
+        remote_host: None,
+        throttled: None,
+        max_temperature_c: None,
+        datadir_reused: false,
+    })?;
+    result.command = SELFTEST_NAME.to_string();
+    let user_time = result.user_time;
+    db.record_job(run_id, SELFTEST_NAME, result, None, None)?;
+
+    match db.get_jobs_for_run(run_id)?.as_slice() {
+        [(job, _)] if job.result.user_time == user_time => Ok(run_id),
+        jobs => bail!(
+            "Read back {} jobs for run {} instead of the one recorded",
+            jobs.len(),
+            run_id
+        ),
+    }
+}
+
+fn plot(db: &Database, run_id: i64, output_dir: &Path) -> Result<()> {
+    let options = GraphOptions {
+        job_name: SELFTEST_NAME,
+        metric: Metric::UserTime,
+        secondary: Some(Metric::MaxRss),
+        log_scale: false,
+        x_axis: XAxis::Date,
+        aggregate: Aggregate::Daily,
+        whiskers: false,
+        error_bars: false,
+        theme: Theme::Light,
+        raw: false,
+        events: &[],
+        deviation: None,
+    };
+    plot_jobs(
+        &db.get_jobs_for_run(run_id)?,
+        &output_dir.to_string_lossy(),
+        &options,
+    )
+}
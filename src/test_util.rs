@@ -0,0 +1,82 @@
+//! Helpers shared by tests which need a real git repository or recorded jobs.
+
+use std::{fs, path::Path, process::Command};
+use tempdir::TempDir;
+
+use crate::database::{Job, Run};
+
+/// 2024-01-01 12:00 UTC. Fixture commits are three days apart so date lookups are unaffected by
+/// the local timezone.
+pub const FIRST_COMMIT_DATE: i64 = 1704110400;
+pub const DAY: i64 = 86400;
+
+pub fn git(repo: &Path, args: &[&str]) -> String {
+    git_at(repo, args, FIRST_COMMIT_DATE)
+}
+
+fn git_at(repo: &Path, args: &[&str], date: i64) -> String {
+    let date = format!("@{} +0000", date);
+    let output = Command::new("git")
+        .args([
+            "-c",
+            "user.name=Bencher",
+            "-c",
+            "user.email=bencher@example.com",
+        ])
+        .args([
+            "-c",
+            "commit.gpgsign=false",
+            "-c",
+            "init.defaultBranch=master",
+        ])
+        .args(args)
+        .env("GIT_AUTHOR_DATE", &date)
+        .env("GIT_COMMITTER_DATE", &date)
+        .current_dir(repo)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Commit a change to `src/init.cpp` at `date`, returning the commit id.
+pub fn commit_at(repo: &Path, date: i64) -> String {
+    fs::write(repo.join("src/init.cpp"), date.to_string()).unwrap();
+    git_at(repo, &["add", "."], date);
+    git_at(repo, &["commit", "-q", "-m", &date.to_string()], date);
+    git(repo, &["rev-parse", "HEAD"])
+}
+
+/// A bitcoin-like repo on `master` with three commits, returned oldest first.
+pub fn fixture_repo() -> (TempDir, Vec<String>) {
+    let dir = TempDir::new("fixture-repo").unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    git(dir.path(), &["init", "-q"]);
+    let commits = (0..3)
+        .map(|i| commit_at(dir.path(), FIRST_COMMIT_DATE + i * 3 * DAY))
+        .collect();
+    (dir, commits)
+}
+
+/// A job recorded in run `run_id` on `run_date`, on master and with no measurements.
+pub fn job_with_run(run_id: i32, run_date: i64) -> (Job, Run) {
+    let job = Job {
+        job_id: run_id.into(),
+        run_id: run_id.into(),
+        ..Default::default()
+    };
+    let run = Run {
+        id: Some(run_id),
+        run_date,
+        commit_id: format!("commit{}", run_id),
+        commit_date: run_date,
+        was_master: true,
+        ..Default::default()
+    };
+    (job, run)
+}
@@ -1,25 +1,59 @@
 use std::{
+    collections::HashMap,
     fs,
-    path::PathBuf,
-    process::{Command, Stdio},
-    time::{Duration, UNIX_EPOCH},
+    io::{Read, Write},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::process::{CommandExt, ExitStatusExt},
+    },
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::JoinHandle,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use chrono::prelude::*;
+use clap::ValueEnum;
 use log::{debug, info, warn};
+use procfs::{process::Process, CpuInfo, Current};
+use sha2::{Digest, Sha256};
 use which::which;
 
 use crate::config;
+use crate::lock::LOCKFILE_NAME;
+use crate::result::Metric;
 extern crate exitcode;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
-pub fn check_binaries_exist(config: &config::Config) -> Result<()> {
+/// Check the configured binaries exist on PATH. With `allow_missing` only binaries used by a job
+/// are required, and the rest are warned about.
+pub fn check_binaries_exist(config: &config::Config, allow_missing: bool) -> Result<()> {
     // Check required binaries exist on PATH
     let mut all_exist: bool = true;
     for prog in &config.settings.binaries {
         if which(prog).is_err() {
-            warn!("{} not found on PATH", prog);
+            let used_by: Vec<&str> = config
+                .jobs
+                .jobs
+                .iter()
+                .filter(|job| job.uses_binary(prog))
+                .map(|job| job.name.as_str())
+                .collect();
+            if allow_missing && used_by.is_empty() {
+                warn!("{} not found on PATH, but no job uses it", prog);
+                continue;
+            }
+            if used_by.is_empty() {
+                warn!("{} not found on PATH", prog);
+            } else {
+                warn!(
+                    "{} not found on PATH, needed by jobs: {}",
+                    prog,
+                    used_by.join(", ")
+                );
+            }
             all_exist = false;
         } else {
             info!("Found {} binary on $PATH", prog);
@@ -33,6 +67,45 @@ pub fn check_binaries_exist(config: &config::Config) -> Result<()> {
     Ok(())
 }
 
+/// The first line of `<binary> --version` for each configured binary, `None` for binaries which
+/// don't support it.
+pub fn get_binary_versions(config: &config::Config) -> Vec<(String, Option<String>)> {
+    config
+        .settings
+        .binaries
+        .iter()
+        .map(|binary| {
+            let version = get_binary_version(binary)
+                .map_err(|e| debug!("Could not get version of {}: {}", binary, e))
+                .ok();
+            (binary.clone(), version)
+        })
+        .collect()
+}
+
+fn get_binary_version(binary: &str) -> Result<String> {
+    let output = Command::new(binary)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to execute {} --version", binary))?;
+
+    if !output.status.success() {
+        bail!("{} --version exited with {}", binary, output.status);
+    }
+
+    // Some tools print their version to stderr
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(String::from)
+        .context("No version output")
+}
+
 pub fn check_source_file(src_dir_path: &PathBuf) -> Result<&PathBuf> {
     let init_cpp_path = src_dir_path.join("src/init.cpp");
     if !init_cpp_path.exists() {
@@ -50,16 +123,21 @@ pub fn check_source_file(src_dir_path: &PathBuf) -> Result<&PathBuf> {
     Ok(src_dir_path)
 }
 
-pub fn get_commit_id_from_date(src_dir_path: &PathBuf, date: &i64) -> Result<String> {
-    let date = Utc.timestamp_opt(*date, 0).unwrap();
+/// Arguments of the `git rev-list` finding the last master commit before `date`.
+pub fn commit_from_date_args(date: i64) -> Vec<String> {
+    let date = Utc.timestamp_opt(date, 0).unwrap();
     let formatted_date = date.format("%Y-%m-%d %H:%M").to_string();
-    debug!(
-        "Checking out commit closest in date to {:?}",
-        formatted_date
-    );
+    ["rev-list", "-n", "1", "--before", &formatted_date, "master"]
+        .map(String::from)
+        .to_vec()
+}
+
+pub fn get_commit_id_from_date(src_dir_path: &PathBuf, date: &i64) -> Result<String> {
+    let args = commit_from_date_args(*date);
+    debug!("Checking out commit closest in date to {:?}", args[4]);
 
     let commit_id_output = Command::new("git")
-        .args(["rev-list", "-n", "1", "--before", &formatted_date, "master"])
+        .args(&args)
         .current_dir(src_dir_path)
         .output()
         .with_context(|| "Failed to execute git rev-list")?;
@@ -76,33 +154,301 @@ pub fn get_commit_id_from_date(src_dir_path: &PathBuf, date: &i64) -> Result<Str
     Ok(commit_id)
 }
 
-pub fn get_commit_date(repo_path: &PathBuf, commit_id: &str) -> Result<i64> {
+/// Split a `<good>..<bad>` or `<good>...<bad>` range expression into its two ends.
+pub fn parse_commit_range(range: &str) -> Result<(&str, &str)> {
+    let (start, end) = range
+        .split_once("...")
+        .or_else(|| range.split_once(".."))
+        .ok_or_else(|| {
+            anyhow!(
+                "Commit range '{}' must be <good>..<bad> or <good>...<bad>",
+                range
+            )
+        })?;
+    if start.is_empty() || end.is_empty() || end.starts_with('.') {
+        bail!(
+            "Commit range '{}' must name a commit on both sides of the dots",
+            range
+        );
+    }
+    Ok((start, end))
+}
+
+/// Order in which every commit in a range is benchmarked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CommitOrder {
+    /// Parents before children, as needed for ancestry-based analysis
+    Topo,
+    /// By commit date, matching the calendar view
+    Date,
+}
+
+impl CommitOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommitOrder::Topo => "topo",
+            CommitOrder::Date => "date",
+        }
+    }
+}
+
+/// Arguments of the `git rev-list` listing the commits of `range`, oldest first. Without an
+/// `order` only the first-parent chain is listed, which is linear so no order applies to it.
+pub fn commits_in_range_args(range: &str, order: Option<CommitOrder>) -> Vec<String> {
+    let walk = match order {
+        None => "--first-parent",
+        Some(CommitOrder::Topo) => "--topo-order",
+        Some(CommitOrder::Date) => "--date-order",
+    };
+    ["rev-list", "--reverse", walk, range]
+        .map(String::from)
+        .to_vec()
+}
+
+/// Commits in the range expression `range`, oldest first. Both ends must resolve to commits.
+pub fn get_commits_in_range(
+    src_dir_path: &PathBuf,
+    range: &str,
+    order: Option<CommitOrder>,
+) -> Result<Vec<String>> {
+    let (start, end) = parse_commit_range(range)?;
+    for end in [start, end] {
+        let status = Command::new("git")
+            .args([
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("{}^{{commit}}", end),
+            ])
+            .current_dir(src_dir_path)
+            .stdout(Stdio::null())
+            .status()
+            .with_context(|| format!("Failed to execute git rev-parse for {}", end))?;
+        if !status.success() {
+            bail!(
+                "'{}' in commit range '{}' doesn't resolve to a commit",
+                end,
+                range
+            );
+        }
+    }
+    let output = Command::new("git")
+        .args(commits_in_range_args(range, order))
+        .current_dir(src_dir_path)
+        .output()
+        .with_context(|| "Failed to execute git rev-list")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("git rev-list failed: {}", stderr);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// Arguments of the `git tag` listing the tags matching `pattern`, oldest first.
+pub fn tags_args(pattern: &str) -> Vec<String> {
+    [
+        "tag",
+        "-l",
+        pattern,
+        "--sort=creatordate",
+        "--format=%(refname:strip=2)",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Tags matching the glob `pattern` with the commits they point at, in order of creation. Both
+/// annotated and lightweight tags are resolved to their commit, tags of anything other than a
+/// commit are skipped.
+pub fn get_tags(src_dir_path: &PathBuf, pattern: &str) -> Result<Vec<(String, String)>> {
+    let output = Command::new("git")
+        .args(tags_args(pattern))
+        .current_dir(src_dir_path)
+        .output()
+        .with_context(|| "Failed to execute git tag")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git tag failed: {}", stderr);
+    }
+
+    let mut tags = Vec::new();
+    for tag in String::from_utf8_lossy(&output.stdout).lines() {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            continue;
+        }
+        let output = Command::new("git")
+            .args([
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("refs/tags/{}^{{commit}}", tag),
+            ])
+            .current_dir(src_dir_path)
+            .output()
+            .with_context(|| "Failed to execute git rev-parse")?;
+        if !output.status.success() {
+            warn!("Skipping tag {}, it doesn't point at a commit", tag);
+            continue;
+        }
+        let commit_id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        tags.push((tag.to_string(), commit_id));
+    }
+    Ok(tags)
+}
+
+/// Number of first-parent commits from the root to `commit_id`, giving a sequence number which is
+/// stable across sweeps.
+pub fn get_commit_height(src_dir_path: &PathBuf, commit_id: &str) -> Result<i64> {
+    let output = Command::new("git")
+        .args(["rev-list", "--count", "--first-parent", commit_id])
+        .current_dir(src_dir_path)
+        .output()
+        .with_context(|| format!("Failed to execute git rev-list for {}", commit_id))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git rev-list failed for {}: {}", commit_id, stderr.trim());
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .with_context(|| format!("Failed to parse commit count of {}", commit_id))
+}
+
+/// Full hash of the commit `rev` resolves to.
+pub fn get_commit_hash(src_dir_path: &Path, rev: &str) -> Result<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--verify", &format!("{}^{{commit}}", rev)])
+        .current_dir(src_dir_path)
+        .output()
+        .with_context(|| format!("Failed to execute git rev-parse for {}", rev))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git rev-parse failed for {}: {}", rev, stderr.trim());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The `depth` most recent commits reachable from `rev` and their commit dates, newest first.
+pub fn get_recent_commits(
+    src_dir_path: &Path,
+    rev: &str,
+    depth: usize,
+) -> Result<Vec<(String, i64)>> {
+    let output = Command::new("git")
+        .args([
+            "log",
+            "--format=%H %ct",
+            &format!("--max-count={}", depth),
+            rev,
+        ])
+        .current_dir(src_dir_path)
+        .output()
+        .with_context(|| "Failed to execute git log")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git log failed: {}", stderr);
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| {
+            let (commit_id, date) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("Unexpected git log line '{}'", line))?;
+            Ok((commit_id.to_string(), date.parse()?))
+        })
+        .collect()
+}
+
+pub fn is_ancestor(src_dir_path: &Path, ancestor: &str, descendant: &str) -> Result<bool> {
+    let status = Command::new("git")
+        .args(["merge-base", "--is-ancestor", ancestor, descendant])
+        .current_dir(src_dir_path)
+        .status()
+        .with_context(|| "Failed to execute git merge-base")?;
+
+    // Exit code 1 means "not an ancestor", anything else non-zero is an error
+    match status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => bail!("git merge-base failed for {} and {}", ancestor, descendant),
+    }
+}
+
+/// One-line summaries of the commits reachable from `to` but not from `from`, newest first.
+pub fn get_git_log(src_dir_path: &Path, from: &str, to: &str) -> Result<Vec<String>> {
+    let output = Command::new("git")
+        .args(["log", "--oneline", &format!("{}..{}", from, to)])
+        .current_dir(src_dir_path)
+        .output()
+        .with_context(|| "Failed to execute git log")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git log failed: {}", stderr);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+/// The parts of a commit used while benchmarking it, read with a single `git show`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitMetadata {
+    pub hash: String,
+    pub tree_hash: String,
+    pub date: i64,
+    pub author: String,
+    pub subject: String,
+}
+
+pub fn get_commit_metadata(repo_path: &Path, commit_id: &str) -> Result<CommitMetadata> {
     let output = Command::new("git")
         .arg("-C")
         .arg(repo_path)
-        .arg("show")
-        .arg("-s")
-        .arg("--format=%ct")
-        .arg(commit_id)
+        .args(["show", "-s", "--format=%H%n%T%n%ct%n%an%n%s"])
+        .arg(format!("{}^{{commit}}", commit_id))
         .stdout(Stdio::piped())
         .output()
-        .with_context(|| format!("Failed to execute git command for commit ID: {}", commit_id))?;
+        .with_context(|| format!("Failed to execute git show for {}", commit_id))?;
 
     if !output.status.success() {
-        bail!(
-            "Git command failed with status: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git show failed for {}: {}", commit_id, stderr.trim());
     }
 
     let output_str = String::from_utf8(output.stdout)
         .context("Failed to convert git command output to string")?;
-    let commit_timestamp = output_str
-        .trim()
-        .parse::<i64>()
-        .context("Failed to parse commit date as i64")?;
-
-    Ok(commit_timestamp)
+    let mut lines = output_str.lines();
+    let mut next = |field: &str| {
+        lines
+            .next()
+            .map(String::from)
+            .ok_or_else(|| anyhow!("git show output for {} has no {}", commit_id, field))
+    };
+    Ok(CommitMetadata {
+        hash: next("hash")?,
+        tree_hash: next("tree hash")?,
+        date: next("date")?
+            .parse()
+            .with_context(|| format!("Failed to parse commit date of {}", commit_id))?,
+        author: next("author")?,
+        subject: next("subject").unwrap_or_default(),
+    })
 }
 
 pub fn unix_timestamp_to_hr(timestamp: i64) -> String {
@@ -115,9 +461,106 @@ pub fn unix_timestamp_to_hr(timestamp: i64) -> String {
     datetime.format("%Y-%m-%d %H:%M:%S").to_string()
 }
 
+/// Format a number of bytes in binary units, e.g. `16.0 GiB`.
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", value)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Format a time in seconds, using milliseconds below a second and minutes and hours above one.
+pub fn format_seconds(seconds: f64) -> String {
+    if seconds.abs() < 1.0 {
+        format!("{:.0} ms", seconds * 1000.0)
+    } else if seconds.abs() < 60.0 {
+        format!("{:.2} s", seconds)
+    } else {
+        format_duration(seconds)
+    }
+}
+
+/// Format a value of `metric` in human readable units. Stored values are unchanged, this is for
+/// display only.
+pub fn format_metric(metric: Metric, value: f64) -> String {
+    match metric {
+        Metric::UserTime | Metric::SystemTime | Metric::Elapsed => format_seconds(value),
+        Metric::MaxRss => format_bytes(value * 1024.0),
+        Metric::ReadBytes | Metric::WriteBytes => format_bytes(value),
+        _ => format!("{}", value),
+    }
+}
+
+/// Format a number of seconds as e.g. `2h 05m`, `3m 20s` or `42s`.
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, _) => format!("{}h {:02}m", h, m),
+    }
+}
+
+/// Parse a duration such as `90`, `45m`, `5h30m` or `1d 12h`. A bare number is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let invalid = || anyhow!("Invalid duration '{}', expected e.g. 90s, 45m or 5h30m", s);
+    let mut seconds: u64 = 0;
+    let mut number = String::new();
+    for c in s.chars().filter(|c| !c.is_whitespace()) {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(invalid()),
+        };
+        let value = number.parse::<u64>().map_err(|_| invalid())?;
+        seconds = value
+            .checked_mul(unit)
+            .and_then(|value| seconds.checked_add(value))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        let value = number.parse::<u64>().map_err(|_| invalid())?;
+        seconds = seconds.checked_add(value).ok_or_else(invalid)?;
+    }
+    if seconds == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// The unix timestamp `ago` before `now`, failing if it's further back than a timestamp reaches.
+pub fn timestamp_before(now: i64, ago: Duration) -> Result<i64> {
+    i64::try_from(ago.as_secs())
+        .ok()
+        .and_then(|ago| now.checked_sub(ago))
+        .ok_or_else(|| anyhow!("{}s ago is out of range", ago.as_secs()))
+}
+
+pub fn unix_timestamp_to_iso(timestamp: i64) -> String {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .map(|datetime| datetime.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
 pub fn checkout_commit(src_dir_path: &PathBuf, commit_id: &str) -> Result<()> {
     let checkout_output = Command::new("git")
-        .args(["checkout", &commit_id, "--detach"])
+        .args(["checkout", commit_id, "--detach"])
         .current_dir(src_dir_path)
         .output()
         .with_context(|| "Failed to execute git checkout")?;
@@ -131,7 +574,417 @@ pub fn checkout_commit(src_dir_path: &PathBuf, commit_id: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn fetch_repo(src_dir_path: &PathBuf) -> Result<()> {
+/// Check the GPG signature of a tag, or of a commit for any other ref. A commit which isn't signed
+/// itself passes if a signed annotated tag points at it, as releases are signed by their tag.
+pub fn verify_signature(src_dir_path: &PathBuf, reference: &str) -> Result<()> {
+    let output = Command::new("git")
+        .args(["cat-file", "-t", reference])
+        .current_dir(src_dir_path)
+        .output()
+        .with_context(|| format!("Failed to execute git cat-file for {}", reference))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git cat-file failed for {}: {}", reference, stderr.trim());
+    }
+    if String::from_utf8_lossy(&output.stdout).trim() == "tag" {
+        return git_verify(src_dir_path, "verify-tag", reference);
+    }
+
+    let Err(commit_error) = git_verify(src_dir_path, "verify-commit", reference) else {
+        return Ok(());
+    };
+    for tag in annotated_tags_pointing_at(src_dir_path, reference)? {
+        match git_verify(src_dir_path, "verify-tag", &tag) {
+            Ok(()) => {
+                info!("Commit {} is signed by tag {}", reference, tag);
+                return Ok(());
+            }
+            Err(e) => debug!("{}", e),
+        }
+    }
+    Err(commit_error)
+}
+
+/// Annotated tags pointing directly at the commit `reference` resolves to.
+fn annotated_tags_pointing_at(src_dir_path: &PathBuf, reference: &str) -> Result<Vec<String>> {
+    let output = Command::new("git")
+        .args([
+            "for-each-ref",
+            "--points-at",
+            reference,
+            "--format=%(objecttype) %(refname:strip=2)",
+            "refs/tags",
+        ])
+        .current_dir(src_dir_path)
+        .output()
+        .with_context(|| format!("Failed to execute git for-each-ref for {}", reference))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "git for-each-ref failed for {}: {}",
+            reference,
+            stderr.trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.strip_prefix("tag "))
+        .map(String::from)
+        .collect())
+}
+
+/// Run `git verify-tag` or `git verify-commit` on `reference`.
+fn git_verify(src_dir_path: &PathBuf, verify: &str, reference: &str) -> Result<()> {
+    let output = Command::new("git")
+        .args([verify, reference])
+        .current_dir(src_dir_path)
+        .output()
+        .with_context(|| format!("Failed to execute git {}", verify))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.trim().is_empty() {
+            bail!("{} is not signed", reference);
+        }
+        if stderr.contains("No public key") {
+            bail!(
+                "Cannot verify the signature of {}, the signer's key is not in your keyring: {}",
+                reference,
+                stderr.trim()
+            );
+        }
+        bail!(
+            "Signature verification failed for {}: {}",
+            reference,
+            stderr.trim()
+        );
+    }
+
+    info!("Verified signature of {}", reference);
+    Ok(())
+}
+
+/// Whether the source tree has no untracked or ignored files, i.e. nothing left over from a
+/// previous build.
+pub fn is_worktree_clean(src_dir_path: &PathBuf) -> Result<bool> {
+    let output = Command::new("git")
+        .args(["clean", "-ndx"])
+        .current_dir(src_dir_path)
+        .output()
+        .with_context(|| "Failed to execute git clean")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git clean failed: {}", stderr);
+    }
+
+    Ok(output.stdout.is_empty())
+}
+
+/// Paths whose changes may alter the chainstate, block or index formats on disk, so a data dir
+/// written by an earlier commit can't be reused. This is a heuristic: a format change made
+/// elsewhere goes unnoticed, and most changes to these paths don't change a format at all.
+const DATADIR_FORMAT_PATHS: [&str; 18] = [
+    "src/chain.h",
+    "src/coins.cpp",
+    "src/coins.h",
+    "src/compressor.h",
+    "src/dbwrapper.cpp",
+    "src/dbwrapper.h",
+    "src/flatfile.cpp",
+    "src/flatfile.h",
+    "src/index/",
+    "src/kernel/",
+    "src/leveldb/",
+    "src/node/blockstorage.cpp",
+    "src/node/blockstorage.h",
+    "src/primitives/",
+    "src/serialize.h",
+    "src/txdb.cpp",
+    "src/txdb.h",
+    "src/undo.h",
+];
+
+/// Why a data dir synced by commit `from` can't be carried on by commit `to`, or `None` if it
+/// can: `to` must descend from `from`, without touching the on-disk formats or mentioning a
+/// reindex in any commit message in between.
+pub fn datadir_incompatibility(
+    src_dir_path: &Path,
+    from: &str,
+    to: &str,
+) -> Result<Option<String>> {
+    if !is_ancestor(src_dir_path, from, to)? {
+        return Ok(Some(format!("{} doesn't descend from {}", to, from)));
+    }
+    let git = |args: &[&str]| -> Result<String> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(src_dir_path)
+            .output()
+            .with_context(|| format!("Failed to execute git {}", args[0]))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("git {} failed: {}", args[0], stderr);
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let range = format!("{}..{}", from, to);
+    let messages = git(&["log", "--format=%B", &range])?;
+    if messages.to_lowercase().contains("reindex") {
+        return Ok(Some(format!(
+            "a commit message in {} mentions reindex",
+            range
+        )));
+    }
+    let mut args = vec!["diff", "--name-only", from, to, "--"];
+    args.extend(DATADIR_FORMAT_PATHS);
+    let changed: Vec<String> = git(&args)?.lines().map(String::from).collect();
+    if !changed.is_empty() {
+        return Ok(Some(format!(
+            "{} changed between {} and {}",
+            changed.join(", "),
+            from,
+            to
+        )));
+    }
+    Ok(None)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CcacheStats {
+    pub hits: i64,
+    pub misses: i64,
+}
+
+impl CcacheStats {
+    /// Stats accumulated since `before`, clamped in case the cache was zeroed in between.
+    pub fn since(&self, before: &CcacheStats) -> CcacheStats {
+        CcacheStats {
+            hits: (self.hits - before.hits).max(0),
+            misses: (self.misses - before.misses).max(0),
+        }
+    }
+}
+
+/// Parse the machine readable form of `ccache -s`, as printed by `ccache --print-stats`.
+fn parse_ccache_stats(output: &str) -> CcacheStats {
+    let mut stats = CcacheStats::default();
+    for line in output.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(key), Some(value)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let Ok(value) = value.parse::<i64>() else {
+            continue;
+        };
+        match key {
+            "direct_cache_hit" | "preprocessed_cache_hit" => stats.hits += value,
+            "cache_miss" => stats.misses += value,
+            _ => {}
+        }
+    }
+    stats
+}
+
+pub fn get_ccache_stats() -> Result<CcacheStats> {
+    let output = Command::new("ccache")
+        .arg("--print-stats")
+        .output()
+        .with_context(|| "Failed to execute ccache --print-stats")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("ccache --print-stats failed: {}", stderr);
+    }
+
+    Ok(parse_ccache_stats(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The compiler, build flags and libc a commit was built with, each `None` if it couldn't be
+/// determined.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Toolchain {
+    /// First line of the compiler's `--version`
+    pub compiler: Option<String>,
+    pub build_flags: Option<String>,
+    pub libc: Option<String>,
+}
+
+/// Directories of `src_dir` checked for a CMake build's `CMakeCache.txt`, in order.
+const CMAKE_BUILD_DIRS: [&str; 2] = ["build", "."];
+
+/// Compiler and flags from the `KEY:TYPE=VALUE` entries of a `CMakeCache.txt`.
+fn cmake_build_info(cache: &str) -> (Option<String>, Option<String>) {
+    let entries: HashMap<&str, &str> = cache
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with("//"))
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            Some((key.split(':').next()?, value.trim()))
+        })
+        .collect();
+    let build_type = entries.get("CMAKE_BUILD_TYPE").copied().unwrap_or_default();
+    let type_flags = format!("CMAKE_CXX_FLAGS_{}", build_type.to_uppercase());
+    let flags: Vec<String> = ["CMAKE_BUILD_TYPE", "CMAKE_CXX_FLAGS", &type_flags]
+        .into_iter()
+        .filter_map(|key| {
+            let value = entries.get(key).filter(|value| !value.is_empty())?;
+            Some(format!("{}={}", key, value))
+        })
+        .collect();
+    let compiler = entries
+        .get("CMAKE_CXX_COMPILER")
+        .filter(|compiler| !compiler.is_empty())
+        .map(|compiler| compiler.to_string());
+    (compiler, (!flags.is_empty()).then(|| flags.join("; ")))
+}
+
+/// Compiler and flags from the `VAR = value` lines of an autotools `Makefile`, along with the
+/// `configure` invocation recorded at the top of `config.log`.
+fn make_build_info(makefile: &str, config_log: Option<&str>) -> (Option<String>, Option<String>) {
+    let var = |name: &str| {
+        makefile.lines().find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == name).then(|| value.trim())
+        })
+    };
+    let mut flags: Vec<String> = config_log
+        .and_then(|log| log.lines().find_map(|line| line.strip_prefix("  $ ")))
+        .map(|configure| vec![configure.trim().to_string()])
+        .unwrap_or_default();
+    for name in ["CXXFLAGS", "CPPFLAGS", "LDFLAGS"] {
+        if let Some(value) = var(name).filter(|value| !value.is_empty()) {
+            flags.push(format!("{}={}", name, value));
+        }
+    }
+    // CXX may be prefixed with a launcher and followed by flags, e.g. `ccache g++ -std=c++17`
+    let compiler = var("CXX").and_then(|cxx| {
+        cxx.split_whitespace()
+            .find(|word| *word != "ccache")
+            .map(String::from)
+    });
+    (compiler, (!flags.is_empty()).then(|| flags.join("; ")))
+}
+
+/// First line `program` prints for `args`, on stdout or stderr.
+fn first_output_line(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    let first_line = |out: &[u8]| {
+        let out = String::from_utf8_lossy(out);
+        let line = out.lines().next()?.trim();
+        (!line.is_empty()).then(|| line.to_string())
+    };
+    first_line(&output.stdout).or_else(|| first_line(&output.stderr))
+}
+
+/// The toolchain used to build `src_dir`, read from its CMake cache or autotools Makefile,
+/// falling back to `$CXX` or `c++` for the compiler. Anything which can't be found is left out.
+/// Only meaningful once a build job has built `src_dir`.
+pub fn detect_toolchain(src_dir: &Path) -> Toolchain {
+    let (compiler, build_flags) = CMAKE_BUILD_DIRS
+        .iter()
+        .find_map(|dir| fs::read_to_string(src_dir.join(dir).join("CMakeCache.txt")).ok())
+        .map(|cache| cmake_build_info(&cache))
+        .or_else(|| {
+            let makefile = fs::read_to_string(src_dir.join("Makefile")).ok()?;
+            let config_log = fs::read_to_string(src_dir.join("config.log")).ok();
+            Some(make_build_info(&makefile, config_log.as_deref()))
+        })
+        .unwrap_or_default();
+    let compiler = compiler
+        .or_else(|| std::env::var("CXX").ok())
+        .unwrap_or_else(|| "c++".to_string());
+    // glibc's ldd prints its version on stdout, musl's on stderr
+    let libc = match std::env::consts::OS {
+        "linux" => first_output_line("ldd", &["--version"]),
+        _ => None,
+    };
+    Toolchain {
+        compiler: first_output_line(&compiler, &["--version"]),
+        build_flags,
+        libc,
+    }
+}
+
+/// Fragments of git stderr which indicate a network blip worth retrying, as opposed to a fatal
+/// problem with the repository or credentials.
+const TRANSIENT_GIT_ERRORS: &[&str] = &[
+    "Could not resolve host",
+    "Connection timed out",
+    "Connection reset",
+    "Connection refused",
+    "Operation timed out",
+    "Temporary failure in name resolution",
+    "early EOF",
+    "the remote end hung up unexpectedly",
+    "RPC failed",
+    // Dropped TLS connections, but not certificate errors, which retrying won't fix
+    "TLS connection was non-properly terminated",
+    "SSL_ERROR_SYSCALL",
+];
+
+/// Longest wait between git fetch retries, however many are configured.
+const MAX_GIT_BACKOFF: Duration = Duration::from_secs(300);
+
+fn is_transient_git_error(stderr: &str) -> bool {
+    TRANSIENT_GIT_ERRORS
+        .iter()
+        .any(|fragment| stderr.contains(fragment))
+}
+
+/// Whether `path` is the top level of a git work tree.
+pub fn is_git_repo(path: &Path) -> bool {
+    Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .current_dir(path)
+        .output()
+        .is_ok_and(|output| {
+            output.status.success()
+                && fs::canonicalize(path).is_ok_and(|path| {
+                    Path::new(String::from_utf8_lossy(&output.stdout).trim()) == path
+                })
+        })
+}
+
+pub fn clone_repo(repo_url: &str, src_dir_path: &Path) -> Result<()> {
+    info!("Cloning {} into {}", repo_url, src_dir_path.display());
+    let output = Command::new("git")
+        .arg("clone")
+        .arg(repo_url)
+        .arg(src_dir_path)
+        .output()
+        .with_context(|| "Failed to execute git clone")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to clone {}: {}", repo_url, stderr);
+    }
+    Ok(())
+}
+
+pub fn fetch_repo(src_dir_path: &PathBuf, retries: u32) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        match try_fetch_repo(src_dir_path) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < retries && is_transient_git_error(&e.to_string()) => {
+                attempt += 1;
+                let backoff =
+                    Duration::from_secs(2u64.saturating_pow(attempt)).min(MAX_GIT_BACKOFF);
+                warn!(
+                    "git fetch failed (retry {}/{} in {}s): {}",
+                    attempt,
+                    retries,
+                    backoff.as_secs(),
+                    e
+                );
+                std::thread::sleep(backoff);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn try_fetch_repo(src_dir_path: &PathBuf) -> Result<()> {
     // Sync the repository by running git fetch --all --tags --prune
     let output = Command::new("git")
         .args(["fetch", "--all", "--tags", "--prune"])
@@ -154,6 +1007,50 @@ pub fn fetch_repo(src_dir_path: &PathBuf) -> Result<()> {
     Ok(())
 }
 
+/// The last `lines` lines of `text`, each cut to `max_line_len` characters, keeping at most
+/// `max_bytes` bytes from the end.
+pub fn tail_lines(text: &str, lines: usize, max_line_len: usize, max_bytes: usize) -> String {
+    let all: Vec<&str> = text.lines().collect();
+    let mut tail = Vec::new();
+    let mut bytes = 0;
+    for line in all.iter().rev().take(lines) {
+        let line = match line.char_indices().nth(max_line_len) {
+            Some((end, _)) => format!("{}...", &line[..end]),
+            None => line.to_string(),
+        };
+        bytes += line.len() + 1;
+        if bytes > max_bytes {
+            break;
+        }
+        tail.push(line);
+    }
+    tail.reverse();
+    tail.join("\n")
+}
+
+/// The 1 minute load average.
+pub fn get_load_average() -> Result<f64> {
+    let mut loads = [0f64; 3];
+    // SAFETY: loads has room for the three averages asked for
+    if unsafe { libc::getloadavg(loads.as_mut_ptr(), 3) } < 1 {
+        bail!("the load average isn't available");
+    }
+    Ok(loads[0])
+}
+
+/// Time since boot, which unlike `Instant` keeps counting while the machine is suspended.
+pub fn boot_time() -> Result<Duration> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: time is valid for writes for the duration of the call
+    if unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut time) } == -1 {
+        return Err(std::io::Error::last_os_error()).context("Failed to read CLOCK_BOOTTIME");
+    }
+    Ok(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
 pub fn get_nproc() -> Result<String> {
     let nproc_output = Command::new("nproc")
         .output()
@@ -168,15 +1065,914 @@ pub fn get_nproc() -> Result<String> {
         .to_string())
 }
 
-pub fn parse_date(date_str: &str) -> Result<i64> {
-    let date = NaiveDate::parse_from_str(date_str, "%Y-%m-%d")?;
-    Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp())
-}
+pub fn check_perf_usable() -> Result<()> {
+    if std::env::consts::OS != "linux" {
+        bail!("perf profiling is only supported on Linux");
+    }
+    which("perf").context("perf not found on PATH")?;
 
-pub fn erase_dir_and_contents(data_dir: &Option<PathBuf>) -> Result<()> {
-    for entry in fs::read_dir(data_dir.clone().unwrap())? {
+    // Unprivileged users can't record kernel call graphs above this level
+    let paranoid: i32 = fs::read_to_string("/proc/sys/kernel/perf_event_paranoid")
+        .context("Failed to read perf_event_paranoid")?
+        .trim()
+        .parse()
+        .context("Failed to parse perf_event_paranoid")?;
+    if paranoid > 2 {
+        bail!(
+            "kernel.perf_event_paranoid is {}, perf record will not be permitted",
+            paranoid
+        );
+    }
+    Ok(())
+}
+
+pub fn check_precondition(condition: &str) -> Result<bool> {
+    let status = Command::new("sh")
+        .args(["-c", condition])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .with_context(|| format!("Failed to execute precondition '{}'", condition))?;
+    debug!("Precondition '{}' exited with {}", condition, status);
+    Ok(status.success())
+}
+
+/// Run a shell command, killing it and everything it started if it runs longer than `timeout`.
+/// Output goes to `log_path`, the tail of which is included in the error if the command fails.
+pub fn run_with_timeout(command: &str, timeout: Duration, log_path: &str) -> Result<()> {
+    let log = fs::File::create(log_path)?;
+    // In its own process group, so processes a compound command backgrounds are killed with it
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("Failed to execute '{}'", command))?;
+
+    let deadline = std::time::Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if std::time::Instant::now() >= deadline {
+            // SAFETY: killpg has no memory safety requirements. The group is the child's pid, which
+            // stays valid until it is waited for below
+            if unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) } == -1 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("Failed to kill '{}'", command));
+            }
+            child.wait()?;
+            bail!("'{}' timed out after {}s", command, timeout.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    if !status.success() {
+        let output = fs::read_to_string(log_path).unwrap_or_default();
+        bail!(
+            "'{}' exited with {}:\n{}",
+            command,
+            status,
+            tail_lines(&output, 20, 200, 4096)
+        );
+    }
+    Ok(())
+}
+
+pub fn get_container_image_id(runtime: &str, image: &str) -> Result<String> {
+    let output = Command::new(runtime)
+        .args(["image", "inspect", "--format", "{{.Id}}", image])
+        .output()
+        .with_context(|| format!("Failed to execute {} image inspect", runtime))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{} image inspect failed: {}", runtime, stderr);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Make `fd` available to the command as fd 3, so e.g. GNU time can write to `/dev/fd/3`.
+pub fn pass_fd_as_3(command: &mut Command, fd: RawFd) {
+    // SAFETY: only async-signal-safe calls are made between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            // dup2 is a no-op when the fd is already 3, so clear close-on-exec explicitly
+            let ret = if fd == 3 {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, 3)
+            };
+            if ret == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Read what has been written to `reader` so far without waiting for EOF, which never comes
+/// while a process the writer was inherited by, e.g. one a job daemonized, keeps it open. Only
+/// call once the writer has finished, as GNU time has when it exits.
+pub fn read_written(mut reader: std::io::PipeReader) -> Result<String> {
+    let fd = reader.as_raw_fd();
+    // SAFETY: fd belongs to `reader`, which stays open for the duration of both calls
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } == -1 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to make the pipe non-blocking");
+    }
+    let mut bytes = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => bytes.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e).context("Failed to read from the pipe"),
+        }
+    }
+    String::from_utf8(bytes).context("Pipe output isn't UTF-8")
+}
+
+/// Parse a kernel CPU list such as `2-3,6` into CPU numbers.
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',').filter(|part| !part.is_empty()) {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let (first, last): (usize, usize) = (first.parse()?, last.parse()?);
+        cpus.extend(first..=last);
+    }
+    Ok(cpus)
+}
+
+/// CPUs isolated from the scheduler with the `isolcpus` kernel parameter, empty if there are none.
+pub fn get_isolated_cpus() -> Result<Vec<usize>> {
+    let isolated = fs::read_to_string("/sys/devices/system/cpu/isolated").unwrap_or_default();
+    if !isolated.trim().is_empty() {
+        return parse_cpu_list(&isolated);
+    }
+    // Older kernels lack the sysfs file, so fall back to the command line, skipping flags such as
+    // `isolcpus=domain,managed_irq,2-3`
+    let cmdline = fs::read_to_string("/proc/cmdline").context("Failed to read /proc/cmdline")?;
+    let Some(value) = cmdline
+        .split_whitespace()
+        .find_map(|arg| arg.strip_prefix("isolcpus="))
+    else {
+        return Ok(Vec::new());
+    };
+    Ok(value
+        .split(',')
+        .filter_map(|part| parse_cpu_list(part).ok())
+        .flatten()
+        .collect())
+}
+
+pub fn format_cpu_list(cpus: &[usize]) -> String {
+    cpus.iter()
+        .map(usize::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Restrict the command, and everything it runs, to `cpus`, which must be below `CPU_SETSIZE`
+/// as that's all a `cpu_set_t` holds.
+pub fn pin_to_cpus(command: &mut Command, cpus: &[usize]) -> Result<()> {
+    if let Some(cpu) = cpus.iter().find(|cpu| !fits_cpu_set(**cpu)) {
+        bail!(
+            "Can't pin to CPU {}, only CPUs below {} can be pinned to",
+            cpu,
+            libc::CPU_SETSIZE
+        );
+    }
+    // SAFETY: cpu_set_t is plain data, for which all zeroes is the empty set
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for cpu in cpus {
+        // SAFETY: CPU_SET writes the bit for `cpu` without bounds checks, and every cpu was checked
+        // to be below CPU_SETSIZE above
+        unsafe { libc::CPU_SET(*cpu, &mut set) };
+    }
+    // SAFETY: the closure runs in the forked child before exec, where only async-signal-safe
+    // functions may be called. It only calls sched_setaffinity, which is a plain syscall, with the
+    // set moved into the closure
+    unsafe {
+        command.pre_exec(move || {
+            if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Whether `cpu` can be held in a `cpu_set_t`, and so pinned to.
+pub fn fits_cpu_set(cpu: usize) -> bool {
+    cpu < libc::CPU_SETSIZE as usize
+}
+
+/// Reap `pid`, returning its exit status and the resource usage of it and its reaped children.
+pub fn wait_with_rusage(pid: u32) -> Result<(ExitStatus, libc::rusage)> {
+    loop {
+        let mut status = 0;
+        // SAFETY: rusage is plain data, for which all zeroes is a valid value
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        // SAFETY: status and usage are valid for writes for the duration of the call. nix has no
+        // wait4, and getrusage(RUSAGE_CHILDREN) would mix in every other child's usage.
+        let ret = unsafe { libc::wait4(pid as libc::pid_t, &mut status, 0, &mut usage) };
+        if ret != -1 {
+            return Ok((ExitStatus::from_raw(status), usage));
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err).with_context(|| format!("Failed to wait for process {}", pid));
+        }
+    }
+}
+
+/// Bytes a process and the children it has reaped caused to be read from or written to storage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IoStats {
+    pub read_bytes: i64,
+    pub write_bytes: i64,
+}
+
+/// Block until `pid`, an unreaped child of this process, exits, leaving it unreaped so its `/proc`
+/// entry can still be read.
+pub fn wait_for_exit(pid: u32) -> Result<()> {
+    loop {
+        // SAFETY: siginfo_t is plain data, for which all zeroes is a valid value
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        // SAFETY: info is valid for writes for the duration of the call. The caller spawned `pid`
+        // and hasn't reaped it, so it can't have been reused by another process, and WNOWAIT keeps
+        // it that way until the caller reaps it
+        let ret = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if ret == 0 {
+            return Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err).with_context(|| format!("Failed to wait for process {}", pid));
+        }
+    }
+}
+
+/// Whether the unified cgroup v2 hierarchy is mounted, which `MemoryHigh` needs.
+pub fn cgroup_v2_available() -> bool {
+    Path::new("/sys/fs/cgroup/cgroup.controllers").exists()
+}
+
+/// Arguments running a command in a transient systemd scope with `MemoryHigh` set to `limit_mb`.
+/// The command's arguments follow. Its cgroup's `memory.events` are copied to `events_path` after
+/// it exits, while the scope still exists.
+pub fn memory_high_args(limit_mb: u64, events_path: &str) -> Vec<String> {
+    let mut args = vec!["systemd-run".to_string()];
+    // SAFETY: geteuid has no preconditions
+    if unsafe { libc::geteuid() } != 0 {
+        args.push("--user".to_string());
+    }
+    args.extend(
+        [
+            "--scope",
+            "--quiet",
+            "-p",
+            &format!("MemoryHigh={}M", limit_mb),
+            "sh",
+            "-c",
+            r#"events=$1; shift; "$@"; status=$?
+cat "/sys/fs/cgroup$(sed -n 's/^0:://p' /proc/self/cgroup)/memory.events" > "$events"
+exit $status"#,
+            "sh",
+            events_path,
+        ]
+        .map(String::from),
+    );
+    args
+}
+
+/// Number of times a cgroup went over its `memory.high` limit, from its `memory.events`.
+pub fn parse_memory_high_events(events: &str) -> Result<u64> {
+    let count = events
+        .lines()
+        .find_map(|line| line.strip_prefix("high "))
+        .context("memory.events has no high entry")?;
+    count
+        .trim()
+        .parse()
+        .map_err(|e| anyhow!("Bad memory.events high count '{}': {}", count, e))
+}
+
+/// Read `/proc/<pid>/io`. Once children have been reaped their I/O is included in the parent's.
+pub fn get_process_io(pid: u32) -> Result<IoStats> {
+    let io = Process::new(pid as i32)
+        .and_then(|process| process.io())
+        .with_context(|| format!("Failed to read /proc/{}/io", pid))?;
+    Ok(IoStats {
+        read_bytes: io.read_bytes as i64,
+        write_bytes: io.write_bytes as i64,
+    })
+}
+
+/// Resident set size in KiB of `pid` and every process descended from it, from `/proc`.
+pub fn process_tree_rss_kb(pid: u32) -> Result<u64> {
+    let page_kb = procfs::page_size() / 1024;
+    let root = Process::new(pid as i32).with_context(|| format!("No process {}", pid))?;
+    let mut total = 0;
+    let mut pending = vec![root];
+    while let Some(process) = pending.pop() {
+        // Descendants may exit while the tree is being walked
+        let Ok(statm) = process.statm() else {
+            continue;
+        };
+        total += statm.resident * page_kb;
+        let Ok(tasks) = process.tasks() else {
+            continue;
+        };
+        for task in tasks.flatten() {
+            let children = task.children().unwrap_or_default();
+            pending.extend(
+                children
+                    .into_iter()
+                    .filter_map(|child| Process::new(child as i32).ok()),
+            );
+        }
+    }
+    Ok(total)
+}
+
+/// Samples the RSS of a process tree on a background thread until stopped or the tree exits.
+pub struct RssSampler {
+    stop: mpsc::Sender<()>,
+    handle: JoinHandle<Vec<(i64, u64)>>,
+}
+
+impl RssSampler {
+    pub fn start(pid: u32, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            let mut samples = Vec::new();
+            loop {
+                // An exited process has no resident memory, even before it is reaped
+                match process_tree_rss_kb(pid) {
+                    Ok(rss_kb) if rss_kb > 0 => {
+                        samples.push((Utc::now().timestamp_millis(), rss_kb))
+                    }
+                    _ => break,
+                }
+                if stopped.recv_timeout(interval) != Err(RecvTimeoutError::Timeout) {
+                    break;
+                }
+            }
+            samples
+        });
+        RssSampler { stop, handle }
+    }
+
+    /// Stop sampling, returning the samples taken as `(unix time in ms, RSS in KiB)`.
+    pub fn stop(self) -> Vec<(i64, u64)> {
+        drop(self.stop);
+        self.handle.join().unwrap_or_default()
+    }
+}
+
+/// Write RSS samples to `path` as CSV with a `timestamp_ms,rss_kb` header.
+pub fn write_memory_timeline(path: &str, samples: &[(i64, u64)]) -> Result<()> {
+    let mut csv = String::from("timestamp_ms,rss_kb\n");
+    for (timestamp, rss_kb) in samples {
+        csv.push_str(&format!("{},{}\n", timestamp, rss_kb));
+    }
+    fs::write(path, csv).with_context(|| format!("Failed to write {}", path))
+}
+
+/// Consecutive thermal samples showing throttling after which a run counts as throttled, so a
+/// momentary spike doesn't flag it.
+const THROTTLED_SAMPLES: usize = 3;
+
+/// One reading of the machine's thermal state from sysfs.
+#[derive(Debug, Default, PartialEq)]
+struct ThermalSample {
+    /// Highest temperature of any thermal zone in °C
+    temperature_c: Option<f64>,
+    /// Some zone is at or above its lowest passive trip point, where the kernel throttles the CPU
+    at_trip_point: bool,
+    /// Total of the CPUs' thermal throttle event counters, which only some CPUs expose
+    throttle_count: Option<u64>,
+}
+
+/// Read the thermal zones and CPU throttle counters of the sysfs mounted at `sys`.
+fn read_thermal(sys: &Path) -> ThermalSample {
+    let read = |path: PathBuf| {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|value| value.trim().parse::<i64>().ok())
+    };
+    let entries = |dir: PathBuf, prefix: &str| -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(prefix))
+            .map(|entry| entry.path())
+            .collect()
+    };
+
+    let mut sample = ThermalSample::default();
+    for zone in entries(sys.join("class/thermal"), "thermal_zone") {
+        let Some(millidegrees) = read(zone.join("temp")) else {
+            continue;
+        };
+        let temperature = millidegrees as f64 / 1000.0;
+        sample.temperature_c = Some(
+            sample
+                .temperature_c
+                .map_or(temperature, |t| t.max(temperature)),
+        );
+        let passive_trip = (0..)
+            .map_while(|n| {
+                fs::read_to_string(zone.join(format!("trip_point_{}_type", n)))
+                    .ok()
+                    .map(|kind| (n, kind))
+            })
+            .filter(|(_, kind)| kind.trim() == "passive")
+            .filter_map(|(n, _)| read(zone.join(format!("trip_point_{}_temp", n))))
+            .min();
+        if passive_trip.is_some_and(|trip| trip > 0 && millidegrees >= trip) {
+            sample.at_trip_point = true;
+        }
+    }
+    for cpu in entries(sys.join("devices/system/cpu"), "cpu") {
+        for counter in ["core_throttle_count", "package_throttle_count"] {
+            if let Some(count) = read(cpu.join("thermal_throttle").join(counter)) {
+                *sample.throttle_count.get_or_insert(0) += count.max(0) as u64;
+            }
+        }
+    }
+    sample
+}
+
+/// What a [`ThermalMonitor`] saw while it ran.
+#[derive(Debug, Default, PartialEq)]
+pub struct ThermalReport {
+    /// The CPU was throttled for `THROTTLED_SAMPLES` consecutive samples
+    pub throttled: bool,
+    pub max_temperature_c: Option<f64>,
+}
+
+/// Builds a [`ThermalReport`] from consecutive samples.
+#[derive(Default)]
+struct ThermalTracker {
+    report: ThermalReport,
+    /// Consecutive samples so far which showed throttling
+    streak: usize,
+    throttle_count: Option<u64>,
+}
+
+impl ThermalTracker {
+    fn add(&mut self, sample: ThermalSample) {
+        if let Some(temperature) = sample.temperature_c {
+            let max = self.report.max_temperature_c.get_or_insert(temperature);
+            *max = max.max(temperature);
+        }
+        let counted = sample
+            .throttle_count
+            .zip(self.throttle_count)
+            .is_some_and(|(count, previous)| count > previous);
+        self.throttle_count = sample.throttle_count;
+        self.streak = match sample.at_trip_point || counted {
+            true => self.streak + 1,
+            false => 0,
+        };
+        if self.streak >= THROTTLED_SAMPLES {
+            self.report.throttled = true;
+        }
+    }
+}
+
+/// Samples the CPU temperature and throttling on a background thread until stopped.
+pub struct ThermalMonitor {
+    stop: mpsc::Sender<()>,
+    handle: JoinHandle<ThermalReport>,
+}
+
+impl ThermalMonitor {
+    /// Start sampling the sysfs mounted at `sys` every `interval`, or return `None` if it exposes
+    /// neither temperatures nor throttle counters.
+    pub fn start(sys: &Path, interval: Duration) -> Option<Self> {
+        let first = read_thermal(sys);
+        if first.temperature_c.is_none() && first.throttle_count.is_none() {
+            return None;
+        }
+        let sys = sys.to_path_buf();
+        let (stop, stopped) = mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            let mut tracker = ThermalTracker::default();
+            tracker.add(first);
+            while stopped.recv_timeout(interval) == Err(RecvTimeoutError::Timeout) {
+                tracker.add(read_thermal(&sys));
+            }
+            tracker.report
+        });
+        Some(ThermalMonitor { stop, handle })
+    }
+
+    pub fn stop(self) -> ThermalReport {
+        drop(self.stop);
+        self.handle.join().unwrap_or_default()
+    }
+}
+
+pub fn get_cpu_model() -> Result<String> {
+    let cpu_info = CpuInfo::current().context("Failed to read /proc/cpuinfo")?;
+    cpu_info
+        .model_name(0)
+        .map(|model| model.trim().to_string())
+        .context("No model name found in /proc/cpuinfo")
+}
+
+pub fn get_cpu_governor() -> Result<String> {
+    let governor = fs::read_to_string("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+        .context("Failed to read CPU scaling governor")?;
+    Ok(governor.trim().to_string())
+}
+
+pub fn get_hostname() -> Result<String> {
+    let hostname =
+        fs::read_to_string("/proc/sys/kernel/hostname").context("Failed to read hostname")?;
+    Ok(hostname.trim().to_string())
+}
+
+pub fn get_kernel_release() -> Result<String> {
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease")
+        .context("Failed to read kernel release")?;
+    Ok(release.trim().to_string())
+}
+
+/// Date formats tried in order by [`parse_date`], none of which can be read two ways.
+const DATE_FORMATS: [&str; 7] = [
+    "%Y-%m-%d", "%Y/%m/%d", "%Y%m%d", "%d %b %Y", "%d %B %Y", "%b %d %Y", "%B %d %Y",
+];
+
+/// Day first and month first formats, accepted only when just one of them gives a valid date.
+const DAY_MONTH_FORMATS: [&str; 4] = ["%d-%m-%Y", "%m-%d-%Y", "%d/%m/%Y", "%m/%d/%Y"];
+
+/// Unix timestamp of midnight UTC on a date given as YYYY-MM-DD or another common format.
+pub fn parse_date(date_str: &str) -> Result<i64> {
+    parse_date_with_format(date_str, None)
+}
+
+/// Parse a date with a chrono `format` such as `%d.%m.%Y`, or any format [`parse_date`] accepts
+/// if none is given.
+pub fn parse_date_with_format(date_str: &str, format: Option<&str>) -> Result<i64> {
+    let date_str = date_str.trim();
+    let date = match format {
+        Some(format) => NaiveDate::parse_from_str(date_str, format)
+            .map_err(|e| anyhow!("Invalid date '{}' for format '{}': {}", date_str, format, e))?,
+        None => detect_date(date_str)?,
+    };
+    Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp())
+}
+
+fn detect_date(date_str: &str) -> Result<NaiveDate> {
+    if let Some(date) = DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(date_str, format).ok())
+    {
+        return Ok(date);
+    }
+    let mut dates: Vec<NaiveDate> = DAY_MONTH_FORMATS
+        .iter()
+        .filter_map(|format| NaiveDate::parse_from_str(date_str, format).ok())
+        .collect();
+    dates.dedup();
+    match dates.as_slice() {
+        [date] => Ok(*date),
+        [first, second] => bail!(
+            "Ambiguous date '{}', it could be {} or {}, write it as YYYY-MM-DD",
+            date_str,
+            first,
+            second
+        ),
+        _ => bail!(
+            "Invalid date '{}', expected YYYY-MM-DD, YYYY/MM/DD, YYYYMMDD, '1 Feb 2024', \
+            'Feb 1 2024', or DD-MM-YYYY, MM-DD-YYYY, DD/MM/YYYY or MM/DD/YYYY when the day is \
+            after the 12th",
+            date_str
+        ),
+    }
+}
+
+pub fn archive_dir(dir: &Path, archive_path: &Path, compress: bool) -> Result<()> {
+    if let Some(parent) = archive_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+
+    let mut command = Command::new("tar");
+    if compress {
+        command.arg("--zstd");
+    }
+    let output = command
+        .arg("--exclude")
+        .arg(LOCKFILE_NAME)
+        .arg("-cf")
+        .arg(archive_path)
+        .arg("-C")
+        .arg(dir)
+        .arg(".")
+        .output()
+        .context("Failed to execute tar")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to archive {}: {}", dir.display(), stderr);
+    }
+    Ok(())
+}
+
+/// Variables of this process recorded as part of a job's environment, alongside the job's own
+/// `env`. The rest of the environment is left out as it may hold secrets under any name.
+const RECORDED_PROCESS_ENV: [&str; 17] = [
+    "PATH",
+    "LD_LIBRARY_PATH",
+    "LD_PRELOAD",
+    "LANG",
+    "LC_ALL",
+    "TZ",
+    "CC",
+    "CXX",
+    "CFLAGS",
+    "CXXFLAGS",
+    "CPPFLAGS",
+    "LDFLAGS",
+    "MAKEFLAGS",
+    "MALLOC_ARENA_MAX",
+    "GLIBC_TUNABLES",
+    "OMP_NUM_THREADS",
+    "CCACHE_DISABLE",
+];
+
+/// Whether this process's environment variable `name` is recorded with each job.
+pub fn is_recorded_process_env(name: &str) -> bool {
+    RECORDED_PROCESS_ENV.contains(&name)
+}
+
+/// Parts of environment variable names whose values are redacted when recording a job's
+/// environment.
+const SENSITIVE_ENV_PATTERNS: [&str; 10] = [
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "CREDENTIAL",
+    "API_KEY",
+    "PRIVATE_KEY",
+    "AUTH",
+    "COOKIE",
+    "SESSION",
+];
+
+/// Whether the environment variable `name` looks like it holds a secret.
+pub fn is_sensitive_env_name(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    name == "KEY"
+        || name.ends_with("_KEY")
+        || SENSITIVE_ENV_PATTERNS
+            .iter()
+            .any(|pattern| name.contains(pattern))
+}
+
+/// `value` with the `user:password@` of any URLs in it redacted, e.g. of a `DATABASE_URL`.
+pub fn redact_url_credentials(value: &str) -> String {
+    let mut redacted = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("://") {
+        let (before, after) = rest.split_at(start + 3);
+        redacted.push_str(before);
+        let authority_end = after
+            .find(|c: char| matches!(c, '/' | '?' | '#') || c.is_whitespace())
+            .unwrap_or(after.len());
+        match after[..authority_end].rfind('@') {
+            Some(at) => {
+                redacted.push_str("<redacted>");
+                rest = &after[at..];
+            }
+            None => rest = after,
+        }
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Where a command writes its output: `path`, or stdout when there's no path or it is `-`. Logs
+/// go to stderr, so stdout stays clean for piping into other tools.
+pub fn open_output(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    match path {
+        None => Ok(Box::new(std::io::stdout().lock())),
+        Some(path) if path == Path::new("-") => Ok(Box::new(std::io::stdout().lock())),
+        Some(path) => {
+            let file = fs::File::create(path)
+                .map_err(|e| anyhow!("Failed to create {}: {}", path.display(), e))?;
+            Ok(Box::new(std::io::BufWriter::new(file)))
+        }
+    }
+}
+
+/// Download `url` to `path` with curl.
+pub fn download_file(url: &str, path: &Path) -> Result<()> {
+    info!("Downloading {} to {}", url, path.display());
+    let output = Command::new("curl")
+        .args([
+            "--fail",
+            "--location",
+            "--silent",
+            "--show-error",
+            "--output",
+        ])
+        .arg(path)
+        .arg(url)
+        .output()
+        .context("Failed to execute curl")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to download {}: {}", url, stderr.trim());
+    }
+    Ok(())
+}
+
+/// Lowercase hex SHA256 of the contents of `path`.
+pub fn file_sha256(path: &Path) -> Result<String> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Modification time of the newest file under `dir`, e.g. an extracted release whose files tar
+/// gave the dates recorded in the archive. Release builds give every file the date of the release
+/// commit.
+pub fn newest_file_date(dir: &Path) -> Result<i64> {
+    fn newest(path: &Path) -> Result<Option<SystemTime>> {
+        let metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to read metadata of {}", path.display()))?;
+        if metadata.is_file() {
+            return Ok(Some(metadata.modified()?));
+        }
+        if !metadata.is_dir() {
+            return Ok(None);
+        }
+        let mut newest_time = None;
+        for entry in fs::read_dir(path)? {
+            newest_time = newest_time.max(newest(&entry?.path())?);
+        }
+        Ok(newest_time)
+    }
+    let modified = newest(dir)?.with_context(|| format!("No files in {}", dir.display()))?;
+    let secs = modified
+        .duration_since(UNIX_EPOCH)
+        .with_context(|| format!("Files in {} are dated before 1970", dir.display()))?
+        .as_secs();
+    i64::try_from(secs).with_context(|| format!("Files in {} are dated too late", dir.display()))
+}
+
+/// Extract `tarball` into a fresh `dir`, returning the directory the release's files are in: the
+/// tarball's single top-level directory if it has one, otherwise `dir` itself.
+pub fn extract_tarball(tarball: &Path, dir: &Path) -> Result<PathBuf> {
+    if dir.exists() {
+        fs::remove_dir_all(dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+    }
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let output = Command::new("tar")
+        .arg("-xf")
+        .arg(tarball)
+        .arg("-C")
+        .arg(dir)
+        .output()
+        .context("Failed to execute tar")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Failed to extract {}: {}", tarball.display(), stderr);
+    }
+
+    let entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    match entries.as_slice() {
+        [root] if root.is_dir() => Ok(root.clone()),
+        _ => Ok(dir.to_path_buf()),
+    }
+}
+
+/// The pid of a process (normally bitcoind) holding the fcntl lock on `<data_dir>/.lock`, if any.
+pub fn get_datadir_lock_holder(data_dir: &Path) -> Result<Option<i32>> {
+    let lock_path = data_dir.join(".lock");
+    let file = match fs::File::open(&lock_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {}", lock_path.display())),
+    };
+
+    // bitcoind takes a write lock with fcntl, which is invisible to flock, so query it directly
+    // SAFETY: flock is plain data, for which all zeroes is a valid value (a zero length covers the
+    // whole file)
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as _;
+    lock.l_whence = libc::SEEK_SET as _;
+    // SAFETY: the fd belongs to `file`, which stays open for the duration of the call, and F_GETLK
+    // takes a pointer to a flock which is valid for reads and writes
+    let ret = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock) };
+    if ret == -1 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to query lock on {}", lock_path.display()));
+    }
+
+    if lock.l_type as libc::c_int == libc::F_UNLCK as libc::c_int {
+        Ok(None)
+    } else {
+        Ok(Some(lock.l_pid))
+    }
+}
+
+/// Wait up to `grace_period` for bitcoind to release the data dir lock, as reported by
+/// `lock_holder`.
+pub fn wait_for_datadir_release(
+    data_dir: &Path,
+    grace_period: Duration,
+    lock_holder: impl Fn(&Path) -> Result<Option<i32>>,
+) -> Result<()> {
+    let deadline = std::time::Instant::now() + grace_period;
+    while let Some(pid) = lock_holder(data_dir)? {
+        if std::time::Instant::now() >= deadline {
+            bail!(
+                "Process {} still holds the lock on {} after {}s, not cleaning up",
+                pid,
+                data_dir.display(),
+                grace_period.as_secs()
+            );
+        }
+        info!(
+            "Waiting for process {} to release the lock on {}",
+            pid,
+            data_dir.display()
+        );
+        std::thread::sleep(Duration::from_secs(1));
+    }
+    Ok(())
+}
+
+/// Total size in bytes of the files under `path`, without following symlinks.
+pub fn dir_size(path: &Path) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to read metadata of {}", path.display()))?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += dir_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+/// Sizes of the bitcoin data dir and the `chainstate`, `blocks` and `indexes` directories within
+/// it or a network's subdirectory, e.g. `signet/chainstate`, as named metrics.
+pub fn datadir_size_metrics(data_dir: &Path) -> Result<Vec<(String, f64)>> {
+    let mut metrics = vec![("datadir_bytes".to_string(), dir_size(data_dir)? as f64)];
+    let network_dirs = ["", "testnet3", "testnet4", "signet", "regtest"];
+    for subdir in ["chainstate", "blocks", "indexes"] {
+        let mut size = None;
+        for network_dir in network_dirs {
+            let path = data_dir.join(network_dir).join(subdir);
+            if path.is_dir() {
+                *size.get_or_insert(0) += dir_size(&path)?;
+            }
+        }
+        if let Some(size) = size {
+            metrics.push((format!("datadir_{}_bytes", subdir), size as f64));
+        }
+    }
+    Ok(metrics)
+}
+
+pub fn erase_dir_and_contents(data_dir: &Option<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(data_dir.clone().unwrap())? {
         let entry = entry?;
         let path = entry.path();
+        if entry.file_name() == LOCKFILE_NAME {
+            continue;
+        }
         if path.is_dir() {
             fs::remove_dir_all(&path)?;
         } else {
@@ -185,3 +1981,616 @@ pub fn erase_dir_and_contents(data_dir: &Option<PathBuf>) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{commit_at, fixture_repo, git, DAY, FIRST_COMMIT_DATE};
+    use std::os::unix::fs::PermissionsExt;
+    use tempdir::TempDir;
+
+    #[test]
+    fn verifies_signed_tag_of_unsigned_commit() {
+        let (repo, commits) = fixture_repo();
+        let gnupg_home = TempDir::new("gnupg").unwrap();
+        let gpg = |args: &[&str]| {
+            let output = Command::new("gpg")
+                .args(["--batch", "--homedir"])
+                .arg(gnupg_home.path())
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "gpg {:?} failed", args);
+        };
+        gpg(&[
+            "--passphrase",
+            "",
+            "--quick-gen-key",
+            "Bencher <bencher@example.com>",
+            "ed25519",
+            "sign",
+            "never",
+        ]);
+        // Point git at the test keyring rather than the user's
+        let wrapper = gnupg_home.path().join("gpg.sh");
+        fs::write(
+            &wrapper,
+            format!(
+                "#!/bin/sh\nexec gpg --homedir '{}' \"$@\"\n",
+                gnupg_home.path().display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+        git(
+            repo.path(),
+            &["config", "gpg.program", &wrapper.to_string_lossy()],
+        );
+        git(
+            repo.path(),
+            &[
+                "-c",
+                "user.signingkey=bencher@example.com",
+                "tag",
+                "-s",
+                "-m",
+                "Release",
+                "v1.0",
+                &commits[1],
+            ],
+        );
+        let src_dir = repo.path().to_path_buf();
+
+        verify_signature(&src_dir, "v1.0").unwrap();
+        // The unsigned commit passes by the signed tag pointing at it, other commits don't
+        verify_signature(&src_dir, &commits[1]).unwrap();
+        let err = verify_signature(&src_dir, &commits[2]).unwrap_err();
+        assert_eq!(err.to_string(), format!("{} is not signed", commits[2]));
+
+        git(
+            repo.path(),
+            &["tag", "-a", "-m", "Unsigned", "v2.0", &commits[2]],
+        );
+        assert!(verify_signature(&src_dir, "v2.0").is_err());
+        assert!(verify_signature(&src_dir, &commits[2]).is_err());
+    }
+
+    #[test]
+    fn measures_datadir_sizes() {
+        let dir = TempDir::new("datadir").unwrap();
+        let write = |path: &str, len: usize| {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, vec![0u8; len]).unwrap();
+        };
+        write("debug.log", 10);
+        write("blocks/blk00000.dat", 1000);
+        write("blocks/index/000001.ldb", 200);
+        write("signet/chainstate/000002.ldb", 30);
+        std::os::unix::fs::symlink("/usr", dir.path().join("linked")).unwrap();
+
+        let metrics = datadir_size_metrics(dir.path()).unwrap();
+        let size = |name: &str| metrics.iter().find(|(n, _)| n == name).map(|(_, v)| *v);
+        assert_eq!(size("datadir_bytes"), Some(1240.0 + 4.0));
+        assert_eq!(size("datadir_blocks_bytes"), Some(1200.0));
+        assert_eq!(size("datadir_chainstate_bytes"), Some(30.0));
+        assert_eq!(size("datadir_indexes_bytes"), None);
+    }
+
+    #[test]
+    fn detects_sustained_thermal_throttling() {
+        let sys = TempDir::new("sys").unwrap();
+        let write = |path: &str, value: &str| {
+            let path = sys.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, format!("{}\n", value)).unwrap();
+        };
+        assert!(ThermalMonitor::start(sys.path(), Duration::from_secs(1)).is_none());
+
+        write("class/thermal/thermal_zone0/temp", "45000");
+        write("class/thermal/thermal_zone0/trip_point_0_type", "critical");
+        write("class/thermal/thermal_zone0/trip_point_0_temp", "100000");
+        write("class/thermal/thermal_zone0/trip_point_1_type", "passive");
+        write("class/thermal/thermal_zone0/trip_point_1_temp", "85000");
+        write("class/thermal/thermal_zone1/temp", "51500");
+        write(
+            "devices/system/cpu/cpu0/thermal_throttle/core_throttle_count",
+            "2",
+        );
+        write(
+            "devices/system/cpu/cpu1/thermal_throttle/core_throttle_count",
+            "1",
+        );
+        assert_eq!(
+            read_thermal(sys.path()),
+            ThermalSample {
+                temperature_c: Some(51.5),
+                at_trip_point: false,
+                throttle_count: Some(3),
+            }
+        );
+        write("class/thermal/thermal_zone0/temp", "85000");
+        assert!(read_thermal(sys.path()).at_trip_point);
+
+        let sample =
+            |temperature_c: f64, at_trip_point: bool, throttle_count: Option<u64>| ThermalSample {
+                temperature_c: Some(temperature_c),
+                at_trip_point,
+                throttle_count,
+            };
+        // Brief spells of throttling are tolerated
+        let mut brief = ThermalTracker::default();
+        for (temperature, at_trip_point) in
+            [(70.0, false), (86.0, true), (88.0, true), (80.0, false)]
+        {
+            brief.add(sample(temperature, at_trip_point, None));
+        }
+        assert_eq!(
+            brief.report,
+            ThermalReport {
+                throttled: false,
+                max_temperature_c: Some(88.0),
+            }
+        );
+
+        // As are counters which stop rising, but not counters which keep rising
+        let mut counted = ThermalTracker::default();
+        for count in [5, 6, 7, 7, 8, 9] {
+            counted.add(sample(60.0, false, Some(count)));
+        }
+        assert!(!counted.report.throttled);
+        counted.add(sample(60.0, false, Some(10)));
+        assert!(counted.report.throttled);
+    }
+
+    #[test]
+    fn reads_build_toolchain() {
+        let cache = "# This is the CMakeCache file.\n\
+            //Choose the type of build\n\
+            CMAKE_BUILD_TYPE:STRING=RelWithDebInfo\n\
+            CMAKE_CXX_COMPILER:FILEPATH=/usr/bin/clang++\n\
+            CMAKE_CXX_FLAGS:STRING=\n\
+            CMAKE_CXX_FLAGS_RELWITHDEBINFO:STRING=-O2 -g\n";
+        assert_eq!(
+            cmake_build_info(cache),
+            (
+                Some("/usr/bin/clang++".to_string()),
+                Some(
+                    "CMAKE_BUILD_TYPE=RelWithDebInfo; CMAKE_CXX_FLAGS_RELWITHDEBINFO=-O2 -g".into()
+                )
+            )
+        );
+
+        let makefile = "CXX = ccache g++ -std=c++17\nCXXFLAGS = -O2 -g\nCPPFLAGS = \n";
+        let config_log = "This file contains...\n\n  $ ./configure --disable-wallet\n";
+        assert_eq!(
+            make_build_info(makefile, Some(config_log)),
+            (
+                Some("g++".to_string()),
+                Some("./configure --disable-wallet; CXXFLAGS=-O2 -g".to_string())
+            )
+        );
+        assert_eq!(make_build_info("all:\n", None), (None, None));
+    }
+
+    #[test]
+    fn parses_memory_high_events() {
+        let events = "low 0\nhigh 17\nmax 0\noom 0\noom_kill 0\n";
+        assert_eq!(parse_memory_high_events(events).unwrap(), 17);
+        assert!(parse_memory_high_events("low 0\n").is_err());
+    }
+
+    #[test]
+    fn samples_process_tree_rss() {
+        // A shell waiting on a child, so the tree has two processes
+        let mut child = Command::new("sh")
+            .args(["-c", "sleep 5; true"])
+            .spawn()
+            .unwrap();
+        let sampler = RssSampler::start(child.id(), Duration::from_millis(20));
+        std::thread::sleep(Duration::from_millis(200));
+        let own = process_tree_rss_kb(child.id()).unwrap();
+        let samples = sampler.stop();
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        assert!(samples.len() > 2);
+        assert!(samples.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert!(samples.iter().all(|(_, rss_kb)| *rss_kb > 0));
+        assert!(own > 0);
+        assert!(process_tree_rss_kb(child.id()).is_err());
+
+        let dir = TempDir::new("timeline").unwrap();
+        let path = dir.path().join("memory.csv").to_string_lossy().into_owned();
+        write_memory_timeline(&path, &[(1000, 2048), (2000, 4096)]).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "timestamp_ms,rss_kb\n1000,2048\n2000,4096\n"
+        );
+    }
+
+    #[test]
+    fn detects_sensitive_env_names() {
+        assert!(is_sensitive_env_name("GITHUB_TOKEN"));
+        assert!(is_sensitive_env_name("aws_secret_access_key"));
+        assert!(is_sensitive_env_name("RPC_PASSWORD"));
+        assert!(is_sensitive_env_name("GH_AUTH"));
+        assert!(is_sensitive_env_name("SSH_PRIVATE_KEY_FILE"));
+        assert!(is_sensitive_env_name("SIGNING_KEY"));
+        assert!(is_sensitive_env_name("RPC_COOKIE"));
+        assert!(is_sensitive_env_name("SESSION_ID"));
+        assert!(!is_sensitive_env_name("MALLOC_ARENA_MAX"));
+        assert!(!is_sensitive_env_name("PATH"));
+    }
+
+    #[test]
+    fn kills_backgrounded_processes_on_timeout() {
+        let dir = TempDir::new("timeout").unwrap();
+        let pid_path = dir.path().join("pid");
+        let log_path = dir.path().join("log");
+        let command = format!("sleep 30 & echo $! > {}; wait", pid_path.display());
+        let timeout = Duration::from_millis(500);
+        let err = run_with_timeout(&command, timeout, &log_path.to_string_lossy()).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+
+        // The backgrounded sleep is killed too, leaving at most a zombie
+        let stat_path = format!(
+            "/proc/{}/stat",
+            fs::read_to_string(&pid_path).unwrap().trim()
+        );
+        let dead = || fs::read_to_string(&stat_path).map_or(true, |stat| stat.contains(") Z "));
+        for _ in 0..50 {
+            if dead() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(dead());
+    }
+
+    #[test]
+    fn redacts_url_credentials() {
+        assert_eq!(
+            redact_url_credentials("postgres://user:pass@db:5432/bench"),
+            "postgres://<redacted>@db:5432/bench"
+        );
+        assert_eq!(
+            redact_url_credentials("https://a:b@x.org https://y.org/p@q"),
+            "https://<redacted>@x.org https://y.org/p@q"
+        );
+        assert_eq!(redact_url_credentials("-dbcache=4096"), "-dbcache=4096");
+    }
+
+    #[test]
+    fn extracts_release_tarball() {
+        let dir = TempDir::new("release").unwrap();
+        let release = dir.path().join("src/bitcoin-27.0");
+        fs::create_dir_all(release.join("bin")).unwrap();
+        fs::write(release.join("bin/bitcoind"), "abc").unwrap();
+        assert_eq!(
+            file_sha256(&release.join("bin/bitcoind")).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let tarball = dir.path().join("bitcoin-27.0.tar.gz");
+        // Dated like a reproducible release build, long before it is extracted
+        let status = Command::new("tar")
+            .arg(format!("--mtime=@{}", FIRST_COMMIT_DATE))
+            .arg("-czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(dir.path().join("src"))
+            .arg("bitcoin-27.0")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let root = extract_tarball(&tarball, &dir.path().join("27.0")).unwrap();
+        assert_eq!(root, dir.path().join("27.0/bitcoin-27.0"));
+        assert_eq!(
+            fs::read_to_string(root.join("bin/bitcoind")).unwrap(),
+            "abc"
+        );
+        assert_eq!(newest_file_date(&root).unwrap(), FIRST_COMMIT_DATE);
+    }
+
+    #[test]
+    fn resolves_commits_in_fixture_repo() {
+        let (dir, commits) = fixture_repo();
+        let repo = dir.path().to_path_buf();
+        assert!(check_source_file(&repo).is_ok());
+
+        let date = FIRST_COMMIT_DATE + 4 * DAY;
+        assert_eq!(get_commit_id_from_date(&repo, &date).unwrap(), commits[1]);
+        let metadata = get_commit_metadata(&repo, &commits[1]).unwrap();
+        assert_eq!(metadata.hash, commits[1]);
+        assert_eq!(metadata.date, FIRST_COMMIT_DATE + 3 * DAY);
+        assert_eq!(metadata.subject, (FIRST_COMMIT_DATE + 3 * DAY).to_string());
+        assert_eq!(
+            get_commits_in_range(&repo, &format!("{}..master", commits[0]), None).unwrap(),
+            commits[1..]
+        );
+        let err = get_commits_in_range(&repo, "master..no-such-ref", None);
+        assert!(err.unwrap_err().to_string().contains("'no-such-ref'"));
+        assert_eq!(get_commit_height(&repo, &commits[2]).unwrap(), 3);
+    }
+
+    #[test]
+    fn orders_merged_commits_in_range() {
+        let (dir, commits) = fixture_repo();
+        let repo = dir.path().to_path_buf();
+        // A branch from the first commit merged after the second
+        git(&repo, &["checkout", "-q", "-b", "feature", &commits[0]]);
+        fs::write(repo.join("src/feature.cpp"), "").unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "feature"]);
+        let side = git(&repo, &["rev-parse", "HEAD"]);
+        git(&repo, &["checkout", "-q", "master"]);
+        git(&repo, &["reset", "-q", "--hard", &commits[1]]);
+        git(&repo, &["merge", "-q", "--no-ff", "-m", "merge", "feature"]);
+        let merge = git(&repo, &["rev-parse", "HEAD"]);
+        let tip = commit_at(&repo, FIRST_COMMIT_DATE + 12 * DAY);
+        let range = format!("{}..master", commits[0]);
+
+        assert_eq!(
+            get_commits_in_range(&repo, &range, None).unwrap(),
+            [commits[1].clone(), merge.clone(), tip.clone()]
+        );
+        let topo = get_commits_in_range(&repo, &range, Some(CommitOrder::Topo)).unwrap();
+        assert_eq!(topo.len(), 4);
+        assert!(topo.contains(&side));
+        assert_eq!(topo[2..], [merge, tip]);
+    }
+
+    #[test]
+    fn resolves_tags_to_commits() {
+        let (dir, commits) = fixture_repo();
+        let repo = dir.path().to_path_buf();
+        git(&repo, &["tag", "v0.2", &commits[1]]);
+        git(&repo, &["tag", "-a", "-m", "release", "v0.1", &commits[0]]);
+        git(&repo, &["tag", "v0.3", "master^{tree}"]);
+        git(&repo, &["tag", "other", &commits[2]]);
+
+        assert_eq!(
+            get_tags(&repo, "v*").unwrap(),
+            [
+                ("v0.1".to_string(), commits[0].clone()),
+                ("v0.2".to_string(), commits[1].clone())
+            ]
+        );
+        assert!(get_tags(&repo, "nothing*").unwrap().is_empty());
+    }
+
+    #[test]
+    fn detects_datadir_incompatible_commits() {
+        let (dir, commits) = fixture_repo();
+        let repo = dir.path().to_path_buf();
+        assert_eq!(
+            datadir_incompatibility(&repo, &commits[0], &commits[2]).unwrap(),
+            None
+        );
+        assert!(datadir_incompatibility(&repo, &commits[2], &commits[0])
+            .unwrap()
+            .is_some());
+
+        fs::write(repo.join("src/txdb.cpp"), "format").unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "txdb: new format"]);
+        let txdb = git(&repo, &["rev-parse", "HEAD"]);
+        let reason = datadir_incompatibility(&repo, &commits[2], &txdb).unwrap();
+        assert!(reason.unwrap().starts_with("src/txdb.cpp changed"));
+
+        git(
+            &repo,
+            &["commit", "-q", "--allow-empty", "-m", "Requires -reindex"],
+        );
+        let reindex = git(&repo, &["rev-parse", "HEAD"]);
+        let reason = datadir_incompatibility(&repo, &txdb, &reindex).unwrap();
+        assert!(reason.unwrap().contains("mentions reindex"));
+    }
+
+    #[test]
+    fn checks_out_detached_commit() {
+        let (dir, commits) = fixture_repo();
+        let repo = dir.path().to_path_buf();
+
+        checkout_commit(&repo, &commits[0]).unwrap();
+        assert_eq!(git(&repo, &["rev-parse", "HEAD"]), commits[0]);
+        assert_eq!(git(&repo, &["rev-parse", "--abbrev-ref", "HEAD"]), "HEAD");
+        assert!(checkout_commit(&repo, "no-such-ref").is_err());
+    }
+
+    #[test]
+    fn clones_into_missing_src_dir() {
+        let (origin, commits) = fixture_repo();
+        let parent = TempDir::new("fixture-clone").unwrap();
+        let src_dir = parent.path().join("bitcoin");
+        assert!(!is_git_repo(parent.path()));
+
+        clone_repo(origin.path().to_str().unwrap(), &src_dir).unwrap();
+        assert!(is_git_repo(&src_dir));
+        assert_eq!(git(&src_dir, &["rev-parse", "HEAD"]), commits[2]);
+    }
+
+    #[test]
+    fn fetches_new_commits_from_origin() {
+        let (origin, _) = fixture_repo();
+        let clone = TempDir::new("fixture-clone").unwrap();
+        let origin_path = origin.path().to_str().unwrap();
+        git(clone.path(), &["clone", "-q", origin_path, "."]);
+
+        let new_commit = commit_at(origin.path(), FIRST_COMMIT_DATE + 9 * DAY);
+        fetch_repo(&clone.path().to_path_buf(), 0).unwrap();
+        assert_eq!(
+            git(clone.path(), &["rev-parse", "origin/master"]),
+            new_commit
+        );
+    }
+
+    #[test]
+    fn parses_cpu_lists() {
+        assert_eq!(parse_cpu_list("2-3,6\n").unwrap(), vec![2, 3, 6]);
+        assert_eq!(parse_cpu_list("").unwrap(), Vec::<usize>::new());
+        assert!(parse_cpu_list("domain").is_err());
+        assert_eq!(format_cpu_list(&[2, 3, 6]), "2,3,6");
+    }
+
+    #[test]
+    fn reads_pipe_held_open_by_another_writer() {
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        // As a daemonized descendant would, keep a copy of the write end open
+        let _daemon = writer.try_clone().unwrap();
+        writer
+            .write_all(b"Command exited with non-zero status 1\n")
+            .unwrap();
+        drop(writer);
+        assert_eq!(
+            read_written(reader).unwrap(),
+            "Command exited with non-zero status 1\n"
+        );
+    }
+
+    #[test]
+    fn rejects_cpus_beyond_cpu_set() {
+        let mut command = Command::new("true");
+        assert!(pin_to_cpus(&mut command, &[0, libc::CPU_SETSIZE as usize - 1]).is_ok());
+        assert!(pin_to_cpus(&mut command, &[0, libc::CPU_SETSIZE as usize]).is_err());
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(42.4), "42s");
+        assert_eq!(format_duration(200.0), "3m 20s");
+        assert_eq!(format_duration(7500.0), "2h 05m");
+    }
+
+    #[test]
+    fn parses_common_date_formats() {
+        let expected = parse_date("2024-02-13").unwrap();
+        assert_eq!(expected, 1707782400);
+        for date in [
+            "2024/02/13",
+            "20240213",
+            "13 Feb 2024",
+            "13 February 2024",
+            "Feb 13 2024",
+            "13-02-2024",
+            "02-13-2024",
+            "13/02/2024",
+            "02/13/2024",
+            " 2024-02-13 ",
+        ] {
+            assert_eq!(parse_date(date).unwrap(), expected, "{}", date);
+        }
+        assert_eq!(
+            parse_date_with_format("13.02.2024", Some("%d.%m.%Y")).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn rejects_ambiguous_dates() {
+        let err = parse_date("01-02-2024").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("could be 2024-02-01 or 2024-01-02"));
+        assert_eq!(
+            parse_date_with_format("01-02-2024", Some("%m-%d-%Y")).unwrap(),
+            parse_date("2024-01-02").unwrap()
+        );
+        // The same day and month can only be read one way
+        assert_eq!(
+            parse_date("05/05/2024").unwrap(),
+            parse_date("2024-05-05").unwrap()
+        );
+        assert!(parse_date("2024-13-01")
+            .unwrap_err()
+            .to_string()
+            .contains("expected"));
+        assert!(parse_date_with_format("2024-02-13", Some("%d/%m/%Y")).is_err());
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("45m").unwrap(), Duration::from_secs(2700));
+        assert_eq!(parse_duration("5h30m").unwrap(), Duration::from_secs(19800));
+        assert_eq!(parse_duration("1d 2h").unwrap(), Duration::from_secs(93600));
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("10 minutes").is_err());
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("999999999999999d").is_err());
+        assert!(parse_duration(&format!("{}s 1s", u64::MAX)).is_err());
+    }
+
+    #[test]
+    fn retries_only_transient_git_errors() {
+        assert!(is_transient_git_error(
+            "fatal: unable to access 'https://example.com/': gnutls_handshake() failed: The TLS connection was non-properly terminated."
+        ));
+        assert!(!is_transient_git_error(
+            "fatal: unable to access 'https://github.com/bitcoin/bitcoin.git/': server certificate verification failed. CAfile: none CRLfile: none"
+        ));
+        assert!(!is_transient_git_error(
+            "fatal: unable to access 'https://example.com/': SSL certificate problem: unable to get local issuer certificate"
+        ));
+    }
+
+    #[test]
+    fn rejects_timestamps_out_of_range() {
+        let now = 1707782400;
+        assert_eq!(
+            timestamp_before(now, Duration::from_secs(86400)).unwrap(),
+            now - 86400
+        );
+        assert!(timestamp_before(now, Duration::from_secs(u64::MAX)).is_err());
+        assert!(timestamp_before(i64::MIN + 1, Duration::from_secs(2)).is_err());
+    }
+
+    #[test]
+    fn formats_metrics_in_human_units() {
+        assert_eq!(format_metric(Metric::MaxRss, 16777216.0), "16.0 GiB");
+        assert_eq!(format_metric(Metric::MaxRss, 1536.0), "1.5 MiB");
+        assert_eq!(format_metric(Metric::WriteBytes, 512.0), "512 B");
+        assert_eq!(format_metric(Metric::UserTime, 0.25), "250 ms");
+        assert_eq!(format_metric(Metric::Elapsed, 12.5), "12.50 s");
+        assert_eq!(format_metric(Metric::Elapsed, 200.0), "3m 20s");
+        assert_eq!(format_metric(Metric::MajorPageFaults, 3.0), "3");
+    }
+
+    #[test]
+    fn tails_and_truncates_lines() {
+        let text = "one\ntwo\nthree\nfour is long\n";
+        assert_eq!(tail_lines(text, 2, 100, 1000), "three\nfour is long");
+        assert_eq!(tail_lines(text, 2, 5, 1000), "three\nfour ...");
+        assert_eq!(tail_lines(text, 10, 100, 20), "three\nfour is long");
+        assert_eq!(tail_lines("", 10, 100, 1000), "");
+    }
+
+    #[test]
+    fn parses_ccache_print_stats() {
+        let output = "stats_updated_timestamp\t1713456789\n\
+                      direct_cache_hit\t120\n\
+                      preprocessed_cache_hit\t30\n\
+                      cache_miss\t50\n\
+                      files_in_cache\t900\n";
+        let before = CcacheStats {
+            hits: 100,
+            misses: 10,
+        };
+        let stats = parse_ccache_stats(output);
+        assert_eq!(
+            stats,
+            CcacheStats {
+                hits: 150,
+                misses: 50
+            }
+        );
+        assert_eq!(
+            stats.since(&before),
+            CcacheStats {
+                hits: 50,
+                misses: 40
+            }
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::{bail, Result};
+
+use crate::database::Database;
+use crate::result::TimeResult;
+
+/// Parse the stored time report of every job again and compare it with the job's stored results,
+/// printing each field which differs. Fails if any job differs or no longer parses.
+pub fn verify_jobs(db: &Database) -> Result<()> {
+    let jobs = db.get_jobs_with_raw_time_output()?;
+    if jobs.is_empty() {
+        println!("no jobs have a stored time output to verify");
+        return Ok(());
+    }
+
+    let mut mismatched = 0;
+    for (job, run, raw) in &jobs {
+        let source = format!("the stored time output of job {}", job.job_id);
+        let differences = match TimeResult::parse(raw, &source) {
+            Ok(parsed) => compare_results(&job.result, &parsed),
+            Err(e) => vec![e.to_string()],
+        };
+        if differences.is_empty() {
+            continue;
+        }
+        mismatched += 1;
+        for difference in differences {
+            println!(
+                "job {} ({} of run {}, commit {}): {}",
+                job.job_id, job.result.command, job.run_id, run.commit_id, difference
+            );
+        }
+    }
+    if mismatched > 0 {
+        bail!(
+            "{} of {} jobs differ from their stored time output",
+            mismatched,
+            jobs.len()
+        );
+    }
+    println!(
+        "verified {} jobs against their stored time output",
+        jobs.len()
+    );
+    Ok(())
+}
+
+/// The fields GNU time reports which differ between the `stored` and `parsed` results. The
+/// command is stored as the job name and the I/O counters don't come from GNU time, so neither is
+/// compared.
+fn compare_results(stored: &TimeResult, parsed: &TimeResult) -> Vec<String> {
+    let int = |value: i64| Some(value as f64);
+    // GNU time reports exit status 0 for a job killed by a signal, which is recorded with the
+    // status the job failed with instead
+    let parsed_exit_status = match parsed.exit_status {
+        0 => stored.exit_status,
+        status => status,
+    };
+    let fields = [
+        ("user_time", Some(stored.user_time), Some(parsed.user_time)),
+        (
+            "system_time",
+            Some(stored.system_time),
+            Some(parsed.system_time),
+        ),
+        (
+            "percent_of_cpu",
+            int(stored.percent_of_cpu.into()),
+            int(parsed.percent_of_cpu.into()),
+        ),
+        (
+            "max_resident_set_size_kb",
+            int(stored.max_resident_set_size_kb),
+            int(parsed.max_resident_set_size_kb),
+        ),
+        (
+            "major_page_faults",
+            int(stored.major_page_faults),
+            int(parsed.major_page_faults),
+        ),
+        (
+            "minor_page_faults",
+            int(stored.minor_page_faults),
+            int(parsed.minor_page_faults),
+        ),
+        (
+            "voluntary_context_switches",
+            int(stored.voluntary_context_switches),
+            int(parsed.voluntary_context_switches),
+        ),
+        (
+            "involuntary_context_switches",
+            int(stored.involuntary_context_switches),
+            int(parsed.involuntary_context_switches),
+        ),
+        (
+            "file_system_outputs",
+            int(stored.file_system_outputs),
+            int(parsed.file_system_outputs),
+        ),
+        (
+            "exit_status",
+            int(stored.exit_status.into()),
+            int(parsed_exit_status.into()),
+        ),
+        (
+            "page_size_bytes",
+            stored.page_size_bytes.and_then(int),
+            parsed.page_size_bytes.and_then(int),
+        ),
+        (
+            "signals_delivered",
+            stored.signals_delivered.and_then(int),
+            parsed.signals_delivered.and_then(int),
+        ),
+        ("elapsed", stored.elapsed, parsed.elapsed),
+    ];
+    let show = |value: Option<f64>| value.map_or("nothing".to_string(), |v| v.to_string());
+    fields
+        .into_iter()
+        .filter(|(_, stored, parsed)| stored != parsed)
+        .map(|(field, stored, parsed)| {
+            format!(
+                "{} is {} but parses as {}",
+                field,
+                show(stored),
+                show(parsed)
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_fields_differing_from_raw_output() {
+        let path = format!("{}/tests/fixtures/gnu-time.txt", env!("CARGO_MANIFEST_DIR"));
+        let raw = std::fs::read_to_string(path).unwrap();
+        let parsed = TimeResult::parse(&raw, "fixture").unwrap();
+        let mut stored = TimeResult::parse(&raw, "fixture").unwrap();
+        stored.command = "make".to_string();
+        stored.read_bytes = Some(4096);
+        assert!(compare_results(&stored, &parsed).is_empty());
+
+        stored.max_resident_set_size_kb += 1;
+        stored.elapsed = None;
+        assert_eq!(
+            compare_results(&stored, &parsed),
+            [
+                format!(
+                    "max_resident_set_size_kb is {} but parses as {}",
+                    parsed.max_resident_set_size_kb + 1,
+                    parsed.max_resident_set_size_kb
+                ),
+                format!(
+                    "elapsed is nothing but parses as {}",
+                    parsed.elapsed.unwrap()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_exit_status_of_killed_job() {
+        let path = format!("{}/tests/fixtures/gnu-time.txt", env!("CARGO_MANIFEST_DIR"));
+        let raw = format!(
+            "Command terminated by signal 9\n{}",
+            std::fs::read_to_string(path).unwrap()
+        );
+        let parsed = TimeResult::parse(&raw, "fixture").unwrap();
+        assert_eq!(parsed.exit_status, 0);
+        let mut stored = TimeResult::parse(&raw, "fixture").unwrap();
+        stored.exit_status = 137;
+        assert!(compare_results(&stored, &parsed).is_empty());
+
+        stored.exit_status = 0;
+        let mut exited = TimeResult::parse(&raw, "fixture").unwrap();
+        exited.exit_status = 1;
+        assert_eq!(
+            compare_results(&stored, &exited),
+            ["exit_status is 0 but parses as 1"]
+        );
+    }
+}